@@ -1,9 +1,11 @@
 mod audio_driver;
 mod linear_resampler;
 mod null_audio_driver;
+mod output_device;
 mod sample_buffer;
 
 pub use audio_driver::AudioDriver;
 pub use linear_resampler::LinearResampler;
-pub use null_audio_driver::NullAudioDriver;
-pub use sample_buffer::SampleBuffer;
+pub use null_audio_driver::{NullAudioDriver, NullAudioSink};
+pub use output_device::{DeviceOutput, DeviceProvider, OutputDevice};
+pub use sample_buffer::{OverflowPolicy, SampleBuffer};
@@ -0,0 +1,107 @@
+use super::SampleBuffer;
+
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+
+// The state a playback device plays from, shared with the emulator side. It outlives any one
+// device, so buffered samples and the sample count the time source reads survive a device swap.
+#[derive(Clone)]
+pub struct DeviceOutput {
+    pub sample_buffer: Arc<Mutex<SampleBuffer>>,
+    pub samples_written: Arc<AtomicU64>,
+}
+
+// Lists and opens playback devices for an audio backend
+pub trait DeviceProvider {
+    type Device;
+
+    // Names of the available playback devices, for a frontend to offer
+    fn device_names(&self) -> Vec<String>;
+
+    // Opens the named device, or the default one, playing from `output`
+    fn open(&self, name: Option<&str>, output: DeviceOutput) -> Result<Self::Device, String>;
+}
+
+// A playback device that can be replaced by another while audio keeps flowing from the same
+// `DeviceOutput`, such as when headphones are plugged in
+pub struct OutputDevice<P: DeviceProvider> {
+    provider: P,
+    device: P::Device,
+    output: DeviceOutput,
+}
+
+impl<P: DeviceProvider> OutputDevice<P> {
+    pub fn open(provider: P, name: Option<&str>, output: DeviceOutput) -> Result<Self, String> {
+        let device = provider.open(name, output.clone())?;
+        Ok(OutputDevice {
+            provider,
+            device,
+            output,
+        })
+    }
+
+    pub fn device(&self) -> &P::Device {
+        &self.device
+    }
+
+    pub fn output(&self) -> &DeviceOutput {
+        &self.output
+    }
+
+    pub fn device_names(&self) -> Vec<String> {
+        self.provider.device_names()
+    }
+
+    // Switches to the named device, or the default one. The current device keeps playing if
+    // the new one can't be opened.
+    pub fn switch(&mut self, name: Option<&str>) -> Result<(), String> {
+        self.device = self.provider.open(name, self.output.clone())?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_switch_device() {
+    use std::sync::atomic::Ordering;
+
+    struct MockProvider;
+
+    impl DeviceProvider for MockProvider {
+        type Device = (String, DeviceOutput);
+
+        fn device_names(&self) -> Vec<String> {
+            vec!["speakers".into(), "headphones".into()]
+        }
+
+        fn open(&self, name: Option<&str>, output: DeviceOutput) -> Result<Self::Device, String> {
+            match name.unwrap_or("speakers") {
+                name @ ("speakers" | "headphones") => Ok((name.into(), output)),
+                name => Err(format!("no device named {}", name)),
+            }
+        }
+    }
+
+    let output = DeviceOutput {
+        sample_buffer: Arc::new(Mutex::new(SampleBuffer::with_max_length(16))),
+        samples_written: Arc::new(AtomicU64::new(0)),
+    };
+    let mut device = OutputDevice::open(MockProvider, None, output.clone()).unwrap();
+    assert_eq!(device.device().0, "speakers");
+    assert_eq!(device.device_names().len(), 2);
+
+    output.sample_buffer.lock().unwrap().push(0.5);
+    output.samples_written.store(1234, Ordering::Relaxed);
+
+    device.switch(Some("headphones")).unwrap();
+    assert_eq!(device.device().0, "headphones");
+    let (_, ref new_output) = *device.device();
+    assert!(Arc::ptr_eq(
+        &new_output.sample_buffer,
+        &output.sample_buffer
+    ));
+    assert_eq!(new_output.sample_buffer.lock().unwrap().len(), 1);
+    assert_eq!(new_output.samples_written.load(Ordering::Relaxed), 1234);
+
+    assert!(device.switch(Some("hdmi")).is_err());
+    assert_eq!(device.device().0, "headphones");
+}
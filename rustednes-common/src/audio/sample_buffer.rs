@@ -1,30 +1,77 @@
 use std::collections::VecDeque;
 
+// What happens to a sample pushed while the buffer is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // Discard the oldest buffered sample to make room, keeping latency bounded
+    #[default]
+    DropOldest,
+    // The producer waits until `is_full` is false before pushing. A sample pushed anyway is
+    // discarded.
+    Block,
+}
+
 pub struct SampleBuffer {
     samples: VecDeque<f32>,
     samples_written: usize,
     max_length: usize,
+    overflow_policy: OverflowPolicy,
 }
 
 impl SampleBuffer {
     pub fn with_max_length(max_length: usize) -> SampleBuffer {
         SampleBuffer {
-            samples: Default::default(),
+            samples: VecDeque::with_capacity(max_length),
             samples_written: 0,
             max_length,
+            overflow_policy: OverflowPolicy::default(),
         }
     }
 
+    pub fn set_overflow_policy(&mut self, overflow_policy: OverflowPolicy) {
+        self.overflow_policy = overflow_policy;
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
     pub fn push(&mut self, value: f32) {
-        if self.samples.len() < self.max_length {
-            self.samples.push_back(value);
+        if self.is_full() {
+            match self.overflow_policy {
+                OverflowPolicy::DropOldest => {
+                    self.samples.pop_front();
+                }
+                OverflowPolicy::Block => {
+                    self.samples_written += 1;
+                    return;
+                }
+            }
         }
+        self.samples.push_back(value);
         self.samples_written += 1;
     }
 
     pub fn samples_written(&self) -> usize {
         self.samples_written
     }
+
+    // The number of samples waiting to be consumed
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.samples.len() >= self.max_length
+    }
+
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
 }
 
 impl Iterator for SampleBuffer {
@@ -34,3 +81,16 @@ impl Iterator for SampleBuffer {
         self.samples.pop_front()
     }
 }
+
+#[test]
+fn test_drop_oldest() {
+    let mut buffer = SampleBuffer::with_max_length(4);
+    for i in 0..10 {
+        buffer.push(i as f32);
+        assert!(buffer.len() <= 4);
+    }
+
+    assert!(buffer.is_full());
+    assert_eq!(buffer.samples_written(), 10);
+    assert_eq!(buffer.collect::<Vec<_>>(), vec![6.0, 7.0, 8.0, 9.0]);
+}
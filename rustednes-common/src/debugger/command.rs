@@ -1,34 +1,158 @@
 use nom::branch::alt;
-use nom::bytes::complete::tag;
+use nom::bytes::complete::{tag, tag_no_case};
 use nom::character::complete::{alphanumeric1, digit1, space0, space1};
-use nom::combinator::{all_consuming, map, map_res, opt};
-use nom::sequence::{preceded, tuple};
+use nom::combinator::{all_consuming, eof, map, map_res, opt, rest};
+use nom::multi::many1;
+use nom::sequence::{preceded, terminated, tuple};
 use nom::IResult;
+use rustednes_core::cpu::{IllegalMode, Regs, IRQ_VECTOR, NMI_VECTOR, RESET_VECTOR};
+use std::fmt;
 use std::str::FromStr;
 
 #[derive(Debug, Clone)]
 pub enum Command {
     ShowRegs,
     Step(u16),
+    StepScanline(u16),
     Continue,
     Goto(u16),
+    GotoVector(Vector),
     ShowMem(Option<u16>),
+    Write(u16, Vec<u8>),
     ShowPpuMem(u16),
+    ShowPalette,
     ShowStack,
+    Compare(u16, u16, u16),
+    FindInstr(String),
     Disassemble(u16),
+    DisassembleBack(u16),
     Label,
     AddLabel(String, u16),
     RemoveLabel(String),
+    AddComment(u16, String),
+    ExportMarkdown(u16, u16, String),
     Breakpoint,
     AddBreakpoint(u16),
     RemoveBreakpoint(u16),
+    AddRegisterBreakpoint(RegisterBreakpoint),
+    RemoveRegisterBreakpoint(RegisterBreakpoint),
     Watchpoint,
     AddWatchpoint(u16),
     RemoveWatchpoint(u16),
+    DumpState(String),
+    DumpSprites(String),
+    LoadCdl(String),
+    ShowInterrupt,
+    ClearInterrupt,
+    ShowTrap,
+    Apu(u16, u8),
+    Tone(u16),
+    NextRom,
+    PrevRom,
+    Mappers,
+    WatchExpr(String, Expr),
+    SetIllegalMode(IllegalMode),
     Exit,
     Repeat,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Vector {
+    Nmi,
+    Irq,
+    Reset,
+}
+
+impl Vector {
+    pub fn address(self) -> u16 {
+        match self {
+            Vector::Nmi => NMI_VECTOR,
+            Vector::Irq => IRQ_VECTOR,
+            Vector::Reset => RESET_VECTOR,
+        }
+    }
+}
+
+// A value shown each time the debugger stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expr {
+    Register(Register),
+    Byte(u16),
+    Word(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    Sp,
+    Pc,
+    Status,
+}
+
+// Breaks after an instruction that changes a register in a particular way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterBreakpoint {
+    pub register: Register,
+    pub transition: Transition,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Changes,
+    // Goes from non-zero to zero
+    BecomesZero,
+    // Wraps around between $FF and $00, in either direction
+    Overflows,
+    // Bit 7 flips
+    ChangesSign,
+}
+
+impl RegisterBreakpoint {
+    // Whether the instruction that took the registers from `before` to `after` triggers this
+    pub fn triggered(&self, before: Regs, after: Regs) -> bool {
+        let value = |regs: Regs| match self.register {
+            Register::A => regs.a,
+            Register::X => regs.x,
+            Register::Y => regs.y,
+            Register::Sp => regs.sp,
+            // Only 8-bit general purpose registers are parsed
+            Register::Pc | Register::Status => 0,
+        };
+        let (before, after) = (value(before), value(after));
+
+        match self.transition {
+            Transition::Changes => before != after,
+            Transition::BecomesZero => before != 0 && after == 0,
+            Transition::Overflows => {
+                (before == 0xFF && after == 0x00) || (before == 0x00 && after == 0xFF)
+            }
+            Transition::ChangesSign => (before ^ after) & 0x80 != 0,
+        }
+    }
+}
+
+impl fmt::Display for RegisterBreakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let register = match self.register {
+            Register::A => "a",
+            Register::X => "x",
+            Register::Y => "y",
+            Register::Sp => "sp",
+            Register::Pc => "pc",
+            Register::Status => "p",
+        };
+        let transition = match self.transition {
+            Transition::Changes => "changes",
+            Transition::BecomesZero => "becomes zero",
+            Transition::Overflows => "overflows",
+            Transition::ChangesSign => "changes sign",
+        };
+        write!(f, "when {} {}", register, transition)
+    }
+}
+
 impl FromStr for Command {
     type Err = String;
 
@@ -49,6 +173,63 @@ fn u16_hex(input: &str) -> IResult<&str, u16> {
     preceded(opt(prefix), digits)(input)
 }
 
+fn u8_hex(input: &str) -> IResult<&str, u8> {
+    let prefix = alt((tag("0x"), tag("$")));
+    let digits = map_res(alphanumeric1, |s: &str| u8::from_str_radix(s, 16));
+    preceded(opt(prefix), digits)(input)
+}
+
+fn expr(input: &str) -> IResult<&str, Expr> {
+    let register = alt((
+        map(tag_no_case("pc"), |_| Register::Pc),
+        map(tag_no_case("sp"), |_| Register::Sp),
+        map(tag_no_case("a"), |_| Register::A),
+        map(tag_no_case("x"), |_| Register::X),
+        map(tag_no_case("y"), |_| Register::Y),
+        map(tag_no_case("p"), |_| Register::Status),
+    ));
+
+    alt((
+        map(preceded(tuple((tag("word"), space1)), u16_hex), Expr::Word),
+        // A register name must be the whole expression, since "a" is also a hex address
+        map(terminated(register, eof), Expr::Register),
+        map(u16_hex, Expr::Byte),
+    ))(input)
+}
+
+// "when <register> <transition>", e.g. "when a becomes zero"
+fn register_breakpoint(input: &str) -> IResult<&str, RegisterBreakpoint> {
+    let register = alt((
+        map(tag_no_case("sp"), |_| Register::Sp),
+        map(tag_no_case("a"), |_| Register::A),
+        map(tag_no_case("x"), |_| Register::X),
+        map(tag_no_case("y"), |_| Register::Y),
+    ));
+
+    // "changes sign" must come before its prefix "changes"
+    let transition = alt((
+        map(tuple((tag("changes"), space1, tag("sign"))), |_| {
+            Transition::ChangesSign
+        }),
+        map(tag("changes"), |_| Transition::Changes),
+        map(tuple((tag("becomes"), space1, tag("zero"))), |_| {
+            Transition::BecomesZero
+        }),
+        map(tag("overflows"), |_| Transition::Overflows),
+    ));
+
+    map(
+        preceded(
+            tuple((tag("when"), space1)),
+            tuple((terminated(register, space1), transition)),
+        ),
+        |(register, transition)| RegisterBreakpoint {
+            register,
+            transition,
+        },
+    )(input)
+}
+
 fn command(input: &str) -> IResult<&str, Command> {
     let show_regs = all_consuming(alt((tag("showregs"), tag("r"))));
 
@@ -57,6 +238,11 @@ fn command(input: &str) -> IResult<&str, Command> {
         opt(preceded(space1, u16_)),
     ));
 
+    let step_scanline = all_consuming(preceded(
+        alt((tag("stepscanline"), tag("sl"))),
+        opt(preceded(space1, u16_)),
+    ));
+
     let continue_ = all_consuming(alt((tag("continue"), tag("c"))));
 
     let goto = all_consuming(preceded(
@@ -64,23 +250,57 @@ fn command(input: &str) -> IResult<&str, Command> {
         preceded(space1, u16_hex),
     ));
 
+    let goto_vector = all_consuming(preceded(
+        alt((tag("goto"), tag("g"))),
+        preceded(
+            space1,
+            alt((
+                map(tag("nmi"), |_| Vector::Nmi),
+                map(tag("irq"), |_| Vector::Irq),
+                map(tag("reset"), |_| Vector::Reset),
+            )),
+        ),
+    ));
+
     let show_mem = all_consuming(preceded(
         alt((tag("showmem"), tag("m"))),
         opt(preceded(space1, u16_hex)),
     ));
 
+    // "write <address> <byte>...", poking consecutive bytes from the address
+    let write = all_consuming(preceded(
+        tag("write"),
+        tuple((preceded(space1, u16_hex), many1(preceded(space1, u8_hex)))),
+    ));
+
     let show_ppu_mem = all_consuming(preceded(
         alt((tag("showppumem"), tag("pm"))),
         preceded(space1, u16_hex),
     ));
 
+    let show_palette = all_consuming(alt((tag("showpalette"), tag("sp"))));
+
     let show_stack = all_consuming(alt((tag("showstack"), tag("ss"))));
 
+    let compare = all_consuming(preceded(
+        tag("cmp"),
+        tuple((
+            preceded(space1, u16_hex),
+            preceded(space1, u16_hex),
+            preceded(space1, u16_),
+        )),
+    ));
+
     let disassemble = all_consuming(preceded(
         alt((tag("disassemble"), tag("d"))),
         opt(preceded(space1, u16_)),
     ));
 
+    let disassemble_back = all_consuming(preceded(
+        alt((tag("disassembleback"), tag("db"))),
+        opt(preceded(space1, u16_)),
+    ));
+
     let label = all_consuming(alt((tag("label"), tag("l"))));
 
     let add_label = all_consuming(preceded(
@@ -88,6 +308,20 @@ fn command(input: &str) -> IResult<&str, Command> {
         tuple((preceded(space1, alphanumeric1), preceded(space1, u16_hex))),
     ));
 
+    let add_comment = all_consuming(preceded(
+        tag("comment"),
+        tuple((preceded(space1, u16_hex), preceded(space1, rest))),
+    ));
+
+    let export_markdown = all_consuming(preceded(
+        tag("exportmd"),
+        tuple((
+            preceded(space1, u16_hex),
+            preceded(space1, u16_hex),
+            preceded(space1, rest),
+        )),
+    ));
+
     let remove_label = all_consuming(preceded(
         alt((tag("removelabel"), tag("rl"))),
         preceded(space1, alphanumeric1),
@@ -105,6 +339,16 @@ fn command(input: &str) -> IResult<&str, Command> {
         preceded(space1, u16_hex),
     ));
 
+    let add_register_breakpoint = all_consuming(preceded(
+        alt((tag("break"), tag("addbreakpoint"), tag("ab"))),
+        preceded(space1, register_breakpoint),
+    ));
+
+    let remove_register_breakpoint = all_consuming(preceded(
+        alt((tag("removebreakpoint"), tag("rb"))),
+        preceded(space1, register_breakpoint),
+    ));
+
     let watchpoint = all_consuming(alt((tag("watchpoint"), tag("w"))));
 
     let add_watchpoint = all_consuming(preceded(
@@ -117,6 +361,55 @@ fn command(input: &str) -> IResult<&str, Command> {
         preceded(space1, u16_hex),
     ));
 
+    let dump_state = all_consuming(preceded(
+        alt((tag("dumpstate"), tag("ds"))),
+        preceded(space1, rest),
+    ));
+
+    let dump_sprites = all_consuming(preceded(
+        alt((tag("dumpsprites"), tag("dsp"))),
+        preceded(space1, rest),
+    ));
+
+    let load_cdl = all_consuming(preceded(tag("loadcdl"), preceded(space1, rest)));
+
+    let find_instr = all_consuming(preceded(tag("find"), preceded(space1, rest)));
+
+    let show_interrupt = all_consuming(alt((tag("showinterrupt"), tag("si"))));
+
+    let clear_interrupt = all_consuming(alt((tag("clearinterrupt"), tag("ci"))));
+
+    let show_trap = all_consuming(alt((tag("showtrap"), tag("st"))));
+
+    let apu = all_consuming(preceded(
+        tag("apu"),
+        tuple((preceded(space1, u16_hex), preceded(space1, u8_hex))),
+    ));
+
+    let tone = all_consuming(preceded(tag("tone"), preceded(space1, u16_)));
+
+    let next_rom = all_consuming(tag("nextrom"));
+
+    let prev_rom = all_consuming(tag("prevrom"));
+
+    let mappers = all_consuming(tag("mappers"));
+
+    let watch_expr = all_consuming(preceded(
+        alt((tag("watchexpr"), tag("we"))),
+        tuple((preceded(space1, alphanumeric1), preceded(space1, expr))),
+    ));
+
+    let set_illegal_mode = all_consuming(preceded(
+        tag("illegal"),
+        preceded(
+            space1,
+            alt((
+                map(tag("execute"), |_| IllegalMode::Execute),
+                map(tag("trap"), |_| IllegalMode::Trap),
+            )),
+        ),
+    ));
+
     let exit = all_consuming(alt((
         tag("exit"),
         tag("quit"),
@@ -129,29 +422,267 @@ fn command(input: &str) -> IResult<&str, Command> {
 
     let mut commands = alt((
         map(show_regs, |_| Command::ShowRegs),
-        map(step, |count| Command::Step(count.unwrap_or(1))),
+        alt((
+            map(step, |count| Command::Step(count.unwrap_or(1))),
+            map(step_scanline, |count| {
+                Command::StepScanline(count.unwrap_or(1))
+            }),
+        )),
         map(continue_, |_| Command::Continue),
-        map(goto, Command::Goto),
+        alt((
+            map(goto_vector, Command::GotoVector),
+            map(goto, Command::Goto),
+        )),
         map(show_mem, Command::ShowMem),
-        map(show_ppu_mem, Command::ShowPpuMem),
-        map(show_stack, |_| Command::ShowStack),
+        alt((
+            map(show_ppu_mem, Command::ShowPpuMem),
+            map(show_palette, |_| Command::ShowPalette),
+        )),
+        alt((
+            map(show_stack, |_| Command::ShowStack),
+            map(compare, |(a, b, len)| Command::Compare(a, b, len)),
+            map(find_instr, |pattern: &str| {
+                Command::FindInstr(pattern.into())
+            }),
+        )),
         map(disassemble, |count| {
             Command::Disassemble(count.unwrap_or(4))
         }),
+        map(disassemble_back, |count| {
+            Command::DisassembleBack(count.unwrap_or(4))
+        }),
         map(label, |_| Command::Label),
         map(add_label, |(name, addr)| {
             Command::AddLabel(name.into(), addr)
         }),
-        map(remove_label, |name: &str| Command::RemoveLabel(name.into())),
+        alt((
+            map(remove_label, |name: &str| Command::RemoveLabel(name.into())),
+            map(add_comment, |(address, text)| {
+                Command::AddComment(address, text.into())
+            }),
+            map(export_markdown, |(start, end, path)| {
+                Command::ExportMarkdown(start, end, path.into())
+            }),
+        )),
         map(breakpoint, |_| Command::Breakpoint),
-        map(add_breakpoint, Command::AddBreakpoint),
-        map(remove_breakpoint, Command::RemoveBreakpoint),
+        alt((
+            map(add_breakpoint, Command::AddBreakpoint),
+            map(add_register_breakpoint, Command::AddRegisterBreakpoint),
+        )),
+        alt((
+            map(remove_breakpoint, Command::RemoveBreakpoint),
+            map(
+                remove_register_breakpoint,
+                Command::RemoveRegisterBreakpoint,
+            ),
+        )),
         map(watchpoint, |_| Command::Watchpoint),
         map(add_watchpoint, Command::AddWatchpoint),
         map(remove_watchpoint, Command::RemoveWatchpoint),
-        map(exit, |_| Command::Exit),
-        map(repeat, |_| Command::Repeat),
+        alt((
+            map(dump_state, |path: &str| Command::DumpState(path.into())),
+            map(dump_sprites, |path: &str| Command::DumpSprites(path.into())),
+            map(load_cdl, |path: &str| Command::LoadCdl(path.into())),
+            map(show_interrupt, |_| Command::ShowInterrupt),
+            map(clear_interrupt, |_| Command::ClearInterrupt),
+            map(show_trap, |_| Command::ShowTrap),
+            map(apu, |(register, value)| Command::Apu(register, value)),
+            map(tone, Command::Tone),
+            map(next_rom, |_| Command::NextRom),
+            map(prev_rom, |_| Command::PrevRom),
+            map(mappers, |_| Command::Mappers),
+            map(watch_expr, |(name, expr)| {
+                Command::WatchExpr(name.into(), expr)
+            }),
+            map(set_illegal_mode, Command::SetIllegalMode),
+            map(write, |(address, bytes)| Command::Write(address, bytes)),
+            map(exit, |_| Command::Exit),
+            map(repeat, |_| Command::Repeat),
+        )),
     ));
 
     commands(input)
 }
+
+#[test]
+fn test_goto_vector() {
+    assert!(matches!(
+        "goto nmi".parse(),
+        Ok(Command::GotoVector(Vector::Nmi))
+    ));
+    assert!(matches!(
+        "g irq".parse(),
+        Ok(Command::GotoVector(Vector::Irq))
+    ));
+    assert!(matches!(
+        "goto reset".parse(),
+        Ok(Command::GotoVector(Vector::Reset))
+    ));
+    assert!(matches!("goto c000".parse(), Ok(Command::Goto(0xC000))));
+}
+
+#[test]
+fn test_compare() {
+    assert!(matches!(
+        "cmp 0 $0300 16".parse(),
+        Ok(Command::Compare(0x0000, 0x0300, 16))
+    ));
+    assert!("cmp 0 0300".parse::<Command>().is_err());
+}
+
+#[test]
+fn test_show_palette() {
+    assert!(matches!("showpalette".parse(), Ok(Command::ShowPalette)));
+    assert!(matches!("sp".parse(), Ok(Command::ShowPalette)));
+}
+
+#[test]
+fn test_find_instr() {
+    assert!(matches!(
+        "find sta $2007".parse(),
+        Ok(Command::FindInstr(pattern)) if pattern == "sta $2007"
+    ));
+    assert!("find".parse::<Command>().is_err());
+}
+
+#[test]
+fn test_apu() {
+    assert!(matches!(
+        "apu 4000 bf".parse(),
+        Ok(Command::Apu(0x4000, 0xBF))
+    ));
+    assert!(matches!(
+        "apu $4015 0x01".parse(),
+        Ok(Command::Apu(0x4015, 0x01))
+    ));
+    assert!("apu 4000 100".parse::<Command>().is_err());
+    assert!(matches!("tone 440".parse(), Ok(Command::Tone(440))));
+}
+
+#[test]
+fn test_watch_expr() {
+    let parse = |s: &str| match s.parse() {
+        Ok(Command::WatchExpr(name, expr)) => Some((name, expr)),
+        _ => None,
+    };
+    assert_eq!(
+        parse("watchexpr lives $0300"),
+        Some(("lives".into(), Expr::Byte(0x0300)))
+    );
+    assert_eq!(
+        parse("we ptr word $0302"),
+        Some(("ptr".into(), Expr::Word(0x0302)))
+    );
+    assert_eq!(
+        parse("we acc A"),
+        Some(("acc".into(), Expr::Register(Register::A)))
+    );
+    assert_eq!(
+        parse("we sp sp"),
+        Some(("sp".into(), Expr::Register(Register::Sp)))
+    );
+    assert_eq!(parse("we ab ab"), Some(("ab".into(), Expr::Byte(0x00AB))));
+    assert_eq!(parse("we x"), None);
+}
+
+#[test]
+fn test_set_illegal_mode() {
+    assert!(matches!(
+        "illegal trap".parse(),
+        Ok(Command::SetIllegalMode(IllegalMode::Trap))
+    ));
+    assert!(matches!(
+        "illegal execute".parse(),
+        Ok(Command::SetIllegalMode(IllegalMode::Execute))
+    ));
+    assert!("illegal".parse::<Command>().is_err());
+}
+
+#[test]
+fn test_write() {
+    assert!(matches!(
+        "write $0400 a9 05 0x8d".parse(),
+        Ok(Command::Write(0x0400, bytes)) if bytes == [0xA9, 0x05, 0x8D]
+    ));
+    assert!("write 0400".parse::<Command>().is_err());
+    assert!("write 0400 100".parse::<Command>().is_err());
+}
+
+#[test]
+fn test_show_trap() {
+    assert!(matches!("showtrap".parse(), Ok(Command::ShowTrap)));
+    assert!(matches!("st".parse(), Ok(Command::ShowTrap)));
+}
+
+#[test]
+fn test_dump_sprites() {
+    assert!(matches!(
+        "dumpsprites sprites.png".parse(),
+        Ok(Command::DumpSprites(path)) if path == "sprites.png"
+    ));
+    assert!(matches!(
+        "ds state.txt".parse(),
+        Ok(Command::DumpState(path)) if path == "state.txt"
+    ));
+}
+
+#[test]
+fn test_load_cdl() {
+    assert!(matches!(
+        "loadcdl game.cdl".parse(),
+        Ok(Command::LoadCdl(path)) if path == "game.cdl"
+    ));
+}
+
+#[test]
+fn test_mappers() {
+    assert!(matches!("mappers".parse(), Ok(Command::Mappers)));
+}
+
+#[test]
+fn test_export_markdown() {
+    assert!(matches!(
+        "comment $8000 waits for vblank".parse(),
+        Ok(Command::AddComment(0x8000, text)) if text == "waits for vblank"
+    ));
+    assert!(matches!(
+        "exportmd 8000 $80ff listing.md".parse(),
+        Ok(Command::ExportMarkdown(0x8000, 0x80FF, path)) if path == "listing.md"
+    ));
+}
+
+#[test]
+fn test_register_breakpoint() {
+    let breakpoint = RegisterBreakpoint {
+        register: Register::A,
+        transition: Transition::BecomesZero,
+    };
+    assert!(matches!(
+        "break when a becomes zero".parse(),
+        Ok(Command::AddRegisterBreakpoint(b)) if b == breakpoint
+    ));
+    assert!(matches!(
+        "rb when A becomes zero".parse(),
+        Ok(Command::RemoveRegisterBreakpoint(b)) if b == breakpoint
+    ));
+    assert!(matches!(
+        "break when x changes sign".parse(),
+        Ok(Command::AddRegisterBreakpoint(RegisterBreakpoint {
+            register: Register::X,
+            transition: Transition::ChangesSign,
+        }))
+    ));
+    assert!("break when pc changes".parse::<Command>().is_err());
+
+    let regs = |x| Regs {
+        x,
+        ..Default::default()
+    };
+    let overflows = RegisterBreakpoint {
+        register: Register::X,
+        transition: Transition::Overflows,
+    };
+    assert!(overflows.triggered(regs(0xFF), regs(0x00)));
+    assert!(overflows.triggered(regs(0x00), regs(0xFF)));
+    assert!(!overflows.triggered(regs(0x7F), regs(0x80)));
+}
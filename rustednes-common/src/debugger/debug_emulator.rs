@@ -17,4 +17,6 @@ where
     fn set_mode(&mut self, mode: EmulationMode);
     fn reset_start_time(&mut self);
     fn step(&mut self, video_frame_sink: &mut V) -> (u32, bool);
+    fn next_rom(&mut self);
+    fn prev_rom(&mut self);
 }
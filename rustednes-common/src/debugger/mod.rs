@@ -3,19 +3,26 @@ mod debug_emulator;
 
 pub use debug_emulator::DebugEmulator;
 
-use std::cmp::min;
+use std::cmp::{max, min};
 use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::thread;
 
+use rustednes_core::cpu::{Cpu, Regs, CPU_FREQUENCY};
 use rustednes_core::disassembler::Disassembler;
+use rustednes_core::mapper::{Mapper, MAPPERS};
 use rustednes_core::memory::Memory;
 use rustednes_core::nes::Nes;
-use rustednes_core::sink::{AudioSink, VideoSink};
+use rustednes_core::ram_system::{RamSystem, INSTRUCTION_LIMIT};
+use rustednes_core::sink::{AudioSink, VideoSink, XRGB8888_PALETTE};
 
 use crate::emulation_mode::EmulationMode;
+use crate::png;
 
-use command::Command;
+use command::{Command, Expr, Register, RegisterBreakpoint, Vector};
 
 use rustyline::error::ReadlineError;
 use rustyline::DefaultEditor;
@@ -23,7 +30,10 @@ use tracing::{debug, error};
 
 pub struct Debugger {
     pub breakpoints: HashSet<u16>,
+    register_breakpoints: Vec<RegisterBreakpoint>,
     labels: HashMap<String, u16>,
+    comments: HashMap<u16, String>,
+    watch_exprs: Vec<(String, Expr)>,
 
     cursor: u16,
     last_command: Option<Command>,
@@ -48,7 +58,10 @@ impl Debugger {
 
         Self {
             breakpoints: Default::default(),
+            register_breakpoints: Default::default(),
             labels: Default::default(),
+            comments: Default::default(),
+            watch_exprs: Default::default(),
             cursor: 0,
             last_command: None,
             prompt_sender,
@@ -89,20 +102,55 @@ impl Debugger {
     }
 
     pub fn start(&mut self, nes: &mut Nes) {
-        self.cursor = nes.cpu.regs().pc;
+        self.show_position(&nes.cpu, &mut nes.interconnect);
+        self.print_cursor();
+    }
+
+    // Debugs a RamSystem until the exit command. There's no frontend polling for commands between
+    // frames, so this blocks on input, and continuing runs the CPU until it stops here again.
+    pub fn run_ram_system(&mut self, system: &mut RamSystem) {
+        self.show_position(&system.cpu, &mut system.ram);
+        self.print_cursor();
+
+        while let Ok(command_string) = self.stdin_receiver.recv() {
+            match self.parse_command(&command_string) {
+                Ok(command) => {
+                    if self.run_ram_command(system, command) {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    println!("{}", e);
+                }
+            }
+
+            self.print_cursor();
+        }
+    }
+
+    fn show_position(&mut self, cpu: &Cpu, mem: &mut impl Memory) {
+        self.cursor = cpu.regs().pc;
 
         for _ in 1..3 {
             print!("0x{:04x}  ", self.cursor);
-            self.disassemble_instruction(nes);
+            self.disassemble_instruction(mem);
         }
 
-        self.print_cursor();
+        self.print_watch_exprs(cpu, mem);
     }
 
     pub fn at_breakpoint(&self, nes: &Nes) -> bool {
         self.breakpoints.contains(&nes.cpu.regs().pc)
     }
 
+    // Whether the instruction that took the CPU from the `before` registers to the `after` ones
+    // triggers a register breakpoint
+    pub fn at_register_breakpoint(&self, before: Regs, after: Regs) -> bool {
+        self.register_breakpoints
+            .iter()
+            .any(|breakpoint| breakpoint.triggered(before, after))
+    }
+
     pub fn run_commands<A, V>(
         &mut self,
         emulator: &mut dyn DebugEmulator<A, V>,
@@ -113,14 +161,7 @@ impl Debugger {
         A: AudioSink,
     {
         while let Ok(command_string) = self.stdin_receiver.try_recv() {
-            let command = match (command_string.parse(), self.last_command.clone()) {
-                (Ok(Command::Repeat), Some(c)) => Ok(c),
-                (Ok(Command::Repeat), None) => Err("No last command".into()),
-                (Ok(c), _) => Ok(c),
-                (Err(e), _) => Err(e),
-            };
-
-            match command {
+            match self.parse_command(&command_string) {
                 Ok(command) => {
                     if self.run_command(emulator, command, video_frame_sink) {
                         return true;
@@ -139,6 +180,16 @@ impl Debugger {
         false
     }
 
+    // Parses a command, with an empty line repeating the last one
+    fn parse_command(&self, command_string: &str) -> Result<Command, String> {
+        match (command_string.parse(), self.last_command.clone()) {
+            (Ok(Command::Repeat), Some(c)) => Ok(c),
+            (Ok(Command::Repeat), None) => Err("No last command".into()),
+            (Ok(c), _) => Ok(c),
+            (Err(e), _) => Err(e),
+        }
+    }
+
     fn run_command<A, V>(
         &mut self,
         emulator: &mut dyn DebugEmulator<A, V>,
@@ -150,19 +201,6 @@ impl Debugger {
         A: AudioSink,
     {
         match command {
-            Command::ShowRegs => {
-                let nes = emulator.nes();
-                let regs = nes.cpu.regs();
-                let flags = nes.cpu.flags();
-                let status: u8 = flags.into();
-                println!("pc: 0x{:04x}", regs.pc);
-                println!("a: 0x{:02x}", regs.a);
-                println!("x: 0x{:02x}", regs.x);
-                println!("y: 0x{:02x}", regs.y);
-                println!("sp: 0x{:02x}", regs.sp);
-                println!("status: 0x{:02x}", status);
-                println!("flags: {:?}", flags);
-            }
             Command::Step(count) => {
                 for _ in 0..count {
                     emulator.step(video_frame_sink);
@@ -172,30 +210,32 @@ impl Debugger {
                         emulator.emulated_instructions(),
                         self.cursor
                     );
-                    self.disassemble_instruction(emulator.nes());
+                    self.disassemble_instruction(&mut emulator.nes().interconnect);
                 }
+                let nes = emulator.nes();
+                self.print_watch_exprs(&nes.cpu, &mut nes.interconnect);
+            }
+            Command::StepScanline(count) => {
+                let (scanline, dot) = step_scanlines(emulator, video_frame_sink, count);
+                self.cursor = emulator.nes().cpu.regs().pc;
+                println!("scanline: {}, dot: {}", scanline, dot);
+                let nes = emulator.nes();
+                self.print_watch_exprs(&nes.cpu, &mut nes.interconnect);
             }
             Command::Continue => {
                 emulator.set_mode(EmulationMode::Running);
                 emulator.reset_start_time();
             }
-            Command::Goto(address) => {
-                self.cursor = address;
-            }
-            Command::ShowMem(address) => {
-                if let Some(address) = address {
-                    self.cursor = address;
-                }
-
-                self.print_labels_at_cursor();
+            Command::ShowPpuMem(address) => {
+                let mut cursor = address;
 
                 const NUM_ROWS: u32 = 16;
                 const NUM_COLS: u32 = 16;
                 for _ in 0..NUM_ROWS {
-                    print!("0x{:04x}  ", self.cursor);
+                    print!("0x{:04x}  ", cursor);
                     for x in 0..NUM_COLS {
-                        let byte = emulator.nes().interconnect.read_byte(self.cursor);
-                        self.cursor = self.cursor.wrapping_add(1);
+                        let byte = emulator.nes().interconnect.ppu.mem.read_byte(cursor);
+                        cursor = (cursor + 1) % 0x4000;
                         print!("{:02x}", byte);
                         if x < NUM_COLS - 1 {
                             print!(" ");
@@ -204,16 +244,104 @@ impl Debugger {
                     println!();
                 }
             }
-            Command::ShowPpuMem(address) => {
-                let mut cursor = address;
+            Command::ShowPalette => {
+                for line in palette_lines(emulator.nes()) {
+                    println!("{}", line);
+                }
+            }
+            Command::DumpState(ref path) => match write_state_dump(emulator.nes(), path) {
+                Ok(()) => println!("Wrote state to {}", path),
+                Err(e) => println!("Unable to write state to {}: {}", path, e),
+            },
+            Command::DumpSprites(ref path) => match write_sprite_sheet(emulator.nes(), path) {
+                Ok(()) => println!("Wrote sprites to {}", path),
+                Err(e) => println!("Unable to write sprites to {}: {}", path, e),
+            },
+            Command::LoadCdl(ref path) => match fs::read(path) {
+                Ok(cdl) => {
+                    emulator.nes().load_cdl(&cdl);
+                    println!("Loaded code/data log from {}", path);
+                }
+                Err(e) => println!("Unable to read {}: {}", path, e),
+            },
+            Command::ShowInterrupt => {
+                print!("{}", interrupt_state(emulator.nes()));
+            }
+            Command::ClearInterrupt => {
+                clear_interrupt(emulator.nes());
+            }
+            Command::Apu(register, value) => {
+                if let Err(e) = apu_write(emulator.nes(), register, value) {
+                    println!("{}", e);
+                }
+            }
+            Command::Tone(frequency) => {
+                tone_on(emulator.nes(), frequency);
+            }
+            Command::NextRom => {
+                emulator.next_rom();
+                self.cursor = emulator.nes().cpu.regs().pc;
+            }
+            Command::PrevRom => {
+                emulator.prev_rom();
+                self.cursor = emulator.nes().cpu.regs().pc;
+            }
+            Command::Mappers => {
+                for info in MAPPERS {
+                    println!("{}", info);
+                }
+            }
+            Command::Exit => {
+                return true;
+            }
+            Command::Repeat => unreachable!(),
+            _ => {
+                let nes = emulator.nes();
+                self.run_cpu_command(&mut nes.cpu, &mut nes.interconnect, &command);
+            }
+        }
+
+        self.last_command = Some(command);
+
+        false
+    }
+
+    // Runs a command that only needs the CPU and its memory, so it works the same with or without
+    // a cartridge. Returns false if the command needs the rest of the machine.
+    fn run_cpu_command(&mut self, cpu: &mut Cpu, mem: &mut impl Memory, command: &Command) -> bool {
+        match *command {
+            Command::ShowRegs => {
+                let regs = cpu.regs();
+                let flags = cpu.flags();
+                let status: u8 = flags.into();
+                println!("pc: 0x{:04x}", regs.pc);
+                println!("a: 0x{:02x}", regs.a);
+                println!("x: 0x{:02x}", regs.x);
+                println!("y: 0x{:02x}", regs.y);
+                println!("sp: 0x{:02x}", regs.sp);
+                println!("status: 0x{:02x}", status);
+                println!("flags: {:?}", flags);
+            }
+            Command::Goto(address) => {
+                self.cursor = address;
+            }
+            Command::GotoVector(vector) => {
+                self.goto_vector(mem, vector);
+            }
+            Command::ShowMem(address) => {
+                if let Some(address) = address {
+                    self.cursor = address;
+                }
+
+                self.print_labels_at_cursor();
 
                 const NUM_ROWS: u32 = 16;
                 const NUM_COLS: u32 = 16;
                 for _ in 0..NUM_ROWS {
-                    print!("0x{:04x}  ", cursor);
+                    print!("0x{:04x}  ", self.cursor);
                     for x in 0..NUM_COLS {
-                        let byte = emulator.nes().interconnect.ppu.mem.read_byte(cursor);
-                        cursor = (cursor + 1) % 0x4000;
+                        let byte = mem.peek_byte(self.cursor);
+                        self.cursor = self.cursor.wrapping_add(1);
                         print!("{:02x}", byte);
                         if x < NUM_COLS - 1 {
                             print!(" ");
@@ -222,18 +350,59 @@ impl Debugger {
                     println!();
                 }
             }
+            Command::Write(address, ref bytes) => {
+                for (offset, &byte) in bytes.iter().enumerate() {
+                    mem.poke_byte(address.wrapping_add(offset as u16), byte);
+                }
+            }
             Command::ShowStack => {
-                let sp = emulator.nes().cpu.regs().sp;
+                let sp = cpu.regs().sp;
                 let addr = 0x0100 | sp as u16;
 
                 for i in 0..min(10, 0x01FF - addr + 1) {
-                    let byte = emulator.nes().interconnect.read_byte(addr + i);
+                    let byte = mem.peek_byte(addr + i);
                     println!("0x{:04x}  {:02x}", addr + i, byte);
                 }
             }
+            Command::Compare(a, b, len) => {
+                let differences = compare_regions(mem, a, b, len);
+                for &offset in &differences {
+                    let a = a.wrapping_add(offset);
+                    let b = b.wrapping_add(offset);
+                    println!(
+                        "0x{:04x}  {:02x}  0x{:04x}  {:02x}",
+                        a,
+                        mem.peek_byte(a),
+                        b,
+                        mem.peek_byte(b)
+                    );
+                }
+                println!("{} difference(s)", differences.len());
+            }
+            Command::FindInstr(ref pattern) => {
+                let matches = find_instructions(mem, pattern);
+                for &address in &matches {
+                    let mut d = Disassembler::new(address);
+                    println!("0x{:04x}  {}", address, d.disassemble_next(mem));
+                }
+                println!("{} match(es)", matches.len());
+            }
             Command::Disassemble(count) => {
                 for _ in 0..count {
-                    self.cursor = self.disassemble_instruction(emulator.nes());
+                    self.cursor = self.disassemble_instruction(mem);
+                }
+            }
+            Command::DisassembleBack(count) => {
+                // Finding instruction boundaries backwards is a heuristic, see
+                // Disassembler::instructions_before
+                let starts = Disassembler::instructions_before(mem, self.cursor, count as usize);
+                for &start in starts.iter() {
+                    self.cursor = start;
+                    print!("0x{:04x}  ", self.cursor);
+                    self.disassemble_instruction(mem);
+                }
+                if let Some(&first) = starts.first() {
+                    self.cursor = first;
                 }
             }
             Command::Label => {
@@ -249,10 +418,27 @@ impl Debugger {
                     println!("Label .{} doesn't exist", label);
                 }
             }
+            Command::AddComment(address, ref text) => {
+                self.comments.insert(address, text.clone());
+            }
+            Command::ExportMarkdown(start, end, ref path) => {
+                let result = File::create(path).and_then(|file| {
+                    let mut w = BufWriter::new(file);
+                    write_markdown_listing(&mut w, mem, start, end, &self.labels, &self.comments)?;
+                    w.flush()
+                });
+                match result {
+                    Ok(()) => println!("Wrote disassembly to {}", path),
+                    Err(e) => println!("Unable to write disassembly to {}: {}", path, e),
+                }
+            }
             Command::Breakpoint => {
                 for address in self.breakpoints.iter() {
                     println!("* 0x{:04x}", address);
                 }
+                for breakpoint in self.register_breakpoints.iter() {
+                    println!("* {}", breakpoint);
+                }
             }
             Command::AddBreakpoint(address) => {
                 self.breakpoints.insert(address);
@@ -262,23 +448,77 @@ impl Debugger {
                     println!("Breakpoint at 0x{:04x} doesn't exist", address);
                 }
             }
+            Command::AddRegisterBreakpoint(breakpoint) => {
+                if !self.register_breakpoints.contains(&breakpoint) {
+                    self.register_breakpoints.push(breakpoint);
+                }
+            }
+            Command::RemoveRegisterBreakpoint(breakpoint) => {
+                let len = self.register_breakpoints.len();
+                self.register_breakpoints.retain(|&b| b != breakpoint);
+                if self.register_breakpoints.len() == len {
+                    println!("Breakpoint {} doesn't exist", breakpoint);
+                }
+            }
             Command::Watchpoint => {
-                for address in emulator.nes().cpu.watchpoints.iter() {
+                for address in cpu.watchpoints.iter() {
                     println!("* 0x{:04x}", address);
                 }
             }
             Command::AddWatchpoint(address) => {
-                emulator.nes().cpu.watchpoints.insert(address);
+                cpu.watchpoints.insert(address);
             }
             Command::RemoveWatchpoint(address) => {
-                if !emulator.nes().cpu.watchpoints.remove(&address) {
+                if !cpu.watchpoints.remove(&address) {
                     println!("Watchpoint at 0x{:04x} doesn't exist", address);
                 }
             }
+            Command::ShowTrap => match cpu.trap() {
+                Some(trap) => println!("CPU trapped: {}", trap),
+                None => println!("CPU is running"),
+            },
+            Command::WatchExpr(ref name, expr) => {
+                match self.watch_exprs.iter_mut().find(|(n, _)| n == name) {
+                    Some(watch_expr) => watch_expr.1 = expr,
+                    None => self.watch_exprs.push((name.clone(), expr)),
+                }
+                self.print_watch_exprs(cpu, mem);
+            }
+            Command::SetIllegalMode(illegal_mode) => {
+                cpu.set_illegal_mode(illegal_mode);
+                println!("Unofficial opcodes: {:?}", illegal_mode);
+            }
+            _ => return false,
+        }
+
+        true
+    }
+
+    // Runs a command against a RamSystem, which only has a CPU and RAM
+    fn run_ram_command(&mut self, system: &mut RamSystem, command: Command) -> bool {
+        match command {
+            Command::Step(count) => {
+                for _ in 0..count {
+                    system.step();
+                    self.cursor = system.cpu.regs().pc;
+                    print!("0x{:04x}  ", self.cursor);
+                    self.disassemble_instruction(&mut system.ram);
+                }
+                self.print_watch_exprs(&system.cpu, &mut system.ram);
+            }
+            Command::Continue => {
+                self.continue_ram_system(system, INSTRUCTION_LIMIT);
+                self.show_position(&system.cpu, &mut system.ram);
+            }
             Command::Exit => {
                 return true;
             }
             Command::Repeat => unreachable!(),
+            _ => {
+                if !self.run_cpu_command(&mut system.cpu, &mut system.ram, &command) {
+                    println!("Not available without a cartridge");
+                }
+            }
         }
 
         self.last_command = Some(command);
@@ -286,10 +526,59 @@ impl Debugger {
         false
     }
 
-    fn disassemble_instruction(&mut self, nes: &mut Nes) -> u16 {
+    // Runs until a breakpoint or watchpoint is hit, the CPU traps, or `max_instructions` have
+    // executed, since there's no frame loop to break into a program that never stops on its own
+    fn continue_ram_system(&self, system: &mut RamSystem, max_instructions: u64) {
+        for _ in 0..max_instructions {
+            let before = system.cpu.regs();
+            let (_, trigger_watchpoint) = system.cpu.step(&mut system.ram);
+            let after = system.cpu.regs();
+            if let Some(trap) = system.cpu.trap() {
+                println!("CPU trapped: {}", trap);
+                return;
+            }
+            if trigger_watchpoint
+                || self.breakpoints.contains(&after.pc)
+                || self.at_register_breakpoint(before, after)
+            {
+                return;
+            }
+        }
+        println!(
+            "Stopped after {} instructions at 0x{:04x}",
+            max_instructions,
+            system.cpu.regs().pc
+        );
+    }
+
+    fn print_watch_exprs(&self, cpu: &Cpu, mem: &mut impl Memory) {
+        for line in self.watch_expr_lines(cpu, mem) {
+            println!("{}", line);
+        }
+    }
+
+    fn watch_expr_lines(&self, cpu: &Cpu, mem: &mut impl Memory) -> Vec<String> {
+        self.watch_exprs
+            .iter()
+            .map(|(name, expr)| format!("{}: {}", name, eval_expr(cpu, mem, *expr)))
+            .collect()
+    }
+
+    fn goto_vector(&mut self, mem: &mut impl Memory, vector: Vector) {
+        let handler = mem.peek_word(vector.address());
+        self.cursor = handler;
+        for _ in 0..4 {
+            self.cursor = self.disassemble_instruction(mem);
+        }
+
+        // Leave the cursor at the handler's entry point
+        self.cursor = handler;
+    }
+
+    fn disassemble_instruction(&mut self, mem: &mut impl Memory) -> u16 {
         self.print_labels_at_cursor();
         let mut d = Disassembler::new(self.cursor);
-        println!("{}", d.disassemble_next(&mut nes.interconnect));
+        println!("{}", d.disassemble_next(mem));
         d.pc
     }
 
@@ -305,3 +594,694 @@ impl Debugger {
         }
     }
 }
+
+/// Writes a markdown table disassembling `start` to `end`, with each instruction's labels, raw
+/// bytes and comment.
+fn write_markdown_listing<W: Write>(
+    w: &mut W,
+    mem: &mut impl Memory,
+    start: u16,
+    end: u16,
+    labels: &HashMap<String, u16>,
+    comments: &HashMap<u16, String>,
+) -> io::Result<()> {
+    writeln!(w, "| Label | Address | Bytes | Instruction | Comment |")?;
+    writeln!(w, "| --- | --- | --- | --- | --- |")?;
+
+    let mut address = start as u32;
+    while address <= end as u32 {
+        let pc = address as u16;
+        let mut d = Disassembler::new(pc);
+        let instruction = d.disassemble_next(mem);
+        let len = d.pc.wrapping_sub(pc);
+
+        let mut names: Vec<_> = labels
+            .iter()
+            .filter(|&(_, &label_address)| label_address == pc)
+            .map(|(name, _)| format!(".{}", name))
+            .collect();
+        names.sort();
+        let bytes: Vec<_> = (0..len)
+            .map(|offset| format!("{:02x}", mem.peek_byte(pc.wrapping_add(offset))))
+            .collect();
+        let comment = comments
+            .get(&pc)
+            .map_or(String::new(), |text| text.replace('|', "\\|"));
+
+        writeln!(
+            w,
+            "| {} | `${:04x}` | `{}` | `{}` | {} |",
+            names.join(", "),
+            pc,
+            bytes.join(" "),
+            instruction,
+            comment
+        )?;
+        address += len as u32;
+    }
+    Ok(())
+}
+
+/// Writes the on-screen sprites in OAM as a PNG sprite sheet.
+fn write_sprite_sheet<P: AsRef<Path>>(nes: &mut Nes, path: P) -> io::Result<()> {
+    let sheet = nes.interconnect.ppu.sprite_sheet();
+    if sheet.height == 0 {
+        return Err(io::Error::other("no sprites on screen"));
+    }
+
+    let pixels: Vec<u32> = sheet
+        .pixels
+        .iter()
+        .map(|&index| XRGB8888_PALETTE[index as usize])
+        .collect();
+    let mut w = BufWriter::new(File::create(path)?);
+    png::write_png(&mut w, sheet.width, sheet.height, &pixels)?;
+    w.flush()
+}
+
+/// Writes a human-readable snapshot of the machine state, suitable for diffing across runs.
+fn write_state_dump<P: AsRef<Path>>(nes: &Nes, path: P) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+
+    let regs = nes.cpu.regs();
+    let flags = nes.cpu.flags();
+    let status: u8 = flags.into();
+    writeln!(w, "[cpu]")?;
+    writeln!(w, "pc: 0x{:04x}", regs.pc)?;
+    writeln!(w, "a: 0x{:02x}", regs.a)?;
+    writeln!(w, "x: 0x{:02x}", regs.x)?;
+    writeln!(w, "y: 0x{:02x}", regs.y)?;
+    writeln!(w, "sp: 0x{:02x}", regs.sp)?;
+    writeln!(w, "status: 0x{:02x}", status)?;
+    writeln!(w, "flags: {:?}", flags)?;
+    writeln!(w, "cycles: {}", nes.cpu.cycles)?;
+
+    let ppu = &nes.interconnect.ppu;
+    let ppu_state = ppu.get_state();
+    writeln!(w, "\n[ppu]")?;
+    writeln!(w, "frame: {}", ppu_state.frame)?;
+    writeln!(w, "scanline: {}", ppu.scanline)?;
+    writeln!(w, "dot: {}", ppu.scanline_cycle())?;
+    writeln!(w, "regs: {}", to_json(&ppu_state.regs))?;
+
+    let apu_state = nes.interconnect.apu.get_state();
+    writeln!(w, "\n[apu]")?;
+    writeln!(w, "pulse_1: {}", to_json(&apu_state.pulse_1))?;
+    writeln!(w, "pulse_2: {}", to_json(&apu_state.pulse_2))?;
+    writeln!(w, "triangle: {}", to_json(&apu_state.triangle))?;
+    writeln!(w, "noise: {}", to_json(&apu_state.noise))?;
+    writeln!(w, "dmc: {}", to_json(&apu_state.dmc))?;
+    writeln!(w, "frame_counter: {}", to_json(&apu_state.frame_counter))?;
+
+    // Leave out the cartridge RAM, only the bank registers are interesting here
+    let mut mapper_state = serde_json::to_value(nes.interconnect.mapper.borrow().get_state())?;
+    if let Some(serde_json::Value::Object(fields)) = mapper_state
+        .as_object_mut()
+        .and_then(|m| m.values_mut().next())
+    {
+        fields.remove("cartridge");
+    }
+    writeln!(w, "\n[mapper]")?;
+    writeln!(w, "{}", to_json(&mapper_state))?;
+
+    w.flush()
+}
+
+fn interrupt_state(nes: &Nes) -> String {
+    let pending = match nes.cpu.interrupt() {
+        Some(interrupt) => format!("{:?}", interrupt),
+        None => "none".into(),
+    };
+    let apu = &nes.interconnect.apu;
+    format!(
+        "pending: {}\napu frame irq: {}\napu dmc irq: {}\nmapper irq: {}\n",
+        pending,
+        apu.frame_interrupt_flag(),
+        apu.dmc_irq_flag(),
+        nes.interconnect.mapper.borrow().irq_flag()
+    )
+}
+
+// Clears the pending interrupt and every IRQ source, since the APU's would otherwise request the
+// IRQ again on its next step
+fn clear_interrupt(nes: &mut Nes) {
+    nes.cpu.clear_interrupt();
+    nes.interconnect.apu.clear_frame_interrupt_flag();
+    nes.interconnect.apu.clear_dmc_irq_flag();
+    nes.interconnect.mapper.borrow_mut().clear_irq_flag();
+}
+
+// Returns the offsets at which the `len` bytes starting at `a` and `b` differ
+fn compare_regions(mem: &mut impl Memory, a: u16, b: u16, len: u16) -> Vec<u16> {
+    (0..len)
+        .filter(|&offset| {
+            mem.peek_byte(a.wrapping_add(offset)) != mem.peek_byte(b.wrapping_add(offset))
+        })
+        .collect()
+}
+
+// One line per palette, with the four palette RAM indexes and the colors they resolve to
+fn palette_lines(nes: &mut Nes) -> Vec<String> {
+    (0..8u16)
+        .map(|palette| {
+            let indexes: Vec<u8> = (0..4)
+                .map(|entry| {
+                    nes.interconnect
+                        .ppu
+                        .mem
+                        .read_byte(0x3F00 + palette * 4 + entry)
+                })
+                .collect();
+            let colors: Vec<String> = indexes
+                .iter()
+                .map(|&index| format!("#{:06x}", XRGB8888_PALETTE[(index & 0x3F) as usize]))
+                .collect();
+            let indexes: Vec<String> = indexes
+                .iter()
+                .map(|index| format!("{:02x}", index))
+                .collect();
+            let name = if palette < 4 { "bg" } else { "sprite" };
+            format!(
+                "{}{}  {}  {}",
+                name,
+                palette % 4,
+                indexes.join(" "),
+                colors.join(" ")
+            )
+        })
+        .collect()
+}
+
+// Returns the addresses in PRG space whose disassembly matches `pattern`. Every byte offset is
+// tried, since code and data boundaries aren't known. The pattern is a mnemonic, optionally
+// followed by an operand, compared case-insensitively; without an operand any operand matches.
+fn find_instructions(mem: &mut impl Memory, pattern: &str) -> Vec<u16> {
+    let pattern = pattern.to_lowercase();
+    let mut pattern = pattern.split_whitespace();
+    let mnemonic = pattern.next().unwrap_or_default();
+    let operand: String = pattern.collect();
+
+    (0x8000..=0xFFFF)
+        .filter(|&address| {
+            let instruction = Disassembler::new(address).disassemble_next(mem);
+            let mut instruction = instruction.split_whitespace();
+            instruction.next() == Some(mnemonic)
+                && (operand.is_empty() || instruction.collect::<String>() == operand)
+        })
+        .collect()
+}
+
+fn eval_expr(cpu: &Cpu, mem: &mut impl Memory, expr: Expr) -> String {
+    let regs = cpu.regs();
+    match expr {
+        Expr::Register(Register::A) => format!("0x{:02x}", regs.a),
+        Expr::Register(Register::X) => format!("0x{:02x}", regs.x),
+        Expr::Register(Register::Y) => format!("0x{:02x}", regs.y),
+        Expr::Register(Register::Sp) => format!("0x{:02x}", regs.sp),
+        Expr::Register(Register::Pc) => format!("0x{:04x}", regs.pc),
+        Expr::Register(Register::Status) => {
+            let status: u8 = cpu.flags().into();
+            format!("0x{:02x}", status)
+        }
+        Expr::Byte(address) => format!("0x{:02x}", mem.peek_byte(address)),
+        Expr::Word(address) => format!("0x{:04x}", mem.peek_word(address)),
+    }
+}
+
+fn apu_write(nes: &mut Nes, register: u16, value: u8) -> Result<(), String> {
+    if !(0x4000..=0x4017).contains(&register) {
+        return Err(format!(
+            "0x{:04x} isn't an APU register (0x4000-0x4017)",
+            register
+        ));
+    }
+    nes.interconnect.write_byte(register, value);
+    Ok(())
+}
+
+// Plays a constant volume square wave at `frequency` Hz on pulse channel 1
+fn tone_on(nes: &mut Nes, frequency: u16) {
+    // Periods below 8 silence the channel, and the timer is 11 bits
+    let period = (CPU_FREQUENCY / (16 * max(frequency, 1) as u64)).saturating_sub(1);
+    let period = period.clamp(8, 0x7FF) as u16;
+
+    let interconnect = &mut nes.interconnect;
+    interconnect.write_byte(0x4015, 0x01);
+    // 50% duty, length counter halted, constant volume 15
+    interconnect.write_byte(0x4000, 0xBF);
+    // Sweep disabled
+    interconnect.write_byte(0x4001, 0x08);
+    interconnect.write_byte(0x4002, period as u8);
+    interconnect.write_byte(0x4003, (period >> 8) as u8);
+}
+
+// Steps instructions until the PPU has moved on by `count` scanlines, returning its position.
+// Stops at the first instruction boundary on or after the target scanline.
+fn step_scanlines<A, V>(
+    emulator: &mut dyn DebugEmulator<A, V>,
+    video_frame_sink: &mut V,
+    count: u16,
+) -> (i16, u16)
+where
+    V: VideoSink,
+    A: AudioSink,
+{
+    let mut scanline = emulator.nes().ppu_position().0;
+    let mut advanced = 0;
+    while advanced < count as u32 {
+        emulator.step(video_frame_sink);
+        let next = emulator.nes().ppu_position().0;
+        // A long instruction such as OAM DMA can span several scanlines
+        let scanlines_per_frame = emulator.nes().region().scanlines_per_frame();
+        advanced += (next - scanline).rem_euclid(scanlines_per_frame as i16) as u32;
+        scanline = next;
+    }
+
+    emulator.nes().ppu_position()
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_else(|e| format!("<{}>", e))
+}
+
+// NROM-128 with `program` at 0x8000 followed by NOPs, the NMI vector at `nmi_vector` and the
+// other vectors at 0x8000
+#[cfg(test)]
+fn test_nes(program: &[u8], nmi_vector: u16) -> Nes {
+    use rustednes_core::cartridge::{nrom_test_rom, Cartridge};
+
+    let mut rom = nrom_test_rom(program);
+    rom[16 + 0x3FFA..16 + 0x3FFC].copy_from_slice(&nmi_vector.to_le_bytes());
+
+    Nes::new(Cartridge::from_bytes(&rom).unwrap())
+}
+
+// A minimal frontend for running debugger commands in tests
+#[cfg(test)]
+struct TestEmulator {
+    nes: Nes,
+    audio_frame_sink: crate::audio::NullAudioSink,
+    emulated_cycles: u64,
+    emulated_instructions: u64,
+    mode: EmulationMode,
+}
+
+#[cfg(test)]
+impl TestEmulator {
+    fn new(nes: Nes) -> TestEmulator {
+        TestEmulator {
+            nes,
+            audio_frame_sink: crate::audio::NullAudioSink,
+            emulated_cycles: 0,
+            emulated_instructions: 0,
+            mode: EmulationMode::Debugging,
+        }
+    }
+}
+
+#[cfg(test)]
+impl<V: VideoSink> DebugEmulator<crate::audio::NullAudioSink, V> for TestEmulator {
+    fn nes(&mut self) -> &mut Nes {
+        &mut self.nes
+    }
+
+    fn audio_frame_sink(&mut self) -> &mut crate::audio::NullAudioSink {
+        &mut self.audio_frame_sink
+    }
+
+    fn emulated_cycles(&self) -> u64 {
+        self.emulated_cycles
+    }
+
+    fn emulated_instructions(&self) -> u64 {
+        self.emulated_instructions
+    }
+
+    fn mode(&self) -> EmulationMode {
+        self.mode
+    }
+
+    fn set_mode(&mut self, mode: EmulationMode) {
+        self.mode = mode;
+    }
+
+    fn reset_start_time(&mut self) {}
+
+    fn step(&mut self, video_frame_sink: &mut V) -> (u32, bool) {
+        let (cycles, trigger_watchpoint, _) =
+            self.nes.step(video_frame_sink, &mut self.audio_frame_sink);
+        self.emulated_cycles += cycles as u64;
+        self.emulated_instructions += 1;
+        (cycles, trigger_watchpoint)
+    }
+
+    fn next_rom(&mut self) {}
+
+    fn prev_rom(&mut self) {}
+}
+
+// A debugger without the stdin thread
+#[cfg(test)]
+fn test_debugger() -> Debugger {
+    let (prompt_sender, _prompt_receiver) = channel();
+    let (_stdin_sender, stdin_receiver) = channel();
+    Debugger {
+        breakpoints: Default::default(),
+        register_breakpoints: Default::default(),
+        labels: Default::default(),
+        comments: Default::default(),
+        watch_exprs: Default::default(),
+        cursor: 0,
+        last_command: None,
+        prompt_sender,
+        stdin_receiver,
+    }
+}
+
+#[test]
+fn test_write_state_dump() {
+    use crate::audio::{AudioDriver, NullAudioDriver};
+    use rustednes_core::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+    use rustednes_core::sink::Xrgb8888VideoSink;
+
+    // LDX #$42, TXS
+    let mut nes = test_nes(&[0xA2, 0x42, 0x9A], 0x8000);
+    let mut buffer = vec![0u32; SCREEN_WIDTH * SCREEN_HEIGHT];
+    let mut video_sink = Xrgb8888VideoSink::new(&mut buffer);
+    let mut audio_sink = NullAudioDriver.sink();
+    for _ in 0..3 {
+        nes.step(&mut video_sink, &mut audio_sink);
+    }
+
+    let path = std::env::temp_dir().join(format!(
+        "rustednes_test_write_state_dump_{}.txt",
+        std::process::id()
+    ));
+    write_state_dump(&nes, &path).unwrap();
+    let dump = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(dump.contains("pc: 0x8004"));
+    assert!(dump.contains("sp: 0x42"));
+    assert!(dump.contains("[mapper]"));
+}
+
+#[test]
+fn test_goto_vector() {
+    let mut nes = test_nes(&[], 0xC123);
+
+    let mut debugger = test_debugger();
+
+    debugger.goto_vector(&mut nes.interconnect, Vector::Nmi);
+    assert_eq!(debugger.cursor, 0xC123);
+    debugger.goto_vector(&mut nes.interconnect, Vector::Reset);
+    assert_eq!(debugger.cursor, 0x8000);
+}
+
+#[test]
+fn test_show_and_clear_interrupt() {
+    use rustednes_core::cpu::Interrupt;
+
+    let mut nes = test_nes(&[], 0x8000);
+    assert!(interrupt_state(&nes).contains("pending: none"));
+
+    nes.cpu.request_interrupt(Interrupt::Nmi);
+    assert!(interrupt_state(&nes).contains("pending: Nmi"));
+
+    clear_interrupt(&mut nes);
+    assert_eq!(nes.cpu.interrupt(), None);
+    assert!(interrupt_state(&nes).contains("pending: none"));
+}
+
+#[test]
+fn test_clear_dmc_interrupt() {
+    use rustednes_core::sink::NullVideoSink;
+
+    // SEI, then spin, so the IRQ stays pending
+    let mut nes = test_nes(&[0x78, 0x4C, 0x01, 0x80], 0x8000);
+    let mut video_sink = NullVideoSink::new();
+    let mut audio_sink = crate::audio::NullAudioSink;
+    // Inhibit the frame IRQ, then play a 1 byte sample with its IRQ enabled
+    nes.interconnect.write_byte(0x4017, 0x40);
+    nes.interconnect.write_byte(0x4010, 0x8F);
+    nes.interconnect.write_byte(0x4013, 0x00);
+    nes.interconnect.write_byte(0x4015, 0x10);
+    for _ in 0..1000 {
+        nes.step(&mut video_sink, &mut audio_sink);
+    }
+    assert!(interrupt_state(&nes).contains("apu dmc irq: true"));
+
+    clear_interrupt(&mut nes);
+    for _ in 0..1000 {
+        nes.step(&mut video_sink, &mut audio_sink);
+    }
+    assert!(interrupt_state(&nes).contains("apu dmc irq: false"));
+    assert_eq!(nes.cpu.interrupt(), None);
+}
+
+#[test]
+fn test_compare_regions() {
+    let mut nes = test_nes(&[], 0x8000);
+    for offset in 0..0x20 {
+        nes.interconnect.write_byte(0x0200 + offset, offset as u8);
+        nes.interconnect.write_byte(0x0300 + offset, offset as u8);
+    }
+    nes.interconnect.write_byte(0x0317, 0xFF);
+
+    assert_eq!(
+        compare_regions(&mut nes.interconnect, 0x0200, 0x0300, 0x20),
+        vec![0x17]
+    );
+    // RAM is mirrored every 0x800 bytes
+    assert!(compare_regions(&mut nes.interconnect, 0x0000, 0x0800, 0x800).is_empty());
+}
+
+#[test]
+fn test_palette_lines() {
+    let mut nes = test_nes(&[], 0x8000);
+    for (offset, &index) in [0x0F, 0x21, 0x31, 0x30].iter().enumerate() {
+        nes.interconnect
+            .ppu
+            .mem
+            .write_byte(0x3F00 + offset as u16, index);
+    }
+    nes.interconnect.ppu.mem.write_byte(0x3F1D, 0x16);
+
+    let lines = palette_lines(&mut nes);
+    assert_eq!(lines.len(), 8);
+    assert_eq!(
+        lines[0],
+        "bg0  0f 21 31 30  #000000 #64b0ff #c0dfff #fffeff"
+    );
+    // $3F10 mirrors the backdrop color at $3F00
+    assert!(lines[4].starts_with("sprite0  0f "));
+    assert!(lines[7].starts_with("sprite3  00 16 00 00  #666666 #"));
+}
+
+#[test]
+fn test_find_instructions() {
+    // sta $2007; lda #$00; sta $2007; sta $2006
+    let program = [
+        0x8D, 0x07, 0x20, 0xA9, 0x00, 0x8D, 0x07, 0x20, 0x8D, 0x06, 0x20,
+    ];
+    let mut nes = test_nes(&program, 0x8000);
+
+    assert_eq!(
+        find_instructions(&mut nes.interconnect, "STA $2007"),
+        vec![0x8000, 0x8005, 0xC000, 0xC005]
+    );
+    assert_eq!(
+        find_instructions(&mut nes.interconnect, "sta"),
+        vec![0x8000, 0x8005, 0x8008, 0xC000, 0xC005, 0xC008]
+    );
+}
+
+#[test]
+fn test_apu_write() {
+    let mut nes = test_nes(&[], 0x8000);
+    assert_eq!(nes.interconnect.read_byte(0x4015) & 0x01, 0);
+
+    // Enable pulse 1, then load its period and length counter
+    apu_write(&mut nes, 0x4015, 0x01).unwrap();
+    apu_write(&mut nes, 0x4000, 0xBF).unwrap();
+    apu_write(&mut nes, 0x4002, 0xFD).unwrap();
+    apu_write(&mut nes, 0x4003, 0x08).unwrap();
+    assert_eq!(nes.interconnect.read_byte(0x4015) & 0x01, 0x01);
+
+    assert!(apu_write(&mut nes, 0x2000, 0x00).is_err());
+
+    let mut nes = test_nes(&[], 0x8000);
+    tone_on(&mut nes, 440);
+    assert_eq!(nes.interconnect.read_byte(0x4015) & 0x01, 0x01);
+}
+
+#[test]
+fn test_watch_exprs() {
+    use crate::audio::{AudioDriver, NullAudioDriver};
+    use rustednes_core::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+    use rustednes_core::sink::Xrgb8888VideoSink;
+
+    // INC $10, LDX #$05
+    let mut nes = test_nes(&[0xE6, 0x10, 0xA2, 0x05], 0x8000);
+    nes.interconnect.write_byte(0x0010, 0x41);
+    let mut debugger = test_debugger();
+    debugger
+        .watch_exprs
+        .push(("counter".into(), Expr::Byte(0x0010)));
+    debugger
+        .watch_exprs
+        .push(("x".into(), Expr::Register(Register::X)));
+    assert_eq!(
+        debugger.watch_expr_lines(&nes.cpu, &mut nes.interconnect),
+        vec!["counter: 0x41", "x: 0x00"]
+    );
+
+    let mut buffer = vec![0u32; SCREEN_WIDTH * SCREEN_HEIGHT];
+    let mut video_sink = Xrgb8888VideoSink::new(&mut buffer);
+    let mut audio_sink = NullAudioDriver.sink();
+    nes.step(&mut video_sink, &mut audio_sink);
+    assert_eq!(
+        debugger.watch_expr_lines(&nes.cpu, &mut nes.interconnect),
+        vec!["counter: 0x42", "x: 0x00"]
+    );
+    nes.step(&mut video_sink, &mut audio_sink);
+    assert_eq!(
+        debugger.watch_expr_lines(&nes.cpu, &mut nes.interconnect),
+        vec!["counter: 0x42", "x: 0x05"]
+    );
+}
+
+#[test]
+fn test_step_scanline() {
+    use rustednes_core::sink::NullVideoSink;
+
+    let mut emulator = TestEmulator::new(test_nes(&[], 0x8000));
+    let mut video_sink = NullVideoSink::new();
+    let mut debugger = test_debugger();
+
+    let (start, _) = emulator.nes.ppu_position();
+    debugger.run_command(&mut emulator, Command::StepScanline(3), &mut video_sink);
+    let (scanline, _) = emulator.nes.ppu_position();
+    assert_eq!(scanline, start + 3);
+    assert_eq!(debugger.cursor, emulator.nes.cpu.regs().pc);
+}
+
+#[test]
+fn test_register_breakpoint() {
+    use rustednes_core::sink::NullVideoSink;
+
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x02, // lda #2
+        0x38,       // sec
+        0xE9, 0x01, // sbc #1
+        0xE9, 0x01, // sbc #1
+        0xA9, 0x00, // lda #0
+        0xA9, 0x05, // lda #5
+    ];
+    let mut emulator = TestEmulator::new(test_nes(&program, 0x8000));
+    let mut video_sink = NullVideoSink::new();
+    let mut debugger = test_debugger();
+    let command = "break when a becomes zero".parse().unwrap();
+    debugger.run_command(&mut emulator, command, &mut video_sink);
+
+    let mut hits = Vec::new();
+    for _ in 0..6 {
+        let before = emulator.nes.cpu.regs();
+        emulator.step(&mut video_sink);
+        if debugger.at_register_breakpoint(before, emulator.nes.cpu.regs()) {
+            hits.push(before.pc);
+        }
+    }
+    // Only the second sbc zeroes A. Loading zero into A while it's already zero doesn't count.
+    assert_eq!(hits, vec![0x8005]);
+}
+
+#[test]
+fn test_write_markdown_listing() {
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x00,       // lda #$00
+        0x8D, 0x00, 0x20, // sta $2000
+        0x4C, 0x05, 0x80, // jmp $8005
+    ];
+    let mut nes = test_nes(&program, 0x8000);
+    let labels = HashMap::from([("forever".to_string(), 0x8005)]);
+    let comments = HashMap::from([(0x8002, "disable NMI".to_string())]);
+
+    let mut markdown = Vec::new();
+    write_markdown_listing(
+        &mut markdown,
+        &mut nes.interconnect,
+        0x8000,
+        0x8007,
+        &labels,
+        &comments,
+    )
+    .unwrap();
+    let markdown = String::from_utf8(markdown).unwrap();
+
+    let lines: Vec<_> = markdown.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[2], "|  | `$8000` | `a9 00` | `lda #$00` |  |");
+    assert_eq!(
+        lines[3],
+        "|  | `$8002` | `8d 00 20` | `sta $2000` | disable NMI |"
+    );
+    assert_eq!(
+        lines[4],
+        "| .forever | `$8005` | `4c 05 80` | `jmp $8005` |  |"
+    );
+}
+
+#[test]
+fn test_ram_system_commands() {
+    let mut system = RamSystem::new(0x0400);
+    let mut debugger = test_debugger();
+    let run = |debugger: &mut Debugger, system: &mut RamSystem, command: &str| {
+        let command = debugger.parse_command(command).unwrap();
+        debugger.run_ram_command(system, command)
+    };
+
+    // ldx #3; inx; stx $10; jmp $0402
+    run(
+        &mut debugger,
+        &mut system,
+        "write 0400 a2 03 e8 86 10 4c 02 04",
+    );
+    assert_eq!(system.ram[0x0405], 0x4C);
+
+    run(&mut debugger, &mut system, "s 3");
+    assert_eq!(system.ram[0x0010], 0x04);
+    assert_eq!(debugger.cursor, 0x0405);
+
+    run(&mut debugger, &mut system, "ab 0405");
+    run(&mut debugger, &mut system, "c");
+    assert_eq!(system.cpu.regs().pc, 0x0405);
+    assert_eq!(system.ram[0x0010], 0x05);
+    assert_eq!(debugger.cursor, 0x0405);
+
+    // Commands that need the rest of the NES are refused rather than run
+    run(&mut debugger, &mut system, "tone 440");
+    assert!(!run(&mut debugger, &mut system, ""));
+
+    // A jam stops continuing even without a breakpoint
+    run(&mut debugger, &mut system, "rb 0405");
+    run(&mut debugger, &mut system, "write 0405 02");
+    run(&mut debugger, &mut system, "c");
+    assert!(system.cpu.trap().is_some());
+    assert!(run(&mut debugger, &mut system, "exit"));
+}
+
+#[test]
+fn test_continue_ram_system_limit() {
+    let mut system = RamSystem::new(0x0400);
+    // jmp $0400
+    system.load(0x0400, &[0x4C, 0x00, 0x04]);
+    let debugger = test_debugger();
+
+    // Without a breakpoint or trap, continuing still comes back
+    debugger.continue_ram_system(&mut system, 1000);
+    assert_eq!(system.cpu.regs().pc, 0x0400);
+    assert_eq!(system.cpu.trap(), None);
+}
@@ -1,5 +1,6 @@
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub enum EmulationMode {
     Running,
+    Paused,
     Debugging,
 }
@@ -2,5 +2,6 @@ pub mod audio;
 pub mod debugger;
 pub mod emulation_mode;
 pub mod logger;
+pub mod png;
 pub mod state;
 pub mod time;
@@ -9,14 +9,23 @@ where
         return;
     }
 
+    let default_filter = verbosity
+        .log_level()
+        .map(|level| level.as_str().to_lowercase())
+        .unwrap_or_else(|| "trace".to_owned());
+
+    initialize_with_filter(Some(&default_filter));
+}
+
+/// Installs the console logger, using `RUST_LOG` if set and `default_filter` otherwise. Nothing is
+/// logged when `default_filter` is `None`.
+pub fn initialize_with_filter(default_filter: Option<&str>) {
+    let Some(default_filter) = default_filter else {
+        return;
+    };
+
     if std::env::var("RUST_LOG").is_err() {
-        std::env::set_var(
-            "RUST_LOG",
-            verbosity
-                .log_level()
-                .map(|level| level.as_str().to_lowercase())
-                .unwrap_or_else(|| "trace".to_owned()),
-        )
+        std::env::set_var("RUST_LOG", default_filter)
     }
 
     let format = tracing_subscriber::fmt::format()
@@ -30,3 +39,10 @@ where
         .event_format(format)
         .init();
 }
+
+#[test]
+fn test_quiet_installs_no_logger() {
+    initialize_with_filter(None);
+    tracing::info!("not printed");
+    assert!(!tracing::dispatcher::has_been_set());
+}
@@ -0,0 +1,141 @@
+// A minimal PNG encoder for debugging images, storing the pixels uncompressed
+
+use std::io::{self, Write};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+// Largest payload of a stored deflate block
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+// Writes `pixels`, `width` XRGB8888 pixels per row, as an 8-bit RGB PNG
+pub fn write_png<W: Write>(
+    w: &mut W,
+    width: usize,
+    height: usize,
+    pixels: &[u32],
+) -> io::Result<()> {
+    assert_eq!(pixels.len(), width * height);
+
+    // Each row starts with its filter type, 0 for none
+    let mut raw = Vec::with_capacity(height * (1 + width * 3));
+    for row in pixels.chunks(width.max(1)) {
+        raw.push(0);
+        for &pixel in row {
+            raw.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8]);
+        }
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth 8, color type 2 (RGB), default compression, filtering and no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    w.write_all(&SIGNATURE)?;
+    write_chunk(w, b"IHDR", &header)?;
+    write_chunk(w, b"IDAT", &zlib_stored(&raw))?;
+    write_chunk(w, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(kind);
+    hasher.update(data);
+
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    w.write_all(&hasher.finalize().to_be_bytes())
+}
+
+// Wraps `data` in a zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % MODULUS;
+        (a, (b + a) % MODULUS)
+    });
+    (b << 16) | a
+}
+
+#[test]
+fn test_adler32() {
+    assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+}
+
+#[test]
+fn test_write_png() {
+    // Big enough to need two stored blocks
+    let (width, height) = (200, 120);
+    let pixels: Vec<u32> = (0..width * height).map(|i| (i as u32) * 0x010203).collect();
+    let mut png = Vec::new();
+    write_png(&mut png, width, height, &pixels).unwrap();
+
+    assert_eq!(png[..8], SIGNATURE);
+    let mut chunks = Vec::new();
+    let mut rest = &png[8..];
+    while !rest.is_empty() {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+        let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+        assert_eq!(crc, crc32fast::hash(&rest[4..8 + len]));
+        chunks.push((kind.to_vec(), data.to_vec()));
+        rest = &rest[12 + len..];
+    }
+    let kinds: Vec<_> = chunks.iter().map(|(kind, _)| &kind[..]).collect();
+    assert_eq!(kinds, [&b"IHDR"[..], b"IDAT", b"IEND"]);
+
+    let header = &chunks[0].1;
+    assert_eq!(header[..4], (width as u32).to_be_bytes());
+    assert_eq!(header[4..8], (height as u32).to_be_bytes());
+    assert_eq!(header[8..], [8, 2, 0, 0, 0]);
+
+    // Inflate the stored blocks
+    let zlib = &chunks[1].1;
+    assert_eq!(u16::from_be_bytes([zlib[0], zlib[1]]) % 31, 0);
+    let mut raw = Vec::new();
+    let mut offset = 2;
+    loop {
+        let last = zlib[offset] & 0x01 != 0;
+        assert_eq!(zlib[offset] & 0x06, 0);
+        let len = u16::from_le_bytes([zlib[offset + 1], zlib[offset + 2]]);
+        let nlen = u16::from_le_bytes([zlib[offset + 3], zlib[offset + 4]]);
+        assert_eq!(nlen, !len);
+        raw.extend_from_slice(&zlib[offset + 5..offset + 5 + len as usize]);
+        offset += 5 + len as usize;
+        if last {
+            break;
+        }
+    }
+    assert_eq!(zlib[offset..], adler32(&raw).to_be_bytes());
+
+    let decoded: Vec<u32> = raw
+        .chunks(1 + width * 3)
+        .flat_map(|row| {
+            assert_eq!(row[0], 0);
+            row[1..]
+                .chunks(3)
+                .map(|rgb| u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    let expected: Vec<u32> = pixels.iter().map(|pixel| pixel & 0xFF_FFFF).collect();
+    assert_eq!(decoded, expected);
+}
@@ -9,8 +9,9 @@ use rustednes_core::{nes::Nes, serialize};
 use tracing::{error, info};
 
 pub struct StateManager {
-    rom_path: PathBuf,
-    slots: Vec<Option<String>>,
+    // Save state files are named after the ROM. Without one, states are only kept in memory.
+    rom_path: Option<PathBuf>,
+    slots: Vec<Option<Vec<u8>>>,
 }
 
 impl StateManager {
@@ -18,47 +19,75 @@ impl StateManager {
         assert!(num_slots > 0);
 
         Self {
-            rom_path,
+            rom_path: Some(rom_path),
             slots: vec![None; num_slots],
         }
     }
 
-    pub fn save_state(&mut self, nes: &Nes, slot: usize) {
+    // For a ROM that isn't a file, such as one read from stdin, so there's nowhere to put save
+    // state files
+    pub fn in_memory(num_slots: usize) -> Self {
+        assert!(num_slots > 0);
+
+        Self {
+            rom_path: None,
+            slots: vec![None; num_slots],
+        }
+    }
+
+    // Returns whether the state was saved
+    pub fn save_state(&mut self, nes: &Nes, slot: usize) -> bool {
         if slot >= self.slots.len() {
             error!(
                 "Couldn't save state to slot {}, the maximum is: {}",
                 slot,
                 self.slots.len() - 1
             );
-            return;
+            return false;
         }
 
         info!("Saving state to slot: {}", slot);
-        self.slots[slot] = serde_json::to_string(&serialize::get_state(nes)).ok();
+        let mut data = serialize::header(nes).to_vec();
+        self.slots[slot] = serde_json::to_writer(&mut data, &serialize::get_state(nes))
+            .ok()
+            .map(|_| data);
+        self.slots[slot].is_some()
     }
 
-    pub fn load_state(&mut self, nes: &mut Nes, slot: usize) {
+    // Returns whether a state was loaded
+    pub fn load_state(&mut self, nes: &mut Nes, slot: usize) -> bool {
         if slot >= self.slots.len() {
             error!(
                 "Couldn't load state to slot {}, the maximum is: {}",
                 slot,
                 self.slots.len() - 1
             );
-            return;
+            return false;
         }
 
         if self.slots[slot].is_none() {
             self.load_state_from_file(slot);
         }
-        if let Some(ref s) = self.slots[slot] {
-            info!("Loading state from slot: {}", slot);
-            match serde_json::from_str(s) {
-                Ok(state) => {
-                    serialize::apply_state(nes, state);
-                }
-                Err(e) => {
-                    error!("Error applying save state: {}", e);
-                }
+        let Some(ref data) = self.slots[slot] else {
+            return false;
+        };
+
+        info!("Loading state from slot: {}", slot);
+        let state = match serialize::check_header(nes, data) {
+            Ok(state) => state,
+            Err(e) => {
+                error!("Error applying save state: {}", e);
+                return false;
+            }
+        };
+        match serde_json::from_slice(state) {
+            Ok(state) => {
+                serialize::apply_state(nes, state);
+                true
+            }
+            Err(e) => {
+                error!("Error applying save state: {}", e);
+                false
             }
         }
     }
@@ -70,8 +99,10 @@ impl StateManager {
     fn write_state_to_file(&mut self, slot: usize) {
         assert!(slot < self.slots.len());
 
+        let Some(path) = self.save_state_file_path(slot) else {
+            return;
+        };
         if let Some(ref s) = self.slots[slot] {
-            let path = self.save_state_file_path(slot);
             let save_file = OpenOptions::new()
                 .write(true)
                 .truncate(true)
@@ -81,7 +112,7 @@ impl StateManager {
                 Ok(save_file) => {
                     info!("Writing save state file: {}", path.display());
                     let mut save_writer = BufWriter::new(save_file);
-                    let _ = save_writer.write_all(s.as_bytes());
+                    let _ = save_writer.write_all(s);
                 }
                 Err(e) => {
                     error!("Unable to open file to save state: {}", e);
@@ -93,7 +124,15 @@ impl StateManager {
     fn load_state_from_file(&mut self, slot: usize) {
         assert!(slot < self.slots.len());
 
-        let path = self.save_state_file_path(slot);
+        let (Some(rom_path), Some(path)) = (&self.rom_path, self.save_state_file_path(slot)) else {
+            return;
+        };
+        // Fall back to the name save states had before, which clashed with battery saves
+        let path = if path.exists() {
+            path
+        } else {
+            rom_path.with_extension(format!("sav{}", slot))
+        };
         let save_file = OpenOptions::new()
             .read(true)
             .write(false)
@@ -102,13 +141,63 @@ impl StateManager {
         if let Ok(save_file) = save_file {
             info!("Loading save state file: {}", path.display());
             let mut save_reader = BufReader::new(save_file);
-            let mut serialized = String::new();
-            let _ = save_reader.read_to_string(&mut serialized);
+            let mut serialized = Vec::new();
+            let _ = save_reader.read_to_end(&mut serialized);
             self.slots[slot] = Some(serialized);
         }
     }
 
-    fn save_state_file_path(&self, slot: usize) -> PathBuf {
-        self.rom_path.with_extension(format!("sav{}", slot))
+    fn save_state_file_path(&self, slot: usize) -> Option<PathBuf> {
+        self.rom_path
+            .as_ref()
+            .map(|rom_path| rom_path.with_extension(format!("state{}", slot)))
+    }
+}
+
+#[test]
+fn test_save_state_file_path() {
+    let state_manager = StateManager::new(PathBuf::from("roms/game.nes"), 8);
+    assert_eq!(
+        state_manager.save_state_file_path(0),
+        Some(PathBuf::from("roms/game.state0"))
+    );
+    assert_eq!(
+        state_manager.save_state_file_path(7),
+        Some(PathBuf::from("roms/game.state7"))
+    );
+
+    let state_manager = StateManager::in_memory(8);
+    assert_eq!(state_manager.save_state_file_path(0), None);
+}
+
+#[test]
+fn test_slots_round_trip() {
+    use rustednes_core::cartridge::{nrom_test_rom, Cartridge};
+    use rustednes_core::memory::Memory;
+
+    let mut nes = Nes::new(Cartridge::from_bytes(&nrom_test_rom(&[])).unwrap());
+
+    // Unique per process, so concurrent test runs don't share the state files
+    let rom_path = std::env::temp_dir().join(format!(
+        "rustednes_test_slots_round_trip_{}.nes",
+        std::process::id()
+    ));
+    let mut state_manager = StateManager::new(rom_path.clone(), 8);
+    nes.interconnect.write_byte(0x0010, 0x11);
+    assert!(state_manager.save_state(&nes, 2));
+    nes.interconnect.write_byte(0x0010, 0x22);
+    assert!(state_manager.save_state(&nes, 5));
+    state_manager.write_state_to_files();
+
+    // A new manager only has the files to go on
+    let mut state_manager = StateManager::new(rom_path, 8);
+    assert!(!state_manager.load_state(&mut nes, 3));
+    assert!(state_manager.load_state(&mut nes, 2));
+    assert_eq!(nes.interconnect.read_byte(0x0010), 0x11);
+    assert!(state_manager.load_state(&mut nes, 5));
+    assert_eq!(nes.interconnect.read_byte(0x0010), 0x22);
+
+    for slot in [2, 5] {
+        std::fs::remove_file(state_manager.save_state_file_path(slot).unwrap()).unwrap();
     }
 }
@@ -0,0 +1,86 @@
+// Runs a bare 6502 program on the RustedNES CPU core, outside of any NES hardware.
+//
+// The program sums an array of bytes and stores the result. The CPU is paced to the clock
+// rate given as the first argument in Hz (1 MHz by default).
+//
+//     cargo run -p rustednes-core --example standalone_6502 -- 2000000
+
+use rustednes_core::cpu::Cpu;
+use rustednes_core::memory::Memory;
+
+use std::env;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PROGRAM_START: u16 = 0x0600;
+const DONE_ADDRESS: u16 = 0x0610;
+
+#[rustfmt::skip]
+const PROGRAM: &[u8] = &[
+    0xA2, 0x00,       // LDX #$00
+    0xA9, 0x00,       // LDA #$00
+    0x18,             // CLC
+    0x7D, 0x00, 0x02, // loop: ADC $0200,X
+    0xE8,             // INX
+    0xE0, 0x08,       // CPX #$08
+    0xD0, 0xF8,       // BNE loop
+    0x8D, 0x00, 0x03, // STA $0300
+    0x4C, 0x10, 0x06, // done: JMP done
+];
+
+// 64KB of flat RAM
+struct FlatRam {
+    bytes: Vec<u8>,
+}
+
+impl Memory for FlatRam {
+    fn read_byte(&mut self, address: u16) -> u8 {
+        self.bytes[address as usize]
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        self.bytes[address as usize] = value;
+    }
+}
+
+fn main() {
+    let clock_hz: u64 = env::args()
+        .nth(1)
+        .map(|arg| arg.parse().expect("clock rate must be a number of Hz"))
+        .unwrap_or(1_000_000);
+
+    let mut ram = FlatRam {
+        bytes: vec![0; 0x10000],
+    };
+    let start = PROGRAM_START as usize;
+    ram.bytes[start..start + PROGRAM.len()].copy_from_slice(PROGRAM);
+    ram.bytes[0x0200..0x0208].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+
+    // Point the reset vector at the program
+    ram.bytes[0xFFFC] = PROGRAM_START as u8;
+    ram.bytes[0xFFFD] = (PROGRAM_START >> 8) as u8;
+
+    let mut cpu = Cpu::standalone();
+    cpu.reset(&mut ram);
+
+    let start_time = Instant::now();
+    let mut cycles = 0u64;
+    while cpu.regs().pc != DONE_ADDRESS {
+        let (step_cycles, _) = cpu.step(&mut ram);
+        cycles += step_cycles as u64;
+
+        // Sleep if we're ahead of the requested clock
+        let target = Duration::from_nanos(cycles * 1_000_000_000 / clock_hz);
+        if let Some(ahead) = target.checked_sub(start_time.elapsed()) {
+            thread::sleep(ahead);
+        }
+    }
+
+    println!(
+        "Sum: {} ({} cycles at {} Hz, registers: {:?})",
+        ram.bytes[0x0300],
+        cycles,
+        clock_hz,
+        cpu.regs()
+    );
+}
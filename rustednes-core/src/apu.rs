@@ -1,4 +1,4 @@
-use crate::cpu::{Cpu, Interrupt, CPU_FREQUENCY};
+use crate::cpu::{Cpu, Interrupt, Region, CPU_FREQUENCY};
 use crate::mapper::{Mapper, MapperEnum};
 use crate::memory::Memory;
 use crate::sink::*;
@@ -7,11 +7,17 @@ use once_cell::sync::Lazy;
 use serde_derive::{Deserialize, Serialize};
 
 use std::cell::RefCell;
+use std::io::{self, Write};
+use std::mem;
+use std::num::NonZeroU64;
 use std::rc::Rc;
 
 pub const CPU_CYCLES_PER_SAMPLE: u64 = 41;
 pub const SAMPLE_RATE: u32 = (CPU_FREQUENCY / CPU_CYCLES_PER_SAMPLE) as u32;
 
+// Channel buffers that aren't taken keep only about this many of the most recent levels
+const CHANNEL_BUFFER_MAX_LEN: usize = 4096;
+
 static DUTY_CYCLE_TABLE: &[[u8; 8]] = &[
     [0, 1, 0, 0, 0, 0, 0, 0],
     [0, 1, 1, 0, 0, 0, 0, 0],
@@ -57,11 +63,41 @@ static TND_TABLE: Lazy<[f32; 203]> = Lazy::new(|| {
     tnd_table
 });
 
+// How the channel levels combine into one sample
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Mixing {
+    // A weighted sum of the levels, which approximates the 2A03 to within a few percent
+    Linear,
+    // The 2A03's nonlinear DAC response, from the documented pulse and triangle/noise/DMC lookup
+    // tables
+    #[default]
+    Nonlinear,
+}
+
+// Mixes the channel levels into a sample between 0 and 1
+fn mix(mixing: Mixing, pulse_1: u8, pulse_2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+    match mixing {
+        Mixing::Linear => {
+            let pulse_out = 0.00752 * (pulse_1 as f32 + pulse_2 as f32);
+            let tnd_out = 0.00851 * triangle as f32 + 0.00494 * noise as f32 + 0.00335 * dmc as f32;
+            pulse_out + tnd_out
+        }
+        Mixing::Nonlinear => {
+            let pulse_out = PULSE_TABLE[pulse_1 as usize + pulse_2 as usize];
+            let tnd_out = TND_TABLE[3 * triangle as usize + 2 * noise as usize + dmc as usize];
+            pulse_out + tnd_out
+        }
+    }
+}
+
 pub struct Apu {
     cycles: u64,
 
     last_sampled_cycles: u64,
 
+    // Number of CPU cycles between generated samples, which sets the internal sample rate
+    cpu_cycles_per_sample: NonZeroU64,
+
     pulse_1: Pulse,
     pulse_2: Pulse,
     triangle: Triangle,
@@ -71,11 +107,73 @@ pub struct Apu {
 
     mapper: Rc<RefCell<MapperEnum>>,
 
+    region: Region,
     filter: Box<dyn Filter>,
+    mixing: Mixing,
+
+    channel_buffers: Option<ChannelBuffers>,
+    register_log: Option<Vec<RegisterWrite>>,
 
     pub settings: Settings,
 }
 
+// A write to an APU register, timestamped with the CPU cycle it happened on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterWrite {
+    pub cycle: u64,
+    pub address: u16,
+    pub value: u8,
+}
+
+// Writes one "cycle address value" line per register write, such as "29781 $4000 $BF", for
+// replaying in external tools
+pub fn write_register_log<W: Write>(log: &[RegisterWrite], w: &mut W) -> io::Result<()> {
+    for write in log {
+        writeln!(
+            w,
+            "{} ${:04X} ${:02X}",
+            write.cycle, write.address, write.value
+        )?;
+    }
+    Ok(())
+}
+
+// Output levels of each channel before mixing, one per generated sample, for frontends that draw
+// oscilloscopes or level meters. Pulse, triangle and noise levels range from 0 to 15, and DMC
+// levels from 0 to 127. Silenced channels are reported as 0, even though the triangle holds its
+// last level.
+#[derive(Debug, Default, Clone)]
+pub struct ChannelBuffers {
+    pub pulse_1: Vec<u8>,
+    pub pulse_2: Vec<u8>,
+    pub triangle: Vec<u8>,
+    pub noise: Vec<u8>,
+    pub dmc: Vec<u8>,
+}
+
+impl ChannelBuffers {
+    fn push(&mut self, pulse_1: u8, pulse_2: u8, triangle: u8, noise: u8, dmc: u8) {
+        if self.pulse_1.len() >= CHANNEL_BUFFER_MAX_LEN {
+            // Drop the oldest half at once, to avoid shifting every buffer for each sample
+            for buffer in [
+                &mut self.pulse_1,
+                &mut self.pulse_2,
+                &mut self.triangle,
+                &mut self.noise,
+                &mut self.dmc,
+            ] {
+                buffer.drain(..CHANNEL_BUFFER_MAX_LEN / 2);
+            }
+        }
+
+        self.pulse_1.push(pulse_1);
+        self.pulse_2.push(pulse_2);
+        self.triangle.push(triangle);
+        self.noise.push(noise);
+        self.dmc.push(dmc);
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct State {
     pub cycles: u64,
@@ -93,6 +191,7 @@ impl Apu {
         Apu {
             cycles: 0,
             last_sampled_cycles: 0,
+            cpu_cycles_per_sample: NonZeroU64::new(CPU_CYCLES_PER_SAMPLE).unwrap(),
             pulse_1: Pulse::new(SweepNegationType::OnesComplement),
             pulse_2: Pulse::new(SweepNegationType::TwosComplement),
             triangle: Triangle::new(),
@@ -100,11 +199,11 @@ impl Apu {
             dmc: Dmc::new(),
             frame_counter: FrameCounter::new(),
             mapper,
-            filter: Box::new(
-                LowPassFilter::new(0.815_686)
-                    .chain(HighPassFilter::new(0.996_039))
-                    .chain(HighPassFilter::new(0.999_835)),
-            ),
+            region: Region::Ntsc,
+            filter: filter_chain(Region::Ntsc, CPU_CYCLES_PER_SAMPLE),
+            mixing: Mixing::default(),
+            channel_buffers: None,
+            register_log: None,
             settings: Settings {
                 pulse_1_enabled: true,
                 pulse_2_enabled: true,
@@ -116,6 +215,49 @@ impl Apu {
         }
     }
 
+    // Generate one sample every `cpu_cycles_per_sample` CPU cycles instead of the default
+    // CPU_CYCLES_PER_SAMPLE, retuning the output filters for the new sample period. The audio sink
+    // must then be resampled from `sample_rate()`.
+    pub fn set_internal_rate(&mut self, cpu_cycles_per_sample: NonZeroU64) {
+        self.cpu_cycles_per_sample = cpu_cycles_per_sample;
+        self.last_sampled_cycles = self.cycles;
+        self.filter = filter_chain(self.region, cpu_cycles_per_sample.get());
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        sample_rate(self.region, self.cpu_cycles_per_sample)
+    }
+
+    // Starts recording each channel's level, to be collected with `take_channel_buffers`
+    pub fn enable_channel_buffers(&mut self) {
+        self.channel_buffers = Some(ChannelBuffers::default());
+    }
+
+    // Returns the levels recorded since the last call, which frontends should call once a frame
+    pub fn take_channel_buffers(&mut self) -> Option<ChannelBuffers> {
+        self.channel_buffers.as_mut().map(std::mem::take)
+    }
+
+    // Starts recording every register write, to be collected with `take_register_log`
+    pub fn enable_register_log(&mut self) {
+        self.register_log = Some(Vec::new());
+    }
+
+    // Returns the writes recorded since the last call
+    pub fn take_register_log(&mut self) -> Option<Vec<RegisterWrite>> {
+        self.register_log.as_mut().map(std::mem::take)
+    }
+
+    // Retunes the output filters for the region's CPU clock
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.filter = filter_chain(region, self.cpu_cycles_per_sample.get());
+    }
+
+    pub fn set_mixing(&mut self, mixing: Mixing) {
+        self.mixing = mixing;
+    }
+
     pub fn reset(&mut self) {
         self.cycles = 0;
         self.pulse_1 = Pulse::new(SweepNegationType::OnesComplement);
@@ -164,8 +306,8 @@ impl Apu {
             }
         }
 
-        if self.cycles > self.last_sampled_cycles + CPU_CYCLES_PER_SAMPLE {
-            self.last_sampled_cycles += CPU_CYCLES_PER_SAMPLE;
+        if self.cycles > self.last_sampled_cycles + self.cpu_cycles_per_sample.get() {
+            self.last_sampled_cycles += self.cpu_cycles_per_sample.get();
             let mut sample = self.generate_sample();
             if self.settings.filter_enabled {
                 sample = self.filter.step(sample);
@@ -201,10 +343,16 @@ impl Apu {
             0
         };
 
-        let pulse_out = PULSE_TABLE[pulse_1 as usize + pulse_2 as usize];
-        let tnd_out = TND_TABLE[3 * triangle as usize + 2 * noise as usize + dmc as usize];
+        if let Some(ref mut buffers) = self.channel_buffers {
+            let triangle = if self.triangle.is_silenced() {
+                0
+            } else {
+                triangle
+            };
+            buffers.push(pulse_1, pulse_2, triangle, noise, dmc);
+        }
 
-        pulse_out + tnd_out
+        mix(self.mixing, pulse_1, pulse_2, triangle, noise, dmc)
     }
 
     fn step_frame_counter(&mut self, cpu: &mut Cpu) {
@@ -292,6 +440,15 @@ impl Apu {
     }
 
     fn read_status(&mut self) -> u8 {
+        let status = self.peek_status();
+
+        self.frame_counter.interrupt_flag = false;
+
+        status
+    }
+
+    // The value a $4015 read would return, without acknowledging the frame interrupt
+    pub fn peek_status(&self) -> u8 {
         let mut status = 0x00;
 
         if self.pulse_1.length_counter.count > 0 {
@@ -318,13 +475,34 @@ impl Apu {
             status |= 0x40;
         }
 
-        if self.dmc.irq_flag {
+        if self.dmc.interrupt_flag {
             status |= 0x80;
         }
 
+        status
+    }
+
+    // Whether the frame counter is asserting the IRQ line
+    pub fn frame_interrupt_flag(&self) -> bool {
+        self.frame_counter.interrupt_flag
+    }
+
+    // Whether the DMC is asserting the IRQ line
+    pub fn dmc_irq_flag(&self) -> bool {
+        self.dmc.interrupt_flag
+    }
+
+    // Whether the DMC has fetched a sample byte since the last call
+    pub fn take_dmc_dma(&mut self) -> bool {
+        mem::take(&mut self.dmc.dma_occurred)
+    }
+
+    pub fn clear_frame_interrupt_flag(&mut self) {
         self.frame_counter.interrupt_flag = false;
+    }
 
-        status
+    pub fn clear_dmc_irq_flag(&mut self) {
+        self.dmc.interrupt_flag = false;
     }
 
     fn write_status(&mut self, value: u8) {
@@ -348,6 +526,8 @@ impl Apu {
             self.noise.length_counter.reset();
         }
 
+        // Writing to the status register acknowledges the DMC interrupt
+        self.dmc.interrupt_flag = false;
         self.dmc.enable_flag = (value & 0x10) != 0;
         if !self.dmc.enable_flag {
             self.dmc.current_length = 0;
@@ -389,6 +569,17 @@ impl Memory for Apu {
     }
 
     fn write_byte(&mut self, address: u16, value: u8) {
+        // 0x4014 is OAM DMA, which isn't part of the APU
+        if let Some(ref mut log) = self.register_log {
+            if address != 0x4014 {
+                log.push(RegisterWrite {
+                    cycle: self.cycles,
+                    address,
+                    value,
+                });
+            }
+        }
+
         match address {
             0x4000 => self.pulse_1.write_control(value),
             0x4001 => self.pulse_1.write_sweep(value),
@@ -729,6 +920,11 @@ impl Triangle {
     fn output(&self) -> u8 {
         TRIANGLE_TABLE[self.duty_cycle as usize]
     }
+
+    // The sequencer is stopped, so the output is a constant level
+    fn is_silenced(&self) -> bool {
+        self.length_counter.count == 0 || self.linear_counter.count == 0
+    }
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -806,7 +1002,11 @@ impl Noise {
 pub struct Dmc {
     enable_flag: bool,
     loop_flag: bool,
-    irq_flag: bool,
+    #[serde(rename = "irq_flag")]
+    irq_enabled: bool,
+    // Set at the end of a sample when IRQs are enabled, and asserts the IRQ line until cleared
+    #[serde(default)]
+    interrupt_flag: bool,
     value: u8,
     sample_address: u16,
     sample_length: u16,
@@ -816,6 +1016,9 @@ pub struct Dmc {
     bit_count: u8,
     tick_period: u8,
     tick_value: u8,
+    // Set when a sample byte is fetched, until the interconnect checks for it
+    #[serde(skip)]
+    dma_occurred: bool,
 }
 
 impl Dmc {
@@ -823,7 +1026,8 @@ impl Dmc {
         Dmc {
             enable_flag: false,
             loop_flag: false,
-            irq_flag: false,
+            irq_enabled: false,
+            interrupt_flag: false,
             value: 0,
             sample_address: 0,
             sample_length: 0,
@@ -833,11 +1037,15 @@ impl Dmc {
             bit_count: 0,
             tick_period: 0,
             tick_value: 0,
+            dma_occurred: false,
         }
     }
 
     fn write_control(&mut self, value: u8) {
-        self.irq_flag = value & 0x80 != 0;
+        self.irq_enabled = value & 0x80 != 0;
+        if !self.irq_enabled {
+            self.interrupt_flag = false;
+        }
         self.loop_flag = value & 0x40 != 0;
         self.tick_period = DMC_TABLE[(value & 0x0F) as usize];
     }
@@ -860,10 +1068,11 @@ impl Dmc {
     }
 
     fn step_timer(&mut self, cpu: &mut Cpu, mapper: Rc<RefCell<MapperEnum>>) {
+        if self.interrupt_flag {
+            cpu.request_interrupt(Interrupt::Irq);
+        }
+
         if self.enable_flag {
-            if self.irq_flag {
-                cpu.request_interrupt(Interrupt::Irq);
-            }
             self.step_reader(cpu, mapper);
             if self.tick_value == 0 {
                 self.tick_value = self.tick_period;
@@ -877,6 +1086,7 @@ impl Dmc {
     fn step_reader(&mut self, cpu: &mut Cpu, mapper: Rc<RefCell<MapperEnum>>) {
         if self.current_length > 0 && self.bit_count == 0 {
             cpu.stall(4);
+            self.dma_occurred = true;
             let mut mapper = mapper.borrow_mut();
             self.shift_register = mapper.prg_read_byte(self.current_address);
             self.bit_count = 8;
@@ -885,8 +1095,12 @@ impl Dmc {
                 self.current_address = 0x8000;
             }
             self.current_length -= 1;
-            if self.current_length == 0 && self.loop_flag {
-                self.restart();
+            if self.current_length == 0 {
+                if self.loop_flag {
+                    self.restart();
+                } else if self.irq_enabled {
+                    self.interrupt_flag = true;
+                }
             }
         }
     }
@@ -969,6 +1183,39 @@ where
     }
 }
 
+// The rate of the samples the APU generates in `region` with one every `cpu_cycles_per_sample` CPU
+// cycles, which the audio sink must resample from
+pub fn sample_rate(region: Region, cpu_cycles_per_sample: NonZeroU64) -> u32 {
+    (region.cpu_frequency() / cpu_cycles_per_sample.get()) as u32
+}
+
+// Coefficients of the low-pass, first high-pass and second high-pass filters. They were tuned for
+// the NTSC clock at the default sample rate and are applied once per sample, so keeping the same
+// cutoffs with another sample period, such as PAL's slower clock or a different internal rate,
+// means scaling them by the ratio of the two periods. The high-pass coefficients are how much of
+// the output carries over each sample, so they're raised to the ratio; the low-pass coefficient
+// is how far the output moves towards the input, so it's the remainder that carries over that's
+// raised.
+fn filter_coefficients(region: Region, cpu_cycles_per_sample: u64) -> [f32; 3] {
+    let [low_pass, high_pass_1, high_pass_2] = [0.815_686f32, 0.996_039f32, 0.999_835f32];
+    let period_ratio = (cpu_cycles_per_sample * CPU_FREQUENCY) as f32
+        / (CPU_CYCLES_PER_SAMPLE * region.cpu_frequency()) as f32;
+    [
+        1.0 - (1.0 - low_pass).powf(period_ratio),
+        high_pass_1.powf(period_ratio),
+        high_pass_2.powf(period_ratio),
+    ]
+}
+
+fn filter_chain(region: Region, cpu_cycles_per_sample: u64) -> Box<dyn Filter> {
+    let [low_pass, high_pass_1, high_pass_2] = filter_coefficients(region, cpu_cycles_per_sample);
+    Box::new(
+        LowPassFilter::new(low_pass)
+            .chain(HighPassFilter::new(high_pass_1))
+            .chain(HighPassFilter::new(high_pass_2)),
+    )
+}
+
 struct LowPassFilter {
     last_out: f32,
     k: f32,
@@ -1012,3 +1259,228 @@ impl Filter for HighPassFilter {
         self.last_out
     }
 }
+
+#[cfg(test)]
+pub(crate) struct CountingAudioSink(pub usize);
+
+#[cfg(test)]
+impl AudioSink for CountingAudioSink {
+    fn write_sample(&mut self, _sample: f32) {
+        self.0 += 1;
+    }
+
+    fn samples_written(&self) -> usize {
+        self.0
+    }
+}
+
+#[test]
+fn test_internal_rate() {
+    use crate::cartridge::{test_rom, Cartridge};
+
+    const CPU_CYCLES_PER_FRAME: usize = 29781;
+
+    let mapper = MapperEnum::from_cartridge(Cartridge::from_bytes(&test_rom()).unwrap());
+    let mut apu = Apu::new(Rc::new(RefCell::new(mapper)));
+    let mut cpu = Cpu::new();
+
+    let mut default_sink = CountingAudioSink(0);
+    for _ in 0..CPU_CYCLES_PER_FRAME {
+        apu.step(&mut cpu, &mut default_sink);
+    }
+    assert_eq!(apu.sample_rate(), SAMPLE_RATE);
+    assert_eq!(
+        default_sink.0,
+        CPU_CYCLES_PER_FRAME / CPU_CYCLES_PER_SAMPLE as usize
+    );
+
+    apu.set_internal_rate(NonZeroU64::new(1).unwrap());
+    let mut per_cycle_sink = CountingAudioSink(0);
+    for _ in 0..CPU_CYCLES_PER_FRAME {
+        apu.step(&mut cpu, &mut per_cycle_sink);
+    }
+    assert_eq!(apu.sample_rate(), CPU_FREQUENCY as u32);
+    assert!(per_cycle_sink.0 >= CPU_CYCLES_PER_FRAME - 1);
+}
+
+#[test]
+fn test_channel_buffers() {
+    use crate::cartridge::{test_rom, Cartridge};
+
+    const CPU_CYCLES_PER_FRAME: usize = 29781;
+
+    let mapper = MapperEnum::from_cartridge(Cartridge::from_bytes(&test_rom()).unwrap());
+    let mut apu = Apu::new(Rc::new(RefCell::new(mapper)));
+    let mut cpu = Cpu::new();
+    let mut sink = CountingAudioSink(0);
+    assert!(apu.take_channel_buffers().is_none());
+
+    apu.enable_channel_buffers();
+
+    // Pulse 1 only, at constant volume 15 with a 50% duty cycle
+    apu.write_byte(0x4015, 0x01);
+    apu.write_byte(0x4000, 0xBF);
+    apu.write_byte(0x4002, 0xFD);
+    apu.write_byte(0x4003, 0x00);
+
+    for _ in 0..CPU_CYCLES_PER_FRAME {
+        apu.step(&mut cpu, &mut sink);
+    }
+
+    let buffers = apu.take_channel_buffers().unwrap();
+    assert_eq!(buffers.pulse_1.len(), sink.0);
+    assert!(buffers.pulse_1.contains(&15));
+    assert!(buffers.pulse_1.contains(&0));
+    assert!(buffers.pulse_2.iter().all(|&level| level == 0));
+    assert!(buffers.triangle.iter().all(|&level| level == 0));
+    assert!(buffers.noise.iter().all(|&level| level == 0));
+    assert!(buffers.dmc.iter().all(|&level| level == 0));
+
+    assert!(apu.take_channel_buffers().unwrap().pulse_1.is_empty());
+}
+
+#[test]
+fn test_dmc_irq() {
+    use crate::cartridge::{test_rom, Cartridge};
+
+    let mapper = MapperEnum::from_cartridge(Cartridge::from_bytes(&test_rom()).unwrap());
+    let mut apu = Apu::new(Rc::new(RefCell::new(mapper)));
+    let mut cpu = Cpu::new();
+    let mut sink = CountingAudioSink(0);
+
+    // 17 byte sample at 0xC000, with IRQ enabled and looping disabled
+    apu.write_byte(0x4010, 0x8F);
+    apu.write_byte(0x4012, 0x00);
+    apu.write_byte(0x4013, 0x01);
+    apu.write_byte(0x4015, 0x10);
+
+    let mut irqs = 0;
+    let mut irq_cycle = 0;
+    for cycle in 0..10_000 {
+        let was_set = apu.dmc_irq_flag();
+        apu.step(&mut cpu, &mut sink);
+        if apu.dmc_irq_flag() && !was_set {
+            irqs += 1;
+            irq_cycle = cycle;
+            // The last byte has just been read
+            assert_eq!(apu.dmc.current_length, 0);
+        }
+    }
+    assert_eq!(irqs, 1);
+    assert!(irq_cycle > 0);
+    assert_eq!(cpu.interrupt(), Some(Interrupt::Irq));
+    assert_eq!(apu.read_byte(0x4015) & 0x80, 0x80);
+
+    apu.write_byte(0x4015, 0x00);
+    assert!(!apu.dmc_irq_flag());
+
+    // With looping, the sample restarts instead
+    apu.write_byte(0x4010, 0xCF);
+    apu.write_byte(0x4015, 0x10);
+    for _ in 0..10_000 {
+        apu.step(&mut cpu, &mut sink);
+        assert!(!apu.dmc_irq_flag());
+    }
+    assert!(apu.dmc.current_length > 0);
+}
+
+#[test]
+fn test_register_log() {
+    use crate::cartridge::{test_rom, Cartridge};
+
+    let mapper = MapperEnum::from_cartridge(Cartridge::from_bytes(&test_rom()).unwrap());
+    let mut apu = Apu::new(Rc::new(RefCell::new(mapper)));
+    let mut cpu = Cpu::new();
+    let mut sink = CountingAudioSink(0);
+
+    apu.write_byte(0x4015, 0x01);
+    apu.enable_register_log();
+
+    // Two notes on pulse 1, 1000 cycles apart
+    let notes = [(0xFDu8, 0x00u8), (0xAB, 0x01)];
+    for &(timer_lo, timer_hi) in &notes {
+        apu.write_byte(0x4000, 0xBF);
+        apu.write_byte(0x4002, timer_lo);
+        apu.write_byte(0x4003, timer_hi);
+        // Not an APU register
+        apu.write_byte(0x4014, 0x02);
+        for _ in 0..1000 {
+            apu.step(&mut cpu, &mut sink);
+        }
+    }
+
+    let log = apu.take_register_log().unwrap();
+    let triples: Vec<(u64, u16, u8)> = log
+        .iter()
+        .map(|write| (write.cycle, write.address, write.value))
+        .collect();
+    assert_eq!(
+        triples,
+        vec![
+            (0, 0x4000, 0xBF),
+            (0, 0x4002, 0xFD),
+            (0, 0x4003, 0x00),
+            (1000, 0x4000, 0xBF),
+            (1000, 0x4002, 0xAB),
+            (1000, 0x4003, 0x01),
+        ]
+    );
+
+    let mut text = Vec::new();
+    write_register_log(&log[3..4], &mut text).unwrap();
+    assert_eq!(String::from_utf8(text).unwrap(), "1000 $4000 $BF\n");
+
+    assert!(apu.take_register_log().unwrap().is_empty());
+}
+
+#[test]
+fn test_region_filter_coefficients() {
+    let ntsc = filter_coefficients(Region::Ntsc, CPU_CYCLES_PER_SAMPLE);
+    let pal = filter_coefficients(Region::Pal, CPU_CYCLES_PER_SAMPLE);
+
+    assert_eq!(ntsc, [0.815_686, 0.996_039, 0.999_835]);
+
+    // A longer sample period means the low-pass output moves further towards the input each step
+    assert!(pal[0] > ntsc[0]);
+    // and the high-pass outputs decay further
+    assert!(pal[1] < ntsc[1]);
+    assert!(pal[2] < ntsc[2]);
+}
+
+#[test]
+fn test_internal_rate_filter_coefficients() {
+    let default = filter_coefficients(Region::Ntsc, CPU_CYCLES_PER_SAMPLE);
+    let per_cycle = filter_coefficients(Region::Ntsc, 1);
+
+    // With a shorter sample period each step has to do less to keep the same cutoffs
+    assert!(per_cycle[0] < default[0]);
+    assert!(per_cycle[1] > default[1]);
+    assert!(per_cycle[2] > default[2]);
+
+    // Stepping the per-cycle filters once per cycle decays as much as the default ones do once
+    // per sample
+    let decay = per_cycle[1].powi(CPU_CYCLES_PER_SAMPLE as i32);
+    assert!((decay - default[1]).abs() < 1e-4);
+}
+
+#[test]
+fn test_mixing() {
+    // Both pulses at full volume
+    let reference = 95.52 / (8128.0 / 30.0 + 100.0);
+    let nonlinear = mix(Mixing::Nonlinear, 15, 15, 0, 0, 0);
+    assert!((nonlinear - reference).abs() < 1e-6);
+
+    // The linear mix is a straight weighted sum, so two pulses are twice as loud as one, while
+    // the nonlinear DAC compresses them
+    let linear = mix(Mixing::Linear, 15, 15, 0, 0, 0);
+    assert!((linear - 0.00752 * 30.0).abs() < 1e-6);
+    assert!(linear < nonlinear);
+    assert_eq!(
+        mix(Mixing::Linear, 15, 0, 0, 0, 0) * 2.0,
+        mix(Mixing::Linear, 15, 15, 0, 0, 0)
+    );
+    assert!(mix(Mixing::Nonlinear, 15, 0, 0, 0, 0) * 2.0 > nonlinear);
+
+    assert_eq!(mix(Mixing::Nonlinear, 0, 0, 0, 0, 0), 0.0);
+    assert_eq!(mix(Mixing::Linear, 0, 0, 0, 0, 0), 0.0);
+}
@@ -6,15 +6,28 @@ use std::cmp::max;
 use std::fmt;
 use std::fmt::{Debug, Formatter};
 use std::io;
-use std::io::Read;
+use std::io::{Cursor, Read};
 
 // ROM must begin with this constant ("NES" followed by MS-DOS end-of-file)
 const MAGIC_CONSTANT: u32 = 0x4e45_531a;
 
+// Famicom Disk System images, either with an fwNES header ("FDS" followed by MS-DOS end-of-file)
+// or a raw disk image, which starts with the disk info block code and "*NI" of "*NINTENDO-HVC*"
+const FDS_MAGIC_CONSTANT: u32 = 0x4644_531a;
+const FDS_RAW_MAGIC_CONSTANT: u32 = 0x012a_4e49;
+
 pub const PRG_ROM_BANK_SIZE: u16 = 16 * 1024;
 pub const CHR_ROM_BANK_SIZE: u16 = 8 * 1024;
 pub const PRG_RAM_BANK_SIZE: u16 = 8 * 1024;
 
+// CRC32s (of PRG and CHR ROM) of games on discrete-logic boards that rely on bus conflicts, but
+// whose headers don't say so with an NES 2.0 submapper. Only add a checksum verified against a
+// known good dump, since a wrong entry breaks that game's bank switching.
+static BUS_CONFLICT_CRC32S: &[u32] = &[];
+
+// Mappers whose NES 2.0 submappers say whether there are bus conflicts
+static DISCRETE_LOGIC_MAPPERS: &[u16] = &[2, 3, 7];
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum Mirroring {
     Horizontal,
@@ -44,12 +57,40 @@ impl Mirroring {
     }
 }
 
+// Sound chips on the cartridge that the Famicom mixes into its audio output. The NES 2.0 header
+// has no field for them, so they follow from the mapper.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExpansionAudio {
+    Fds,
+    Mmc5,
+    Namco163,
+    Sunsoft5B,
+    Vrc6,
+    Vrc7,
+}
+
+impl fmt::Display for ExpansionAudio {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name = match *self {
+            ExpansionAudio::Fds => "FDS",
+            ExpansionAudio::Mmc5 => "MMC5",
+            ExpansionAudio::Namco163 => "Namco 163",
+            ExpansionAudio::Sunsoft5B => "Sunsoft 5B",
+            ExpansionAudio::Vrc6 => "VRC6",
+            ExpansionAudio::Vrc7 => "VRC7",
+        };
+        f.write_str(name)
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum LoadError {
     #[error("{0}")]
     FormatError(String),
     #[error("{0}")]
     IoError(#[from] io::Error),
+    #[error("{0} images are not supported, only iNES ROMs can be loaded")]
+    UnsupportedFormat(String),
 }
 
 pub struct Cartridge {
@@ -61,8 +102,21 @@ pub struct Cartridge {
     pub prg_rom: Vec<u8>,
     pub chr_num_banks: u8,
     pub chr: Vec<u8>,
+    // Whether `chr` is RAM, allocated because the image has no CHR ROM
+    pub has_chr_ram: bool,
     pub prg_ram: Vec<u8>,
+    // Whether the board has RAM at 0x6000-0x7FFF. `prg_ram` is allocated regardless, for mappers
+    // that always map it.
+    pub has_prg_ram: bool,
+    // NES 2.0 miscellaneous ROM areas, such as PCM sample data
+    pub misc_rom: Vec<u8>,
     pub is_battery_backed: bool,
+    // CRC32 of the PRG and CHR ROM, excluding the header
+    pub crc32: u32,
+    // CRC32 of the iNES header followed by the PRG and CHR ROM
+    pub crc32_with_header: u32,
+    // Whether writes to ROM are ANDed with the ROM contents, for mappers that emulate it
+    pub bus_conflicts: bool,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -81,8 +135,19 @@ impl Debug for Cartridge {
         writeln!(f, "mirroring: {:?}", self.mirroring)?;
         writeln!(f, "PRG ROM size: {}", self.prg_rom.len())?;
         writeln!(f, "CHR ROM size: {}", self.chr.len())?;
-        writeln!(f, "PRG RAM size: {}", self.prg_ram.len())?;
-        writeln!(f, "battery backed: {}", self.is_battery_backed)
+        if self.has_prg_ram {
+            writeln!(f, "PRG RAM size: {}", self.prg_ram.len())?;
+        } else {
+            writeln!(f, "PRG RAM size: none")?;
+        }
+        writeln!(f, "misc ROM size: {}", self.misc_rom.len())?;
+        writeln!(f, "battery backed: {}", self.is_battery_backed)?;
+        if let Some(expansion_audio) = self.expansion_audio() {
+            writeln!(f, "expansion audio: {}", expansion_audio)?;
+        }
+        writeln!(f, "CRC32: {:08X}", self.rom_hash(false))?;
+        writeln!(f, "CRC32 (with header): {:08X}", self.rom_hash(true))?;
+        writeln!(f, "bus conflicts: {}", self.bus_conflicts)
     }
 }
 
@@ -90,6 +155,10 @@ impl Cartridge {
     pub fn load<R: Read>(r: &mut R) -> Result<Cartridge, LoadError> {
         let magic = r.read_u32::<BigEndian>()?;
 
+        if magic == FDS_MAGIC_CONSTANT || magic == FDS_RAW_MAGIC_CONSTANT {
+            return Err(LoadError::UnsupportedFormat("FDS".into()));
+        }
+
         if magic != MAGIC_CONSTANT {
             return Err(LoadError::FormatError(
                 "magic constant in header is incorrect".into(),
@@ -105,13 +174,10 @@ impl Cartridge {
         let flags6 = r.read_u8()?;
         let flags7 = r.read_u8()?;
 
-        let prg_ram_size = max(1, r.read_u8()?) as usize * PRG_RAM_BANK_SIZE as usize;
+        let mut header = [0u8; 8];
+        r.read_exact(&mut header)?;
 
-        // Skip the rest of the header
-        // TODO: Implement NEW 2.0
-        for _ in 0..7 {
-            r.read_u8()?;
-        }
+        let is_nes2 = (flags7 & 0x0C) == 0x08;
 
         let is_battery_backed = (flags6 & 0x02) != 0;
 
@@ -123,8 +189,42 @@ impl Cartridge {
             }
         }
 
-        let mapper = ((flags7 & 0xf0) | (flags6 >> 4)) as u16;
-        let sub_mapper = 0u8;
+        let mut mapper = ((flags7 & 0xf0) | (flags6 >> 4)) as u16;
+        let mut sub_mapper = 0u8;
+
+        let prg_ram_size;
+        // iNES 1.0 can't say there's no PRG RAM, and many games rely on it being there
+        let mut has_prg_ram = true;
+        let mut chr_ram_size = CHR_ROM_BANK_SIZE as usize;
+        let mut num_misc_roms = 0;
+        if is_nes2 {
+            // https://www.nesdev.org/wiki/NES_2.0
+            mapper |= ((header[0] & 0x0F) as u16) << 8;
+            sub_mapper = header[0] >> 4;
+
+            if header[1] != 0 {
+                return Err(LoadError::FormatError(
+                    "NES 2.0 ROM sizes of 256 banks or more are not supported".into(),
+                ));
+            }
+
+            // Volatile and battery-backed RAM share the same buffer. Mappers expect a full 8KB
+            // window at 0x6000, so that's the minimum.
+            has_prg_ram = header[2] != 0;
+            prg_ram_size = max(
+                nes2_ram_size(header[2] & 0x0F) + nes2_ram_size(header[2] >> 4),
+                PRG_RAM_BANK_SIZE as usize,
+            );
+            if chr_num_banks == 0 {
+                chr_ram_size = nes2_ram_size(header[3] & 0x0F) + nes2_ram_size(header[3] >> 4);
+                if chr_ram_size == 0 {
+                    chr_ram_size = CHR_ROM_BANK_SIZE as usize;
+                }
+            }
+            num_misc_roms = header[6] & 0x03;
+        } else {
+            prg_ram_size = max(1, header[0]) as usize * PRG_RAM_BANK_SIZE as usize;
+        }
 
         let mirroring = if (flags6 & 0x08) != 0 {
             Mirroring::FourScreen
@@ -140,10 +240,30 @@ impl Cartridge {
         let mut chr = vec![0u8; chr_rom_size];
         r.read_exact(&mut chr[..])?;
 
-        // Add CHR bank if not in file
-        if chr_num_banks == 0 {
-            chr_num_banks = 1;
-            chr = vec![0u8; CHR_ROM_BANK_SIZE as usize];
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&prg_rom);
+        hasher.update(&chr);
+        let crc32 = hasher.finalize();
+
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&magic.to_be_bytes());
+        hasher.update(&[prg_rom_num_banks, chr_num_banks, flags6, flags7]);
+        hasher.update(&header);
+        hasher.update(&prg_rom);
+        hasher.update(&chr);
+        let crc32_with_header = hasher.finalize();
+
+        // Miscellaneous ROM areas follow CHR ROM, and take up the rest of the file
+        let mut misc_rom = Vec::new();
+        if num_misc_roms > 0 {
+            r.read_to_end(&mut misc_rom)?;
+        }
+
+        // Add CHR RAM if there's no CHR ROM in the file
+        let has_chr_ram = chr_num_banks == 0;
+        if has_chr_ram {
+            chr_num_banks = max(1, chr_ram_size / CHR_ROM_BANK_SIZE as usize) as u8;
+            chr = vec![0u8; chr_ram_size];
         }
 
         let prg_ram = vec![0u8; prg_ram_size];
@@ -157,11 +277,61 @@ impl Cartridge {
             prg_rom,
             chr_num_banks,
             chr,
+            has_chr_ram,
             prg_ram,
+            has_prg_ram,
+            misc_rom,
             is_battery_backed,
+            crc32,
+            crc32_with_header,
+            bus_conflicts: match sub_mapper {
+                // Discrete-logic mappers use submapper 1 for no bus conflicts and 2 for bus
+                // conflicts
+                1 if is_nes2 && DISCRETE_LOGIC_MAPPERS.contains(&mapper) => false,
+                2 if is_nes2 && DISCRETE_LOGIC_MAPPERS.contains(&mapper) => true,
+                _ => {
+                    DISCRETE_LOGIC_MAPPERS.contains(&mapper) && BUS_CONFLICT_CRC32S.contains(&crc32)
+                }
+            },
         })
     }
 
+    pub fn from_bytes(bytes: &[u8]) -> Result<Cartridge, LoadError> {
+        Cartridge::load(&mut Cursor::new(bytes))
+    }
+
+    // The RAM at 0x6000-0x7FFF, if the board has any
+    pub fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        if self.has_prg_ram {
+            Some(&mut self.prg_ram)
+        } else {
+            None
+        }
+    }
+
+    // The sound chip the board adds, none of which are emulated yet
+    pub fn expansion_audio(&self) -> Option<ExpansionAudio> {
+        match self.mapper {
+            5 => Some(ExpansionAudio::Mmc5),
+            19 => Some(ExpansionAudio::Namco163),
+            20 => Some(ExpansionAudio::Fds),
+            24 | 26 => Some(ExpansionAudio::Vrc6),
+            69 => Some(ExpansionAudio::Sunsoft5B),
+            85 => Some(ExpansionAudio::Vrc7),
+            _ => None,
+        }
+    }
+
+    // Databases differ in whether they hash the 16 byte header: GoodNES uses the PRG and CHR ROM
+    // only
+    pub fn rom_hash(&self, include_header: bool) -> u32 {
+        if include_header {
+            self.crc32_with_header
+        } else {
+            self.crc32
+        }
+    }
+
     pub fn get_state(&self) -> State {
         State {
             mirroring: self.mirroring,
@@ -176,3 +346,143 @@ impl Cartridge {
         self.prg_ram = state.prg_ram.clone();
     }
 }
+
+// RAM size from a NES 2.0 shift count, which is 64 << count, or none when the count is 0
+fn nes2_ram_size(shift_count: u8) -> usize {
+    if shift_count == 0 {
+        0
+    } else {
+        64 << shift_count
+    }
+}
+
+// iNES image for `mapper` with horizontal mirroring and zeroed PRG and CHR ROM
+#[cfg(any(test, feature = "test-roms"))]
+pub fn ines_test_rom(mapper: u8, prg_rom_num_banks: u8, chr_num_banks: u8) -> Vec<u8> {
+    let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, prg_rom_num_banks, chr_num_banks];
+    rom.extend_from_slice(&[(mapper & 0x0F) << 4, mapper & 0xF0]);
+    rom.extend_from_slice(&[0u8; 8]);
+    rom.resize(
+        rom.len()
+            + prg_rom_num_banks as usize * PRG_ROM_BANK_SIZE as usize
+            + chr_num_banks as usize * CHR_ROM_BANK_SIZE as usize,
+        0,
+    );
+    rom
+}
+
+#[cfg(test)]
+pub(crate) fn test_rom() -> Vec<u8> {
+    let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x01, 0x11, 0x00];
+    rom.extend_from_slice(&[0u8; 8]);
+    rom.extend((0..PRG_ROM_BANK_SIZE as usize).map(|i| i as u8));
+    rom.extend((0..CHR_ROM_BANK_SIZE as usize).map(|i| (i >> 8) as u8));
+    rom
+}
+
+// NROM-128 image with the given program at $8000, padded with NOPs and all vectors pointing to
+// the start of the program
+#[cfg(any(test, feature = "test-roms"))]
+pub fn nrom_test_rom(program: &[u8]) -> Vec<u8> {
+    let mut rom = ines_test_rom(0, 1, 1);
+    let prg_rom = &mut rom[16..16 + PRG_ROM_BANK_SIZE as usize];
+    prg_rom.fill(0xEA);
+    prg_rom[..program.len()].copy_from_slice(program);
+    prg_rom[0x3FFA..].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x00, 0x80]);
+    rom
+}
+
+#[test]
+fn test_from_bytes_matches_reader() {
+    let rom = test_rom();
+
+    let from_bytes = Cartridge::from_bytes(&rom).unwrap();
+    let from_reader = Cartridge::load(&mut &rom[..]).unwrap();
+
+    assert_eq!(from_bytes.mapper, 1);
+    assert_eq!(from_bytes.mirroring, Mirroring::Vertical);
+    assert_eq!(from_bytes.mapper, from_reader.mapper);
+    assert_eq!(from_bytes.mirroring, from_reader.mirroring);
+    assert_eq!(from_bytes.prg_rom, from_reader.prg_rom);
+    assert_eq!(from_bytes.chr, from_reader.chr);
+    assert_eq!(from_bytes.prg_ram.len(), from_reader.prg_ram.len());
+}
+
+#[test]
+fn test_from_bytes_truncated() {
+    let rom = test_rom();
+
+    assert!(Cartridge::from_bytes(&rom[..rom.len() - 1]).is_err());
+    assert!(Cartridge::from_bytes(&rom[1..]).is_err());
+}
+
+#[test]
+fn test_fds_rejected() {
+    let mut fds = b"FDS\x1a\x01".to_vec();
+    fds.extend_from_slice(&[0u8; 11]);
+    fds.extend_from_slice(b"\x01*NINTENDO-HVC*");
+
+    for image in [&fds[..], &fds[16..]] {
+        match Cartridge::from_bytes(image) {
+            Err(LoadError::UnsupportedFormat(format)) => assert_eq!(format, "FDS"),
+            _ => panic!("FDS image wasn't rejected as an unsupported format"),
+        }
+    }
+}
+
+#[test]
+fn test_nes2_ram_sizes() {
+    // NES 2.0 header with mapper 2, no CHR ROM, 8KB battery-backed PRG RAM and 16KB CHR RAM
+    let mut rom = ines_test_rom(2, 1, 0);
+    rom[6] |= 0x02;
+    rom[7] |= 0x08;
+    rom[8..12].copy_from_slice(&[0x20, 0x00, 0x70, 0x08]);
+
+    let cartridge = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(cartridge.mapper, 2);
+    assert_eq!(cartridge.sub_mapper, 2);
+    assert!(cartridge.bus_conflicts);
+    assert_eq!(cartridge.chr.len(), 16 * 1024);
+    assert_eq!(cartridge.chr_num_banks, 2);
+    assert_eq!(cartridge.prg_ram.len(), 8 * 1024);
+    assert!(cartridge.misc_rom.is_empty());
+
+    // 64KB of PRG RAM, split between volatile and battery-backed RAM, and a misc ROM
+    rom[10] = 0x99;
+    rom[14] = 0x01;
+    rom.extend_from_slice(&[1, 2, 3]);
+    let cartridge = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(cartridge.prg_ram.len(), 64 * 1024);
+    assert_eq!(cartridge.misc_rom, [1, 2, 3]);
+}
+
+#[test]
+fn test_expansion_audio() {
+    // NES 2.0 header with mapper 24, the VRC6a
+    let mut rom = ines_test_rom(24, 1, 1);
+    rom[7] |= 0x08;
+
+    let cartridge = Cartridge::from_bytes(&rom).unwrap();
+    assert_eq!(cartridge.mapper, 24);
+    assert_eq!(cartridge.expansion_audio(), Some(ExpansionAudio::Vrc6));
+
+    let cartridge = Cartridge::from_bytes(&nrom_test_rom(&[])).unwrap();
+    assert_eq!(cartridge.expansion_audio(), None);
+}
+
+#[test]
+fn test_rom_hash() {
+    let rom = test_rom();
+    let cartridge = Cartridge::from_bytes(&rom).unwrap();
+
+    assert_ne!(cartridge.rom_hash(false), cartridge.rom_hash(true));
+    let prg_chr_len = cartridge.prg_rom.len() + cartridge.chr.len();
+    assert_eq!(
+        cartridge.rom_hash(false),
+        crc32fast::hash(&rom[16..16 + prg_chr_len])
+    );
+    assert_eq!(
+        cartridge.rom_hash(true),
+        crc32fast::hash(&rom[..16 + prg_chr_len])
+    );
+}
@@ -0,0 +1,81 @@
+use crate::memory::Fetch;
+
+use std::io::{self, Write};
+
+// Code/data log bits marking a byte as executed or read as data, in the FCEUX .cdl format
+const CDL_CODE: u8 = 0x01;
+const CDL_DATA: u8 = 0x02;
+
+// Records which PRG ROM bytes the CPU has fetched as opcodes or operands, to tell code apart
+// from data
+pub struct Coverage {
+    prg_rom: Vec<Option<Fetch>>,
+}
+
+impl Coverage {
+    pub fn new(prg_rom_len: usize) -> Self {
+        Coverage {
+            prg_rom: vec![None; prg_rom_len],
+        }
+    }
+
+    // Bytes executed as an opcode stay marked as such, even if they're later read as an operand
+    pub fn record(&mut self, prg_rom_offset: usize, fetch: Fetch) {
+        if let Some(usage) = self.prg_rom.get_mut(prg_rom_offset) {
+            if *usage != Some(Fetch::Opcode) {
+                *usage = Some(fetch);
+            }
+        }
+    }
+
+    // How the byte at `prg_rom_offset` was fetched, or `None` if it hasn't been
+    pub fn get(&self, prg_rom_offset: usize) -> Option<Fetch> {
+        self.prg_rom.get(prg_rom_offset).copied().flatten()
+    }
+
+    // Writes the PRG ROM section of an FCEUX code/data log, with opcodes and operands both
+    // marked as code
+    pub fn write_cdl<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let bytes: Vec<u8> = self
+            .prg_rom
+            .iter()
+            .map(|usage| if usage.is_some() { CDL_CODE } else { 0 })
+            .collect();
+        w.write_all(&bytes)
+    }
+}
+
+// A code/data log loaded from an FCEUX .cdl file, to tell disassemblers which PRG ROM bytes are
+// data rather than instructions
+pub struct CodeDataLog {
+    prg_rom: Vec<u8>,
+}
+
+impl CodeDataLog {
+    // The PRG ROM section comes first in the file, followed by CHR ROM, which is ignored. A file
+    // shorter than the PRG ROM leaves the rest unmarked.
+    pub fn from_cdl(cdl: &[u8], prg_rom_len: usize) -> Self {
+        CodeDataLog {
+            prg_rom: cdl[..prg_rom_len.min(cdl.len())].to_vec(),
+        }
+    }
+
+    // Whether the byte at `prg_rom_offset` was only ever read as data
+    pub fn is_data(&self, prg_rom_offset: usize) -> bool {
+        self.prg_rom
+            .get(prg_rom_offset)
+            .is_some_and(|&flags| flags & (CDL_CODE | CDL_DATA) == CDL_DATA)
+    }
+}
+
+#[test]
+fn test_code_data_log() {
+    let log = CodeDataLog::from_cdl(&[0x01, 0x02, 0x03, 0x00, 0x22, 0x02], 5);
+    assert!(!log.is_data(0));
+    assert!(log.is_data(1));
+    assert!(!log.is_data(2));
+    assert!(!log.is_data(3));
+    assert!(log.is_data(4));
+    // Past the PRG ROM
+    assert!(!log.is_data(5));
+}
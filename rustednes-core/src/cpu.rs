@@ -1,4 +1,5 @@
-use crate::memory::Memory;
+use crate::memory::{Fetch, Memory};
+use crate::opcode_table::{opcode_table, OpKind};
 
 use serde_derive::{Deserialize, Serialize};
 
@@ -9,6 +10,59 @@ use std::fmt::{Debug, Formatter};
 pub const OAMDATA_ADDRESS: u16 = 0x2004;
 pub const OAMDMA_ADDRESS: u16 = 0x4014;
 pub const CPU_FREQUENCY: u64 = 1_789_773;
+pub const PAL_CPU_FREQUENCY: u64 = 1_662_607;
+pub const DENDY_CPU_FREQUENCY: u64 = 1_773_448;
+
+// The console's region, which decides the CPU and PPU clocks and the number of scanlines in a
+// frame
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Region {
+    #[default]
+    Ntsc, // 2A03
+    Pal, // 2A07
+    // Famiclones such as the Dendy divide the PAL master clock by 15 rather than 16, so the PPU
+    // runs 3 cycles per CPU cycle as on NTSC, but keep PAL's 312 scanlines per frame
+    Dendy, // UA6527P
+}
+
+impl Region {
+    pub fn cpu_frequency(self) -> u64 {
+        match self {
+            Region::Ntsc => CPU_FREQUENCY,
+            Region::Pal => PAL_CPU_FREQUENCY,
+            Region::Dendy => DENDY_CPU_FREQUENCY,
+        }
+    }
+
+    // Real time taken by one CPU cycle, rounded up to whole nanoseconds
+    pub fn cpu_cycle_time_ns(self) -> u64 {
+        (1e9_f64 / self.cpu_frequency() as f64) as u64 + 1
+    }
+
+    pub fn scanlines_per_frame(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    // The scanline whose second dot raises the vblank flag. The Dendy starts vblank 50 scanlines
+    // later than PAL, so that it comes as long after rendering as on NTSC.
+    pub fn vblank_scanline(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    // PPU cycles per CPU cycle, as (PPU cycles, CPU cycles): 3 to 1, or 3.2 to 1 on PAL
+    pub fn ppu_clock_ratio(self) -> (u32, u32) {
+        match self {
+            Region::Ntsc | Region::Dendy => (3, 1),
+            Region::Pal => (16, 5),
+        }
+    }
+}
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum Interrupt {
@@ -16,11 +70,42 @@ pub enum Interrupt {
     Irq,
 }
 
-const NMI_VECTOR: u16 = 0xFFFA;
-const IRQ_VECTOR: u16 = 0xFFFE;
-const RESET_VECTOR: u16 = 0xFFFC;
+pub const NMI_VECTOR: u16 = 0xFFFA;
+pub const IRQ_VECTOR: u16 = 0xFFFE;
+pub const RESET_VECTOR: u16 = 0xFFFC;
 const BRK_VECTOR: u16 = 0xFFFE;
 
+// What the CPU does with the unofficial opcodes it implements
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum IllegalMode {
+    #[default]
+    Execute,
+    // Trap as if they were unimplemented, to check that a program doesn't use them
+    Trap,
+}
+
+// Why the CPU stopped executing instructions
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Trap {
+    // An opcode the core doesn't implement, or an unofficial one in `IllegalMode::Trap`, at `pc`
+    IllegalOpcode { pc: u16, opcode: u8 },
+    // One of the JAM opcodes at `pc`, which halt a real 6502 until it's reset
+    Jam { pc: u16, opcode: u8 },
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Trap::IllegalOpcode { pc, opcode } => {
+                write!(f, "illegal opcode ${:02X} at ${:04X}", opcode, pc)
+            }
+            Trap::Jam { pc, opcode } => {
+                write!(f, "jammed by opcode ${:02X} at ${:04X}", opcode, pc)
+            }
+        }
+    }
+}
+
 #[derive(Copy, Clone, Default, Deserialize, Serialize)]
 pub struct Flags {
     c: bool, // Carry
@@ -122,16 +207,55 @@ fn mem_pages_same(m1: u16, m2: u16) -> bool {
     (m1 & 0xFF00) == (m2 & 0xFF00)
 }
 
+fn check_cycles(opcode: u8, pc: u16, taken: u32) {
+    let expected = opcode_table()[opcode as usize].cycle_range();
+    debug_assert!(
+        expected.contains(&taken),
+        "Opcode ${:02X} at ${:04X} took {} cycles, expected {:?}",
+        opcode,
+        pc,
+        taken,
+        expected
+    );
+}
+
 #[derive(Default)]
 pub struct Cpu {
     pub cycles: u64,
     stall_cycles: u8,
+    // Cycles added in one go before the next instruction, for hardware outside the core that
+    // holds the CPU off the bus
+    external_stall_cycles: u32,
     regs: Regs,
     flags: Flags,
     interrupt: Option<Interrupt>,
 
     pub watchpoints: HashSet<u16>,
     trigger_watchpoint: bool,
+
+    // Set instead of executing an opcode the core can't handle. The CPU then idles until reset.
+    trap: Option<Trap>,
+    illegal_mode: IllegalMode,
+
+    // Spend the cycles of the reads the 6502 makes and discards without performing them, which
+    // saves time but misses their side effects on registers
+    pub skip_dummy_reads: bool,
+
+    // When set, the CPU behaves as a plain 6502 with no NES-specific behavior
+    standalone: bool,
+
+    // When set, ADC and SBC do packed BCD arithmetic while the decimal flag is set, as on a
+    // plain 6502. The 2A03 has no decimal mode, so this is off for the NES.
+    decimal_enabled: bool,
+
+    // Addresses of ADC and SBC instructions run with the decimal flag set, which the 2A03
+    // ignores, so a game relying on BCD arithmetic is buggy
+    decimal_warnings: Option<Vec<u16>>,
+
+    // Debug builds check the cycles each instruction takes against the opcode table when set
+    validate_cycles: bool,
+    // Cycles spent on OAM DMA during the current instruction, which the validation leaves out
+    oam_dma_cycles: u64,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -148,6 +272,24 @@ impl Cpu {
         Default::default()
     }
 
+    // Creates a plain 6502 with no NES assumptions, for use with any `Memory`.
+    // Writes to 0x4014 are ordinary writes instead of triggering OAM DMA.
+    // Call `reset` to load the program counter from the reset vector before stepping.
+    pub fn standalone() -> Self {
+        Cpu {
+            standalone: true,
+            ..Default::default()
+        }
+    }
+
+    // Creates a plain 6502 like `standalone`, with the decimal mode the 2A03 lacks
+    pub fn standalone_with_decimal() -> Self {
+        Cpu {
+            decimal_enabled: true,
+            ..Cpu::standalone()
+        }
+    }
+
     pub fn get_state(&self) -> State {
         State {
             cycles: self.cycles,
@@ -170,14 +312,29 @@ impl Cpu {
         self.stall_cycles += cycles;
     }
 
+    // Delays the next instruction by `cycles`, which the `step` that runs it includes in its
+    // count. Unlike `stall`, which spends one cycle per step, the whole delay passes in one step.
+    pub fn add_stall_cycles(&mut self, cycles: u32) {
+        self.external_stall_cycles += cycles;
+    }
+
     pub fn regs(&self) -> Regs {
         self.regs
     }
 
+    // Cycles run since the CPU was created, including stalls and interrupts
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
     pub fn flags(&self) -> Flags {
         self.flags
     }
 
+    pub fn set_pc(&mut self, pc: u16) {
+        self.regs.pc = pc;
+    }
+
     pub fn reset(&mut self, mem: &mut impl Memory) {
         self.regs.pc = mem.read_word(RESET_VECTOR);
         self.regs.sp = 0xFD;
@@ -192,31 +349,85 @@ impl Cpu {
             n: false,
         };
         self.interrupt = None;
+        self.trap = None;
     }
 
     pub fn step(&mut self, mem: &mut impl Memory) -> (u32, bool) {
         if self.stall_cycles > 0 {
             self.stall_cycles -= 1;
+            self.cycles += 1;
+            return (1, false);
+        }
+
+        // A trapped CPU keeps time so the rest of the system runs on
+        if self.trap.is_some() {
+            self.cycles += 1;
             return (1, false);
         }
 
         self.trigger_watchpoint = false;
         let cycles = self.cycles;
+        self.cycles += std::mem::take(&mut self.external_stall_cycles) as u64;
 
         self.handle_interrupts(mem);
+        let instruction_pc = self.regs.pc;
+        let instruction_cycles = self.cycles;
+        self.oam_dma_cycles = 0;
+
+        let opcode = self.next_opcode(mem);
+        if self.illegal_mode == IllegalMode::Trap
+            && opcode_table()[opcode as usize].kind == OpKind::Unofficial
+        {
+            self.unknown(mem, opcode);
+        } else {
+            handle_opcode!(opcode, self, mem);
+        }
 
-        handle_opcode!(self.next_pc_byte(mem), self, mem);
+        if self.validate_cycles && self.trap.is_none() {
+            let taken = (self.cycles - instruction_cycles - self.oam_dma_cycles) as u32;
+            check_cycles(opcode, instruction_pc, taken);
+        }
 
         let cycles = (self.cycles - cycles) as u32;
 
         (cycles, self.trigger_watchpoint)
     }
 
+    // Starts recording ADC and SBC instructions run in decimal mode, to be collected with
+    // `take_decimal_warnings`. They still perform binary arithmetic, as on the 2A03.
+    pub fn enable_decimal_warnings(&mut self) {
+        self.decimal_warnings = Some(Vec::new());
+    }
+
+    // Returns the addresses of the instructions recorded since the last call
+    pub fn take_decimal_warnings(&mut self) -> Option<Vec<u16>> {
+        self.decimal_warnings.as_mut().map(std::mem::take)
+    }
+
+    // Asserts, in debug builds, that every instruction from now on takes a number of cycles the
+    // opcode table allows for, to catch timing mistakes during development
+    pub fn enable_cycle_validation(&mut self) {
+        self.validate_cycles = true;
+    }
+
+    pub fn trap(&self) -> Option<Trap> {
+        self.trap
+    }
+
+    pub fn illegal_mode(&self) -> IllegalMode {
+        self.illegal_mode
+    }
+
+    pub fn set_illegal_mode(&mut self, illegal_mode: IllegalMode) {
+        self.illegal_mode = illegal_mode;
+    }
+
     fn check_watchpoints(&self, addr: u16) -> bool {
         !self.watchpoints.is_empty() && self.watchpoints.contains(&addr)
     }
 
     fn handle_oam_dma(&mut self, mem: &mut impl Memory, addr_hi: u8) {
+        let start_cycles = self.cycles;
         self.dummy_read(mem);
 
         // An extra cycle should be added on an odd CPU cycle
@@ -225,11 +436,13 @@ impl Cpu {
             self.cycles += 1;
         }
 
+        // Copy the page to OAM through OAMDATA, alternating reads and writes
         let start = (addr_hi as u16) << 8;
         for i in 0..256 {
             let val = self.read_byte(mem, start + i);
             self.write_byte(mem, OAMDATA_ADDRESS, val);
         }
+        self.oam_dma_cycles += self.cycles - start_cycles;
     }
 
     #[inline(always)]
@@ -241,31 +454,50 @@ impl Cpu {
 
     #[inline(always)]
     fn read_word(&mut self, mem: &mut impl Memory, address: u16) -> u16 {
-        self.read_byte(mem, address) as u16 | ((self.read_byte(mem, address + 1) as u16) << 8)
+        self.read_byte(mem, address) as u16
+            | ((self.read_byte(mem, address.wrapping_add(1)) as u16) << 8)
     }
 
     #[inline(always)]
     fn dummy_read(&mut self, mem: &mut impl Memory) {
-        self.read_byte(mem, self.regs.pc);
+        if self.skip_dummy_reads {
+            self.cycles += 1;
+        } else {
+            self.read_byte(mem, self.regs.pc);
+        }
+    }
+
+    #[inline(always)]
+    fn next_opcode(&mut self, mem: &mut impl Memory) -> u8 {
+        let pc = self.regs.pc;
+        let op = self.read_byte(mem, pc);
+        mem.record_fetch(pc, Fetch::Opcode);
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        op
     }
 
     #[inline(always)]
     fn next_pc_byte(&mut self, mem: &mut impl Memory) -> u8 {
-        let b = self.read_byte(mem, self.regs.pc);
-        self.regs.pc += 1;
+        let pc = self.regs.pc;
+        let b = self.read_byte(mem, pc);
+        mem.record_fetch(pc, Fetch::Operand);
+        self.regs.pc = self.regs.pc.wrapping_add(1);
         b
     }
 
     #[inline(always)]
     fn next_pc_word(&mut self, mem: &mut impl Memory) -> u16 {
-        let w = self.read_word(mem, self.regs.pc);
-        self.regs.pc += 2;
+        let pc = self.regs.pc;
+        let w = self.read_word(mem, pc);
+        mem.record_fetch(pc, Fetch::Operand);
+        mem.record_fetch(pc.wrapping_add(1), Fetch::Operand);
+        self.regs.pc = self.regs.pc.wrapping_add(2);
         w
     }
 
     fn load_word_zero_page(&mut self, mem: &mut impl Memory, offset: u8) -> u16 {
         if offset == 0xFF {
-            self.read_byte(mem, 0xFF) as u16 + ((self.read_byte(mem, 0x00) as u16) << 8)
+            self.read_byte(mem, 0xFF) as u16 | ((self.read_byte(mem, 0x00) as u16) << 8)
         } else {
             self.read_word(mem, offset as u16)
         }
@@ -293,7 +525,7 @@ impl Cpu {
             AbsoluteIndexed(reg) => {
                 let base = self.next_pc_word(mem);
                 let index = self.get_register(reg) as u16;
-                let addr = base + index;
+                let addr = base.wrapping_add(index);
                 self.trigger_watchpoint |= self.check_watchpoints(addr);
 
                 // When crossing page boundaries, we do an
@@ -308,7 +540,7 @@ impl Cpu {
                 let base = self.next_pc_byte(mem) as u16;
                 self.read_byte(mem, base);
                 let index = self.get_register(reg) as u16;
-                let addr = (base + index) % 0x0100;
+                let addr = (base + index) & 0x00FF;
                 self.trigger_watchpoint |= self.check_watchpoints(addr);
 
                 (self.read_byte(mem, addr), Some(addr))
@@ -317,7 +549,7 @@ impl Cpu {
                 let base = self.next_pc_byte(mem);
                 self.read_byte(mem, base as u16);
                 let index = self.get_register(reg);
-                let addr = self.load_word_zero_page(mem, base + index);
+                let addr = self.load_word_zero_page(mem, base.wrapping_add(index));
                 self.trigger_watchpoint |= self.check_watchpoints(addr);
 
                 (self.read_byte(mem, addr), Some(addr))
@@ -326,7 +558,7 @@ impl Cpu {
                 let zp_offset = self.next_pc_byte(mem);
                 let base = self.load_word_zero_page(mem, zp_offset);
                 let index = self.get_register(reg) as u16;
-                let addr = base + index;
+                let addr = base.wrapping_add(index);
                 self.trigger_watchpoint |= self.check_watchpoints(addr);
 
                 // When crossing page boundaries, we do an
@@ -346,7 +578,7 @@ impl Cpu {
 
     #[inline(always)]
     fn write_byte(&mut self, mem: &mut impl Memory, address: u16, value: u8) {
-        if address == OAMDMA_ADDRESS {
+        if address == OAMDMA_ADDRESS && !self.standalone {
             self.cycles += 1;
             self.handle_oam_dma(mem, value);
         } else {
@@ -369,7 +601,7 @@ impl Cpu {
             AbsoluteIndexed(reg) => {
                 let base = self.next_pc_word(mem);
                 let index = self.get_register(reg) as u16;
-                let addr = base + index;
+                let addr = base.wrapping_add(index);
                 self.read_byte(mem, (base & 0xFF00) | (addr & 0x00FF));
                 self.write_byte(mem, addr, val);
             }
@@ -377,21 +609,21 @@ impl Cpu {
                 let base = self.next_pc_byte(mem) as u16;
                 self.read_byte(mem, base);
                 let index = self.get_register(reg) as u16;
-                let addr = (base + index) % 0x0100;
+                let addr = (base + index) & 0x00FF;
                 self.write_byte(mem, addr, val);
             }
             IndexedIndirect(reg) => {
                 let base = self.next_pc_byte(mem);
                 self.read_byte(mem, base as u16);
                 let index = self.get_register(reg);
-                let addr = self.load_word_zero_page(mem, base + index);
+                let addr = self.load_word_zero_page(mem, base.wrapping_add(index));
                 self.write_byte(mem, addr, val);
             }
             IndirectIndexed(reg) => {
                 let zp_offset = self.next_pc_byte(mem);
                 let base = self.load_word_zero_page(mem, zp_offset);
                 let index = self.get_register(reg) as u16;
-                let addr = base + index;
+                let addr = base.wrapping_add(index);
                 self.read_byte(mem, (base & 0xFF00) | (addr & 0x00FF));
                 self.write_byte(mem, addr, val);
             }
@@ -457,7 +689,7 @@ impl Cpu {
         let offset = self.next_pc_byte(mem) as i8;
         if cond {
             self.dummy_read(mem);
-            let addr = (self.regs.pc as i16 + offset as i16) as u16;
+            let addr = self.regs.pc.wrapping_add_signed(offset as i16);
 
             // Add another cycle if the branching to a new page
             if !mem_pages_same(self.regs.pc, addr) {
@@ -482,6 +714,11 @@ impl Cpu {
     }
 
     fn add_value(&mut self, value: u8) {
+        if self.decimal_enabled && self.flags.d {
+            self.add_decimal(value);
+            return;
+        }
+
         let result = self.regs.a as u32 + value as u32 + self.flags.c as u32;
 
         self.flags.c = (result & 0x100) != 0;
@@ -493,17 +730,68 @@ impl Cpu {
         self.regs.a = result;
     }
 
+    // Subtraction is addition of the operand's ones' complement, with the carry as an inverted
+    // borrow
     fn sub_value(&mut self, value: u8) {
-        let result = self.regs.a as i32 - value as i32 - (!self.flags.c) as i32;
+        let decimal_result =
+            (self.decimal_enabled && self.flags.d).then(|| self.sub_decimal(value));
+        let result = self.regs.a as u16 + (!value) as u16 + self.flags.c as u16;
 
-        self.flags.c = result >= 0;
+        self.flags.c = (result & 0x100) != 0;
 
         let result = result as u8;
-        self.flags.v =
-            ((self.regs.a ^ value) & 0x80 != 0) && (((self.regs.a ^ result) & 0x80) != 0);
+        self.flags.v = ((self.regs.a ^ result) & (self.regs.a ^ value) & 0x80) != 0;
         self.set_zero_negative(result);
 
-        self.regs.a = result;
+        self.regs.a = decimal_result.unwrap_or(result);
+    }
+
+    // Packed BCD addition. As on the NMOS 6502, Z comes from the binary sum, and N and V from
+    // the sum before the high digit is adjusted.
+    fn add_decimal(&mut self, value: u8) {
+        let a = self.regs.a as u16;
+        let value = value as u16;
+        let carry = self.flags.c as u16;
+
+        let mut lo = (a & 0x0F) + (value & 0x0F) + carry;
+        let mut hi = (a & 0xF0) + (value & 0xF0);
+        if lo > 0x09 {
+            lo += 0x06;
+        }
+        if lo > 0x0F {
+            hi += 0x10;
+        }
+
+        self.flags.z = (a + value + carry) & 0xFF == 0;
+        self.flags.n = (hi & 0x80) != 0;
+        self.flags.v = ((a ^ hi) & !(a ^ value) & 0x80) != 0;
+
+        if hi > 0x90 {
+            hi += 0x60;
+        }
+        self.flags.c = hi > 0xFF;
+
+        self.regs.a = ((hi & 0xF0) | (lo & 0x0F)) as u8;
+    }
+
+    // Packed BCD subtraction. The NMOS 6502 sets every flag from the binary difference, so
+    // this only computes the result.
+    fn sub_decimal(&self, value: u8) -> u8 {
+        let a = self.regs.a as i16;
+        let value = value as i16;
+        let borrow = !self.flags.c as i16;
+
+        let mut lo = (a & 0x0F) - (value & 0x0F) - borrow;
+        let mut hi = (a >> 4) - (value >> 4);
+        if lo < 0 {
+            lo -= 0x06;
+            hi -= 1;
+        }
+        if hi < 0 {
+            hi -= 0x06;
+        }
+
+        ((hi << 4) | (lo & 0x0F)) as u8
     }
 
     fn and_value(&mut self, value: u8) -> u8 {
@@ -525,6 +813,8 @@ impl Cpu {
         self.regs.a = result;
     }
 
+    // Read-modify-write instructions write the unmodified value back before writing the result,
+    // and mappers and the APU see both writes
     fn increment(&mut self, mem: &mut impl Memory, am: AddressMode) -> u8 {
         if let (val, Some(addr)) = self.load(mem, am, true) {
             self.write_byte(mem, addr, val);
@@ -619,12 +909,12 @@ impl Cpu {
     fn push_byte(&mut self, mem: &mut impl Memory, val: u8) {
         let s = self.regs.sp;
         self.write_byte(mem, 0x0100 | (s as u16), val);
-        self.regs.sp = s - 1;
+        self.regs.sp = s.wrapping_sub(1);
     }
 
     // Pull byte from the stack
     fn pull_byte(&mut self, mem: &mut impl Memory) -> u8 {
-        let s = self.regs.sp + 1;
+        let s = self.regs.sp.wrapping_add(1);
         self.regs.sp = s;
 
         self.read_byte(mem, 0x0100 | (s as u16))
@@ -673,15 +963,26 @@ impl Cpu {
     }
 
     fn adc(&mut self, mem: &mut impl Memory, am: AddressMode) {
+        self.check_decimal();
         let (m, _) = self.load(mem, am, false);
         self.add_value(m);
     }
 
     fn sbc(&mut self, mem: &mut impl Memory, am: AddressMode) {
+        self.check_decimal();
         let (m, _) = self.load(mem, am, false);
         self.sub_value(m);
     }
 
+    // Called before an ADC or SBC loads its operand, while the PC is just past the opcode
+    fn check_decimal(&mut self) {
+        if let (true, false, Some(warnings)) =
+            (self.flags.d, self.standalone, &mut self.decimal_warnings)
+        {
+            warnings.push(self.regs.pc.wrapping_sub(1));
+        }
+    }
+
     fn and(&mut self, mem: &mut impl Memory, am: AddressMode) {
         let (m, _) = self.load(mem, am, false);
         self.and_value(m);
@@ -749,7 +1050,7 @@ impl Cpu {
             if (addr & 0xFF) == 0xFF {
                 addr & 0xff00
             } else {
-                addr + 1
+                addr.wrapping_add(1)
             },
         );
 
@@ -891,7 +1192,7 @@ impl Cpu {
     fn rts(&mut self, mem: &mut impl Memory) {
         self.dummy_read(mem);
         self.cycles += 1;
-        self.regs.pc = self.pull_word(mem) + 1;
+        self.regs.pc = self.pull_word(mem).wrapping_add(1);
         self.cycles += 1;
     }
 
@@ -941,7 +1242,7 @@ impl Cpu {
 
     fn brk(&mut self, mem: &mut impl Memory) {
         self.dummy_read(mem);
-        self.push_word(mem, self.regs.pc + 1);
+        self.push_word(mem, self.regs.pc.wrapping_add(1));
         let mut status = self.flags;
         status.b = true;
         status.e = true;
@@ -960,6 +1261,20 @@ impl Cpu {
         self.regs.pc = pc;
     }
 
+    fn unknown(&mut self, _mem: &mut impl Memory, opcode: u8) {
+        // Leave the PC on the opcode
+        let pc = self.regs.pc.wrapping_sub(1);
+        self.regs.pc = pc;
+        self.trap = Some(Trap::IllegalOpcode { pc, opcode });
+    }
+
+    fn jam(&mut self, _mem: &mut impl Memory, opcode: u8) {
+        // The PC stays on the opcode and the CPU stops fetching until it's reset
+        let pc = self.regs.pc.wrapping_sub(1);
+        self.regs.pc = pc;
+        self.trap = Some(Trap::Jam { pc, opcode });
+    }
+
     fn nop(&mut self, mem: &mut impl Memory, am: AddressMode) {
         let pc = self.regs.pc;
         self.load(mem, am, false);
@@ -1079,9 +1394,9 @@ impl Cpu {
     // Some unofficial write instructions have an internal bus conflict that causes strange behaviors.
     fn unofficial_strange_write(&mut self, mem: &mut impl Memory, value: u8, index: u8) {
         let base = self.next_pc_word(mem);
-        let addr = base + index as u16;
+        let addr = base.wrapping_add(index as u16);
 
-        let result = value & ((base >> 8) + 1) as u8;
+        let result = value & ((base >> 8) as u8).wrapping_add(1);
 
         let addr = if ((base ^ addr) & 0x100) != 0 {
             // Page crossed
@@ -1123,6 +1438,15 @@ impl Cpu {
         }
     }
 
+    // Interrupt that will be run on the next step, if any
+    pub fn interrupt(&self) -> Option<Interrupt> {
+        self.interrupt
+    }
+
+    pub fn clear_interrupt(&mut self) {
+        self.interrupt = None;
+    }
+
     fn handle_interrupts(&mut self, mem: &mut impl Memory) -> bool {
         if let Some(interrupt) = self.interrupt {
             match interrupt {
@@ -1151,3 +1475,489 @@ impl Cpu {
         self.interrupt = None;
     }
 }
+
+#[cfg(test)]
+pub(crate) struct TestMemory(pub Vec<u8>);
+
+#[cfg(test)]
+impl TestMemory {
+    // 64KB of RAM with `program` loaded at 0x0600 and the reset vector pointing to it
+    pub fn with_program(program: &[u8]) -> Self {
+        let mut bytes = vec![0u8; 0x10000];
+        bytes[0x0600..0x0600 + program.len()].copy_from_slice(program);
+        bytes[RESET_VECTOR as usize] = 0x00;
+        bytes[RESET_VECTOR as usize + 1] = 0x06;
+        TestMemory(bytes)
+    }
+}
+
+#[cfg(test)]
+impl Memory for TestMemory {
+    fn read_byte(&mut self, address: u16) -> u8 {
+        self.0[address as usize]
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        self.0[address as usize] = value;
+    }
+}
+
+#[test]
+fn test_standalone_program() {
+    #[rustfmt::skip]
+    let program = [
+        0xA2, 0x00,       // LDX #$00
+        0xA9, 0x00,       // LDA #$00
+        0x18,             // CLC
+        0x7D, 0x00, 0x02, // loop: ADC $0200,X
+        0xE8,             // INX
+        0xE0, 0x08,       // CPX #$08
+        0xD0, 0xF8,       // BNE loop
+        0x8D, 0x00, 0x03, // STA $0300
+        0x8D, 0x14, 0x40, // STA $4014
+        0x4C, 0x13, 0x06, // done: JMP done
+    ];
+    let mut mem = TestMemory::with_program(&program);
+    mem.0[0x0200..0x0208].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+
+    let mut cpu = Cpu::standalone();
+    cpu.reset(&mut mem);
+    while cpu.regs().pc != 0x0613 {
+        cpu.step(&mut mem);
+    }
+
+    assert_eq!(mem.0[0x0300], 36);
+    assert_eq!(mem.0[0x4014], 36);
+    assert_eq!(cpu.regs().a, 36);
+}
+
+#[test]
+fn test_rmw_double_write() {
+    // Logs writes to a register, like a mapper would see them
+    struct RegisterMemory {
+        mem: TestMemory,
+        writes: Vec<u8>,
+    }
+
+    impl Memory for RegisterMemory {
+        fn read_byte(&mut self, address: u16) -> u8 {
+            self.mem.read_byte(address)
+        }
+
+        fn write_byte(&mut self, address: u16, value: u8) {
+            if address == 0x8000 {
+                self.writes.push(value);
+            }
+            self.mem.write_byte(address, value);
+        }
+    }
+
+    #[rustfmt::skip]
+    let program = [
+        0xEE, 0x00, 0x80, // INC $8000
+        0x0E, 0x00, 0x80, // ASL $8000
+        0xA2, 0x00,       // LDX #$00
+        0xDE, 0x00, 0x80, // DEC $8000,X
+    ];
+    let mut mem = RegisterMemory {
+        mem: TestMemory::with_program(&program),
+        writes: Vec::new(),
+    };
+    mem.mem.0[0x8000] = 0x41;
+
+    let mut cpu = Cpu::standalone();
+    cpu.reset(&mut mem);
+    cpu.step(&mut mem);
+    assert_eq!(mem.writes, [0x41, 0x42]);
+
+    cpu.step(&mut mem);
+    assert_eq!(mem.writes[2..], [0x42, 0x84]);
+
+    cpu.step(&mut mem);
+    cpu.step(&mut mem);
+    assert_eq!(mem.writes[4..], [0x84, 0x83]);
+}
+
+#[test]
+fn test_illegal_mode() {
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x01,       // LDA #$01
+        0x07, 0x10,       // SLO $10
+    ];
+    let run = |illegal_mode| {
+        let mut mem = TestMemory::with_program(&program);
+        mem.0[0x0010] = 0x81;
+        let mut cpu = Cpu::standalone();
+        cpu.set_illegal_mode(illegal_mode);
+        cpu.reset(&mut mem);
+        cpu.step(&mut mem);
+        cpu.step(&mut mem);
+        (cpu, mem)
+    };
+
+    let (cpu, mem) = run(IllegalMode::Trap);
+    assert_eq!(
+        cpu.trap(),
+        Some(Trap::IllegalOpcode {
+            pc: 0x0602,
+            opcode: 0x07
+        })
+    );
+    assert_eq!(cpu.regs().pc, 0x0602);
+    assert_eq!(mem.0[0x0010], 0x81);
+
+    // $81 << 1 = $02 with carry, then A = $01 | $02
+    let (cpu, mem) = run(IllegalMode::Execute);
+    assert_eq!(cpu.trap(), None);
+    assert_eq!(mem.0[0x0010], 0x02);
+    assert_eq!(cpu.regs().a, 0x03);
+    assert!(cpu.flags().c);
+}
+
+#[test]
+fn test_jam() {
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x01, // LDA #$01
+        0x12,       // JAM
+        0xA9, 0x02, // LDA #$02
+    ];
+    let mut mem = TestMemory::with_program(&program);
+    let mut cpu = Cpu::standalone();
+    cpu.reset(&mut mem);
+    cpu.step(&mut mem);
+    cpu.step(&mut mem);
+
+    let jam = Some(Trap::Jam {
+        pc: 0x0602,
+        opcode: 0x12,
+    });
+    assert_eq!(cpu.trap(), jam);
+
+    // Later steps only let time pass
+    for _ in 0..10 {
+        assert_eq!(cpu.step(&mut mem), (1, false));
+        assert_eq!(cpu.trap(), jam);
+        assert_eq!(cpu.regs().pc, 0x0602);
+        assert_eq!(cpu.regs().a, 0x01);
+    }
+
+    cpu.reset(&mut mem);
+    assert_eq!(cpu.trap(), None);
+}
+
+#[test]
+fn test_decimal_warnings() {
+    #[rustfmt::skip]
+    let program = [
+        0xF8,       // sed
+        0x18,       // clc
+        0xA9, 0x09, // lda #$09
+        0x69, 0x01, // adc #$01
+        0xE9, 0x00, // sbc #$00
+    ];
+    let mut mem = TestMemory::with_program(&program);
+    let mut cpu = Cpu::new();
+    cpu.reset(&mut mem);
+    assert!(cpu.take_decimal_warnings().is_none());
+    cpu.enable_decimal_warnings();
+
+    for _ in 0..4 {
+        cpu.step(&mut mem);
+    }
+    // $09 + $01 stays binary rather than becoming BCD $10
+    assert_eq!(cpu.regs().a, 0x0A);
+    assert_eq!(cpu.take_decimal_warnings(), Some(vec![0x0604]));
+
+    cpu.step(&mut mem);
+    assert_eq!(cpu.take_decimal_warnings(), Some(vec![0x0606]));
+}
+
+#[test]
+fn test_inx_wraps() {
+    #[rustfmt::skip]
+    let program = [
+        0xA2, 0xFF, // ldx #$FF
+        0xE8,       // inx
+    ];
+    let mut mem = TestMemory::with_program(&program);
+    let mut cpu = Cpu::new();
+    cpu.reset(&mut mem);
+
+    cpu.step(&mut mem);
+    cpu.step(&mut mem);
+    assert_eq!(cpu.regs().x, 0x00);
+    assert!(cpu.flags().z);
+    assert!(!cpu.flags().n);
+}
+
+#[test]
+fn test_region_timing() {
+    assert_eq!(Region::Ntsc.scanlines_per_frame(), 262);
+    assert_eq!(Region::Pal.scanlines_per_frame(), 312);
+    assert_eq!(Region::Dendy.scanlines_per_frame(), 312);
+    assert_eq!(Region::Ntsc.vblank_scanline(), 241);
+    assert_eq!(Region::Pal.vblank_scanline(), 241);
+    assert_eq!(Region::Dendy.vblank_scanline(), 291);
+
+    assert_eq!(Region::Ntsc.cpu_cycle_time_ns(), 559);
+    assert_eq!(Region::Pal.cpu_cycle_time_ns(), 602);
+    assert_eq!(Region::Dendy.cpu_cycle_time_ns(), 564);
+}
+
+#[test]
+fn test_sbc_flags() {
+    // (a, m, carry in) -> (result, carry out, overflow)
+    let vectors = [
+        (0x50, 0xF0, true, 0x60, false, false),
+        (0x50, 0xB0, true, 0xA0, false, true),
+        (0x50, 0x70, true, 0xE0, false, false),
+        (0x50, 0x30, true, 0x20, true, false),
+        (0xD0, 0xF0, true, 0xE0, false, false),
+        (0xD0, 0xB0, true, 0x20, true, false),
+        (0xD0, 0x70, true, 0x60, true, true),
+        (0xD0, 0x30, true, 0xA0, true, false),
+        (0x00, 0x00, false, 0xFF, false, false),
+        (0x80, 0x00, false, 0x7F, true, true),
+    ];
+
+    for (a, m, carry, result, carry_out, overflow) in vectors {
+        let mut cpu = Cpu::new();
+        cpu.regs.a = a;
+        cpu.flags.c = carry;
+        cpu.sub_value(m);
+        assert_eq!(cpu.regs.a, result, "{:02X} - {:02X}", a, m);
+        assert_eq!(cpu.flags.c, carry_out, "{:02X} - {:02X}", a, m);
+        assert_eq!(cpu.flags.v, overflow, "{:02X} - {:02X}", a, m);
+    }
+}
+
+#[test]
+fn test_rol_ror_accumulator() {
+    #[rustfmt::skip]
+    let program = [
+        0x38,       // sec
+        0xA9, 0x81, // lda #$81
+        0x2A,       // rol a
+        0x38,       // sec
+        0xA9, 0x81, // lda #$81
+        0x6A,       // ror a
+    ];
+    let mut mem = TestMemory::with_program(&program);
+    let mut cpu = Cpu::new();
+    cpu.reset(&mut mem);
+
+    for _ in 0..3 {
+        cpu.step(&mut mem);
+    }
+    assert_eq!(cpu.regs().a, 0x03);
+    assert!(cpu.flags().c);
+
+    for _ in 0..3 {
+        cpu.step(&mut mem);
+    }
+    assert_eq!(cpu.regs().a, 0xC0);
+    assert!(cpu.flags().c);
+    assert!(cpu.flags().n);
+}
+
+#[test]
+fn test_decimal_mode() {
+    // (a, value, carry in) -> (result, carry out)
+    let additions = [
+        (0x09, 0x01, false, 0x10, false),
+        (0x15, 0x26, false, 0x41, false),
+        (0x58, 0x46, true, 0x05, true),
+        (0x99, 0x01, false, 0x00, true),
+        (0x12, 0x34, true, 0x47, false),
+    ];
+    for (a, value, carry, result, carry_out) in additions {
+        let mut cpu = Cpu::standalone_with_decimal();
+        cpu.flags.d = true;
+        cpu.regs.a = a;
+        cpu.flags.c = carry;
+        cpu.add_value(value);
+        assert_eq!(cpu.regs.a, result, "{:02X} + {:02X}", a, value);
+        assert_eq!(cpu.flags.c, carry_out, "{:02X} + {:02X}", a, value);
+    }
+
+    let subtractions = [
+        (0x10, 0x01, true, 0x09, true),
+        (0x46, 0x12, true, 0x34, true),
+        (0x40, 0x13, true, 0x27, true),
+        (0x32, 0x02, false, 0x29, true),
+        (0x00, 0x01, true, 0x99, false),
+    ];
+    for (a, value, carry, result, carry_out) in subtractions {
+        let mut cpu = Cpu::standalone_with_decimal();
+        cpu.flags.d = true;
+        cpu.regs.a = a;
+        cpu.flags.c = carry;
+        cpu.sub_value(value);
+        assert_eq!(cpu.regs.a, result, "{:02X} - {:02X}", a, value);
+        assert_eq!(cpu.flags.c, carry_out, "{:02X} - {:02X}", a, value);
+    }
+
+    // The 2A03 ignores the decimal flag
+    let mut cpu = Cpu::new();
+    cpu.flags.d = true;
+    cpu.regs.a = 0x09;
+    cpu.add_value(0x01);
+    assert_eq!(cpu.regs.a, 0x0A);
+}
+
+#[test]
+fn test_cycle_validation() {
+    #[rustfmt::skip]
+    let program = [
+        0xA2, 0xFF,       // ldx #$FF
+        0xBD, 0x01, 0x02, // lda $0201,x (crosses a page)
+        0x9D, 0x00, 0x02, // sta $0200,x
+        0xFE, 0x00, 0x02, // inc $0200,x
+        0xD0, 0x00,       // bne +0 (taken)
+        0xF0, 0x00,       // beq +0 (not taken)
+        0x20, 0x13, 0x06, // jsr $0613
+        0xEA,             // nop
+        0x60,             // rts
+    ];
+    let mut mem = TestMemory::with_program(&program);
+    let mut cpu = Cpu::new();
+    cpu.reset(&mut mem);
+    cpu.enable_cycle_validation();
+
+    for expected in [2, 5, 5, 7, 3, 2, 6, 6, 2] {
+        assert_eq!(cpu.step(&mut mem).0, expected);
+    }
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "took 3 cycles, expected 2..=2")]
+fn test_cycle_validation_rejects_wrong_count() {
+    // LDA immediate with a spurious extra cycle
+    check_cycles(0xA9, 0x0600, 3);
+}
+
+#[test]
+fn test_unofficial_rmw_combos() {
+    // Every addressing mode runs without trapping and in the cycles the opcode table gives
+    for (opcode, info) in opcode_table().iter().enumerate() {
+        if !["RLA", "SRE", "RRA", "DCP", "ISC", "SAX"].contains(&info.mnemonic) {
+            continue;
+        }
+        let mut mem = TestMemory::with_program(&[opcode as u8, 0x10, 0x02]);
+        let mut cpu = Cpu::new();
+        cpu.reset(&mut mem);
+        cpu.enable_cycle_validation();
+        cpu.step(&mut mem);
+        assert_eq!(cpu.trap(), None, "opcode {:02X}", opcode);
+    }
+
+    // (opcode, a, x, carry, memory) -> (a, carry, memory)
+    let cases = [
+        (0x27, 0xFF, 0x00, false, 0x81, 0x02, true, 0x02), // rla $10
+        (0x47, 0x0F, 0x00, false, 0x03, 0x0E, true, 0x01), // sre $10
+        (0x67, 0x10, 0x00, true, 0x02, 0x91, false, 0x81), // rra $10
+        (0xC7, 0x04, 0x00, false, 0x05, 0x04, true, 0x04), // dcp $10
+        (0xE7, 0x05, 0x00, true, 0x01, 0x03, true, 0x02),  // isc $10
+        (0x87, 0xF0, 0x3C, false, 0x00, 0xF0, false, 0x30), // sax $10
+    ];
+    for (opcode, a, x, carry, value, result_a, result_carry, result_value) in cases {
+        let mut mem = TestMemory::with_program(&[opcode, 0x10]);
+        mem.0[0x10] = value;
+        let mut cpu = Cpu::new();
+        cpu.reset(&mut mem);
+        cpu.regs.a = a;
+        cpu.regs.x = x;
+        cpu.flags.c = carry;
+        cpu.step(&mut mem);
+        assert_eq!(cpu.regs.a, result_a, "opcode {:02X}", opcode);
+        assert_eq!(cpu.flags.c, result_carry, "opcode {:02X}", opcode);
+        assert_eq!(mem.0[0x10], result_value, "opcode {:02X}", opcode);
+    }
+}
+
+#[test]
+fn test_every_opcode_steps() {
+    // No byte stops the emulator by panicking: each opcode either runs or traps with its PC
+    for opcode in 0..=0xFF {
+        let mut mem = TestMemory::with_program(&[opcode, 0x10, 0x02]);
+        let mut cpu = Cpu::new();
+        cpu.reset(&mut mem);
+        cpu.step(&mut mem);
+
+        match cpu.trap() {
+            Some(Trap::IllegalOpcode {
+                pc,
+                opcode: trapped,
+            })
+            | Some(Trap::Jam {
+                pc,
+                opcode: trapped,
+            }) => {
+                assert_eq!((pc, trapped), (0x0600, opcode));
+                assert_eq!(opcode_table()[opcode as usize].kind, OpKind::Unimplemented);
+            }
+            None => assert_ne!(opcode_table()[opcode as usize].kind, OpKind::Unimplemented),
+        }
+    }
+}
+
+#[test]
+fn test_add_stall_cycles() {
+    #[rustfmt::skip]
+    let program = [
+        0xE8, // inx
+        0xE8, // inx
+    ];
+    let mut mem = TestMemory::with_program(&program);
+    let mut cpu = Cpu::new();
+    cpu.reset(&mut mem);
+
+    cpu.add_stall_cycles(5);
+    assert_eq!(cpu.step(&mut mem).0, 2 + 5);
+    assert_eq!(cpu.regs().x, 1);
+    assert_eq!(cpu.regs().pc, 0x0601);
+
+    assert_eq!(cpu.step(&mut mem).0, 2);
+    assert_eq!(cpu.regs().x, 2);
+}
+
+#[test]
+fn test_state_round_trip() {
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x80, // lda #$80
+        0xA2, 0x12, // ldx #$12
+        0xA0, 0x34, // ldy #$34
+        0x38,       // sec
+        0x48,       // pha
+    ];
+    let mut mem = TestMemory::with_program(&program);
+    let mut cpu = Cpu::new();
+    cpu.reset(&mut mem);
+    for _ in 0..5 {
+        cpu.step(&mut mem);
+    }
+    cpu.request_interrupt(Interrupt::Nmi);
+
+    let json = serde_json::to_string(&cpu.get_state()).unwrap();
+    let mut restored = Cpu::new();
+    restored.apply_state(&serde_json::from_str(&json).unwrap());
+
+    let (regs, restored_regs) = (cpu.regs(), restored.regs());
+    assert_eq!(
+        (regs.pc, regs.a, regs.x, regs.y, regs.sp),
+        (
+            restored_regs.pc,
+            restored_regs.a,
+            restored_regs.x,
+            restored_regs.y,
+            restored_regs.sp
+        )
+    );
+    assert_eq!(u8::from(cpu.flags()), u8::from(restored.flags()));
+    assert_eq!(restored.cycles(), cpu.cycles());
+    assert_eq!(restored.interrupt(), Some(Interrupt::Nmi));
+}
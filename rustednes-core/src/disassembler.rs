@@ -3,29 +3,64 @@ use crate::memory::Memory;
 
 pub struct Disassembler {
     pub pc: u16,
+    // BRK skips the byte after it, which the CPU pushes as part of the return address, so it's
+    // shown as BRK's signature operand by default. Clear this to list BRK as a 1-byte
+    // instruction.
+    pub brk_signature: bool,
 }
 
 impl Disassembler {
     pub fn new(pc: u16) -> Disassembler {
-        Disassembler { pc }
+        Disassembler {
+            pc,
+            brk_signature: true,
+        }
     }
 
     pub fn disassemble_next<M: Memory>(&mut self, mem: &mut M) -> String {
+        let is_data = mem.is_data(self.pc);
         let op = self.next_pc_byte(mem);
+        if is_data {
+            return self.unknown(mem, op);
+        }
         handle_opcode!(op, self, mem)
     }
 
+    // Best-effort search for the starts of the `count` instructions preceding `address`.
+    // Decoding backwards is ambiguous with variable-length instructions, so this decodes forward
+    // from each address in a window before `address`, and uses the earliest one that lands
+    // exactly on `address`. Instruction streams tend to resynchronize within a few instructions,
+    // so the result is usually right, but data mixed in with code can fool it.
+    pub fn instructions_before<M: Memory>(mem: &mut M, address: u16, count: usize) -> Vec<u16> {
+        let window = (count * 3 + 16).min(address as usize) as u16;
+
+        for start in (address - window)..address {
+            let mut d = Disassembler::new(start);
+            let mut starts = Vec::new();
+            while d.pc >= start && d.pc < address {
+                starts.push(d.pc);
+                d.disassemble_next(mem);
+            }
+
+            if d.pc == address && starts.len() >= count.min(window as usize) {
+                return starts.split_off(starts.len().saturating_sub(count));
+            }
+        }
+
+        Vec::new()
+    }
+
     fn next_pc_byte<M: Memory>(&mut self, mem: &mut M) -> u8 {
         let pc = self.pc;
-        let b = mem.read_byte(pc);
-        self.pc += 1;
+        let b = mem.peek_byte(pc);
+        self.pc = self.pc.wrapping_add(1);
         b
     }
 
     fn next_pc_word<M: Memory>(&mut self, mem: &mut M) -> u16 {
         let pc = self.pc;
-        let w = mem.read_word(pc);
-        self.pc += 2;
+        let w = mem.peek_word(pc);
+        self.pc = self.pc.wrapping_add(2);
         w
     }
 
@@ -295,8 +330,12 @@ impl Disassembler {
         self.dis_instruction("rol", mem, am)
     }
 
-    fn brk<M: Memory>(&mut self, _mem: &mut M) -> String {
-        "brk".into()
+    fn brk<M: Memory>(&mut self, mem: &mut M) -> String {
+        if self.brk_signature {
+            format!("brk #{}", self.dis_pc_byte(mem))
+        } else {
+            "brk".into()
+        }
     }
 
     fn rti<M: Memory>(&mut self, _mem: &mut M) -> String {
@@ -307,6 +346,14 @@ impl Disassembler {
         "nop".into()
     }
 
+    fn unknown(&mut self, _mem: &mut impl Memory, opcode: u8) -> String {
+        format!(".byte ${:02x}", opcode)
+    }
+
+    fn jam(&mut self, mem: &mut impl Memory, opcode: u8) -> String {
+        self.unknown(mem, opcode)
+    }
+
     ///////////////////////////
     // Unofficial Instructions
     ///////////////////////////
@@ -383,3 +430,52 @@ impl Disassembler {
         self.dis_instruction("sxa", mem, AddressMode::AbsoluteIndexed(Register8::Y))
     }
 }
+
+#[test]
+fn test_instructions_before() {
+    use crate::cpu::TestMemory;
+
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x01,       // 0x0600: LDA #$01
+        0x8D, 0x00, 0x03, // 0x0602: STA $0300
+        0xE8,             // 0x0605: INX
+        0xBD, 0x00, 0x02, // 0x0606: LDA $0200,X
+        0x4C, 0x00, 0x06, // 0x0609: JMP $0600
+    ];
+    let mut mem = TestMemory::with_program(&program);
+    // Code before the program, rather than zeros, which decode as 2-byte BRKs and so depend on
+    // where decoding starts
+    mem.0[0x0500..0x0600].fill(0xEA);
+
+    assert_eq!(
+        Disassembler::instructions_before(&mut mem, 0x060C, 3),
+        vec![0x0605, 0x0606, 0x0609]
+    );
+    assert_eq!(
+        Disassembler::instructions_before(&mut mem, 0x0609, 4),
+        vec![0x0600, 0x0602, 0x0605, 0x0606]
+    );
+}
+
+#[test]
+fn test_brk_signature() {
+    use crate::cpu::TestMemory;
+
+    #[rustfmt::skip]
+    let program = [
+        0x00, 0x42, // 0x0600: BRK #$42
+        0xE8,       // 0x0602: INX
+    ];
+    let mut mem = TestMemory::with_program(&program);
+
+    let mut d = Disassembler::new(0x0600);
+    assert_eq!(d.disassemble_next(&mut mem), "brk #$42");
+    assert_eq!(d.pc, 0x0602);
+    assert_eq!(d.disassemble_next(&mut mem), "inx");
+
+    let mut d = Disassembler::new(0x0600);
+    d.brk_signature = false;
+    assert_eq!(d.disassemble_next(&mut mem), "brk");
+    assert_eq!(d.pc, 0x0601);
+}
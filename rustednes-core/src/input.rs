@@ -15,6 +15,19 @@ pub enum Button {
     Right,
 }
 
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+    ];
+}
+
 #[derive(Copy, Clone, Default, Deserialize, Serialize)]
 pub struct GamePad {
     a_pressed: bool,
@@ -56,6 +69,26 @@ impl GamePad {
         }
     }
 
+    // The pressed buttons as a bit mask, with `Button::ALL[n]` in bit n
+    pub fn buttons(&self) -> u8 {
+        Button::ALL
+            .iter()
+            .enumerate()
+            .fold(0, |buttons, (n, &button)| {
+                buttons | ((self.button_pressed(button) as u8) << n)
+            })
+    }
+
+    pub fn set_buttons(&mut self, buttons: u8) {
+        for (n, &button) in Button::ALL.iter().enumerate() {
+            self.set_button_pressed(button, buttons & (1 << n) != 0);
+        }
+    }
+
+    fn peek_button_state(&self) -> bool {
+        self.button_pressed(self.strobe_state.button)
+    }
+
     fn next_button_state(&mut self) -> bool {
         let state = self.button_pressed(self.strobe_state.button);
         self.strobe_state.next();
@@ -63,6 +96,81 @@ impl GamePad {
     }
 }
 
+// Frames per turbo press and release cycle
+const TURBO_PERIOD_FRAMES: u32 = 4;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TurboMode {
+    // Turbo fires while its key is held
+    #[default]
+    Hold,
+    // Each press of the turbo key turns turbo on or off
+    Toggle,
+}
+
+#[derive(Copy, Clone, Default)]
+struct TurboButton {
+    mode: TurboMode,
+    key_pressed: bool,
+    toggled_on: bool,
+}
+
+impl TurboButton {
+    fn update(&mut self, key_pressed: bool) {
+        if self.mode == TurboMode::Toggle && key_pressed && !self.key_pressed {
+            self.toggled_on = !self.toggled_on;
+        }
+        self.key_pressed = key_pressed;
+    }
+
+    fn is_active(&self) -> bool {
+        match self.mode {
+            TurboMode::Hold => self.key_pressed,
+            TurboMode::Toggle => self.toggled_on,
+        }
+    }
+}
+
+// Rapidly presses and releases buttons while their turbo is active, tracking toggle state across
+// frames
+#[derive(Default)]
+pub struct Turbo {
+    buttons: [TurboButton; 8],
+    frame: u32,
+}
+
+impl Turbo {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn set_mode(&mut self, button: Button, mode: TurboMode) {
+        self.buttons[button as usize] = TurboButton {
+            mode,
+            ..Default::default()
+        };
+    }
+
+    pub fn is_active(&self, button: Button) -> bool {
+        self.buttons[button as usize].is_active()
+    }
+
+    // Called once per frame after the game pad's regular buttons are set, with whether each
+    // button's turbo key is pressed. Active turbo buttons override their regular button.
+    pub fn update<F: Fn(Button) -> bool>(&mut self, game_pad: &mut GamePad, turbo_key_pressed: F) {
+        self.frame = self.frame.wrapping_add(1);
+        let pressed = self.frame % TURBO_PERIOD_FRAMES < TURBO_PERIOD_FRAMES / 2;
+
+        for button in Button::ALL {
+            let turbo_button = &mut self.buttons[button as usize];
+            turbo_button.update(turbo_key_pressed(button));
+            if turbo_button.is_active() {
+                game_pad.set_button_pressed(button, pressed);
+            }
+        }
+    }
+}
+
 #[derive(Copy, Clone, Default, Deserialize, Serialize)]
 struct StrobeState {
     button: Button,
@@ -87,10 +195,50 @@ impl StrobeState {
     }
 }
 
-#[derive(Default)]
+// What's plugged into a controller port, which decides how its register reads
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ControllerType {
+    Standard,
+    // A Famicom pad. The second one has a microphone, read through $4016 bit 2.
+    Famicom,
+    Zapper,
+    // Nothing is plugged in, so reads see open bus
+    None,
+}
+
+#[derive(Copy, Clone, Default)]
+pub struct Zapper {
+    // Whether the photodiode sees a bright spot on screen
+    pub light_sensed: bool,
+    pub trigger_pulled: bool,
+}
+
+impl Zapper {
+    // Bit 3 is low while light is sensed, and bit 4 is high while the trigger is pulled
+    fn read(&self) -> u8 {
+        ((!self.light_sensed as u8) << 3) | ((self.trigger_pulled as u8) << 4)
+    }
+}
+
 pub struct Input {
     pub game_pad_1: GamePad,
     pub game_pad_2: GamePad,
+    pub zapper: Zapper,
+    // Whether the second Famicom pad's microphone is picking up sound
+    pub microphone: bool,
+    controller_types: [ControllerType; 2],
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Input {
+            game_pad_1: GamePad::default(),
+            game_pad_2: GamePad::default(),
+            zapper: Zapper::default(),
+            microphone: false,
+            controller_types: [ControllerType::Standard, ControllerType::None],
+        }
+    }
 }
 
 #[derive(Copy, Clone, Deserialize, Serialize)]
@@ -116,6 +264,67 @@ impl Input {
         self.game_pad_2 = state.game_pad_2;
     }
 
+    pub fn controller_type(&self, port: usize) -> ControllerType {
+        self.controller_types[port]
+    }
+
+    pub fn set_controller_type(&mut self, port: usize, controller_type: ControllerType) {
+        self.controller_types[port] = controller_type;
+    }
+
+    // Clears the controllers' state, keeping what's plugged into the ports
+    pub fn reset(&mut self) {
+        *self = Input {
+            controller_types: self.controller_types,
+            ..Default::default()
+        };
+    }
+
+    // The port read through `address`, if it's a controller register
+    pub fn port(address: u16) -> Option<usize> {
+        match address {
+            0x4016 => Some(0),
+            0x4017 => Some(1),
+            _ => None,
+        }
+    }
+
+    // The bits the next read of `address` would return, without shifting the controller
+    pub fn peek_byte(&self, address: u16) -> u8 {
+        match Input::port(address) {
+            Some(port) => match self.controller_types[port] {
+                ControllerType::Standard | ControllerType::Famicom => {
+                    self.game_pad(port).peek_button_state() as u8 | self.microphone_bit(port)
+                }
+                ControllerType::Zapper => self.zapper.read() | self.microphone_bit(port),
+                ControllerType::None => self.microphone_bit(port),
+            },
+            None => 0,
+        }
+    }
+
+    // The microphone on a second Famicom pad shows up in bit 2 of reads from the first port
+    fn microphone_bit(&self, port: usize) -> u8 {
+        let connected = self.controller_types[1] == ControllerType::Famicom;
+        ((port == 0 && connected && self.microphone) as u8) << 2
+    }
+
+    fn game_pad(&self, port: usize) -> &GamePad {
+        if port == 0 {
+            &self.game_pad_1
+        } else {
+            &self.game_pad_2
+        }
+    }
+
+    fn game_pad_mut(&mut self, port: usize) -> &mut GamePad {
+        if port == 0 {
+            &mut self.game_pad_1
+        } else {
+            &mut self.game_pad_2
+        }
+    }
+
     fn reset_strobe_states(&mut self) {
         self.game_pad_1.strobe_state.reset();
         self.game_pad_2.strobe_state.reset();
@@ -124,12 +333,18 @@ impl Input {
 
 impl Memory for Input {
     fn read_byte(&mut self, address: u16) -> u8 {
-        if address == 0x4016 {
-            self.game_pad_1.next_button_state() as u8
-        } else if address == 0x4017 {
-            self.game_pad_2.next_button_state() as u8
-        } else {
-            0
+        match Input::port(address) {
+            Some(port) => {
+                let microphone_bit = self.microphone_bit(port);
+                match self.controller_types[port] {
+                    ControllerType::Standard | ControllerType::Famicom => {
+                        self.game_pad_mut(port).next_button_state() as u8 | microphone_bit
+                    }
+                    ControllerType::Zapper => self.zapper.read() | microphone_bit,
+                    ControllerType::None => microphone_bit,
+                }
+            }
+            None => 0,
         }
     }
 
@@ -139,3 +354,57 @@ impl Memory for Input {
         }
     }
 }
+
+#[test]
+fn test_turbo_toggle() {
+    let mut game_pad = GamePad::default();
+    let mut turbo = Turbo::new();
+    turbo.set_mode(Button::A, TurboMode::Toggle);
+
+    // A single press turns turbo on
+    turbo.update(&mut game_pad, |button| matches!(button, Button::A));
+    assert!(turbo.is_active(Button::A));
+
+    // It stays on after release, alternating the button
+    let mut presses = 0;
+    for _ in 0..TURBO_PERIOD_FRAMES * 4 {
+        turbo.update(&mut game_pad, |_| false);
+        assert!(turbo.is_active(Button::A));
+        presses += game_pad.button_pressed(Button::A) as u32;
+    }
+    assert_eq!(presses, TURBO_PERIOD_FRAMES * 2);
+
+    // Pressing again turns it off, and holding the key doesn't turn it back on
+    turbo.update(&mut game_pad, |button| matches!(button, Button::A));
+    turbo.update(&mut game_pad, |button| matches!(button, Button::A));
+    turbo.update(&mut game_pad, |_| false);
+    assert!(!turbo.is_active(Button::A));
+    turbo.update(&mut game_pad, |_| false);
+    assert!(!turbo.is_active(Button::A));
+
+    // B is still in hold mode
+    turbo.update(&mut game_pad, |button| matches!(button, Button::B));
+    assert!(turbo.is_active(Button::B));
+    turbo.update(&mut game_pad, |_| false);
+    assert!(!turbo.is_active(Button::B));
+}
+
+#[test]
+fn test_famicom_microphone() {
+    let mut input = Input::new();
+    input.microphone = true;
+    // Only the second Famicom pad has a microphone
+    assert_eq!(input.read_byte(0x4016), 0x00);
+
+    input.set_controller_type(1, ControllerType::Famicom);
+    input.game_pad_1.set_button_pressed(Button::A, true);
+    input.write_byte(0x4016, 1);
+    input.write_byte(0x4016, 0);
+    assert_eq!(input.peek_byte(0x4016), 0x05);
+    assert_eq!(input.read_byte(0x4016), 0x05);
+    assert_eq!(input.read_byte(0x4016), 0x04);
+    assert_eq!(input.read_byte(0x4017), 0x00);
+
+    input.microphone = false;
+    assert_eq!(input.read_byte(0x4016), 0x00);
+}
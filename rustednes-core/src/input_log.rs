@@ -0,0 +1,170 @@
+use crate::input::Input;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use std::io::{self, Read, Write};
+
+// File header: "RNIL" followed by the format version
+const MAGIC: &[u8; 4] = b"RNIL";
+const VERSION: u8 = 1;
+
+// The buttons held on both controller ports from `frame` on, as `GamePad::buttons` masks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputLogEntry {
+    pub frame: u32,
+    pub port_0: u8,
+    pub port_1: u8,
+}
+
+// Controller input by frame number since power-on, with an entry only when it changes. Replaying
+// it from power-on reproduces a run exactly.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct InputLog {
+    entries: Vec<InputLogEntry>,
+    // Index of the next entry to apply during playback
+    position: usize,
+}
+
+impl InputLog {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn entries(&self) -> &[InputLogEntry] {
+        &self.entries
+    }
+
+    // Records the controllers' state at the start of `frame`, if it changed since the last call
+    pub fn record(&mut self, frame: u32, input: &Input) {
+        let entry = InputLogEntry {
+            frame,
+            port_0: input.game_pad_1.buttons(),
+            port_1: input.game_pad_2.buttons(),
+        };
+
+        let changed = match self.entries.last() {
+            Some(last) => (last.port_0, last.port_1) != (entry.port_0, entry.port_1),
+            None => entry.port_0 != 0 || entry.port_1 != 0,
+        };
+        if changed {
+            self.entries.push(entry);
+        }
+    }
+
+    // Sets the controllers to their logged state at the start of `frame`. Frames must be played
+    // back in order.
+    pub fn play(&mut self, frame: u32, input: &mut Input) {
+        while let Some(entry) = self.entries.get(self.position) {
+            if entry.frame > frame {
+                break;
+            }
+            input.game_pad_1.set_buttons(entry.port_0);
+            input.game_pad_2.set_buttons(entry.port_1);
+            self.position += 1;
+        }
+    }
+
+    // Whether playback has applied every entry
+    pub fn finished(&self) -> bool {
+        self.position >= self.entries.len()
+    }
+
+    // Writes the header, then 6 bytes per entry: the frame as a little-endian u32 and the two
+    // ports' buttons
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_u8(VERSION)?;
+        for entry in &self.entries {
+            w.write_u32::<LittleEndian>(entry.frame)?;
+            w.write_u8(entry.port_0)?;
+            w.write_u8(entry.port_1)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> io::Result<InputLog> {
+        let mut header = [0u8; 5];
+        r.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a version 1 input log",
+            ));
+        }
+
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes)?;
+        if !bytes.len().is_multiple_of(6) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "input log ends in a partial entry",
+            ));
+        }
+
+        let mut entries = Vec::with_capacity(bytes.len() / 6);
+        let mut r = &bytes[..];
+        while !r.is_empty() {
+            entries.push(InputLogEntry {
+                frame: r.read_u32::<LittleEndian>()?,
+                port_0: r.read_u8()?,
+                port_1: r.read_u8()?,
+            });
+        }
+
+        Ok(InputLog {
+            entries,
+            position: 0,
+        })
+    }
+}
+
+#[test]
+fn test_record_and_play() {
+    use crate::input::Button;
+
+    // Buttons held on port 0 and 1 from a frame on
+    let script = [
+        (3, &[Button::Start][..], &[][..]),
+        (5, &[], &[]),
+        (20, &[Button::Right, Button::A], &[Button::B]),
+        (21, &[Button::Right], &[Button::B]),
+        (40, &[], &[]),
+    ];
+    const FRAMES: u32 = 50;
+
+    let mut input = Input::new();
+    let mut states = Vec::new();
+    let mut log = InputLog::new();
+    for frame in 0..FRAMES {
+        if let Some(&(_, port_0, port_1)) = script.iter().find(|&&(f, _, _)| f == frame) {
+            input.game_pad_1 = Default::default();
+            input.game_pad_2 = Default::default();
+            for &button in port_0 {
+                input.game_pad_1.set_button_pressed(button, true);
+            }
+            for &button in port_1 {
+                input.game_pad_2.set_button_pressed(button, true);
+            }
+        }
+        log.record(frame, &input);
+        states.push((input.game_pad_1.buttons(), input.game_pad_2.buttons()));
+    }
+    assert_eq!(log.entries().len(), script.len());
+
+    let mut bytes = Vec::new();
+    log.write(&mut bytes).unwrap();
+    assert_eq!(bytes.len(), 5 + 6 * script.len());
+    let mut log = InputLog::read(&mut &bytes[..]).unwrap();
+
+    let mut input = Input::new();
+    for (frame, &state) in (0..FRAMES).zip(states.iter()) {
+        log.play(frame, &mut input);
+        assert_eq!(
+            (input.game_pad_1.buttons(), input.game_pad_2.buttons()),
+            state,
+            "frame {}",
+            frame
+        );
+    }
+    assert!(log.finished());
+}
@@ -1,10 +1,11 @@
 use crate::apu;
 use crate::apu::Apu;
-use crate::cpu::Cpu;
+use crate::coverage::{CodeDataLog, Coverage};
+use crate::cpu::{Cpu, Region};
 use crate::game_genie::Cheat;
-use crate::input::{self, Input};
+use crate::input::{self, ControllerType, Input};
 use crate::mapper::{self, Mapper, MapperEnum};
-use crate::memory::{Memory, Ram};
+use crate::memory::{Fetch, Memory, Ram};
 use crate::ppu::{self, Ppu};
 use crate::sink::*;
 
@@ -14,14 +15,45 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
+// Data lines of a controller read that no controller drives
+const CONTROLLER_OPEN_BUS_MASK: u8 = 0xE0;
+
 pub struct Interconnect {
     pub ram: Ram,
     pub ppu: Ppu,
     pub apu: Apu,
     pub input: Input,
     pub mapper: Rc<RefCell<MapperEnum>>,
+    pub coverage: Option<Coverage>,
+    pub code_data_log: Option<CodeDataLog>,
+    // Emulate the extra controller clock when a DMC DMA coincides with a $4016/$4017 read,
+    // which deletes a bit from the read sequence
+    pub dmc_controller_conflicts: bool,
+    // Fill the upper bits of controller reads from open bus, instead of leaving them zero.
+    // Some games, like Paperboy, expect $4016 to read back as $41 rather than $01.
+    pub controller_open_bus: bool,
 
+    region: Region,
+    // PPU cycles owed towards the next whole one, for regions that don't run a whole number per
+    // CPU cycle
+    ppu_clock_remainder: u32,
+    // The controller port read during the current instruction, if any
+    controller_read: Option<u16>,
     cheats: HashMap<u16, Cheat>,
+    // A value a test fixture drives onto the data bus, read back from unmapped addresses
+    open_bus: Option<u8>,
+    // Address of the opcode of the instruction being executed
+    opcode_address: u16,
+    self_modifications: Option<Vec<SelfModification>>,
+}
+
+// A write by code running from RAM or PRG RAM into the 8KB bank it's running from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelfModification {
+    // Address of the writing instruction
+    pub pc: u16,
+    pub address: u16,
+    pub value: u8,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -42,10 +74,31 @@ impl Interconnect {
             apu: Apu::new(mapper.clone()),
             input: Input::new(),
             mapper,
+            coverage: None,
+            code_data_log: None,
+            dmc_controller_conflicts: false,
+            controller_open_bus: true,
+            region: Region::default(),
+            ppu_clock_remainder: 0,
+            controller_read: None,
             cheats: HashMap::new(),
+            open_bus: None,
+            opcode_address: 0,
+            self_modifications: None,
         }
     }
 
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu_clock_remainder = 0;
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+    }
+
     pub fn get_state(&self) -> State {
         let mapper = self.mapper.borrow();
         State {
@@ -65,6 +118,86 @@ impl Interconnect {
         let mut mapper = self.mapper.borrow_mut();
         mapper.apply_state(&state.mapper);
     }
+
+    fn read_prg_ram(&mut self, address: u16) -> u8 {
+        let mut mapper = self.mapper.borrow_mut();
+        if mapper.maps_prg_ram() {
+            return mapper.prg_read_byte(address);
+        }
+
+        match mapper.prg_ram() {
+            Some(prg_ram) => prg_ram[(address as usize - 0x6000) % prg_ram.len()],
+            None => self.open_bus(address),
+        }
+    }
+
+    // Controllers only drive the low data lines, so the upper bits of a read keep the open bus
+    // value. Nothing drives an empty port.
+    fn controller_byte(&self, address: u16, bits: u8) -> u8 {
+        let connected = Input::port(address)
+            .is_some_and(|port| self.input.controller_type(port) != ControllerType::None);
+        if !connected {
+            self.open_bus(address)
+        } else if self.controller_open_bus {
+            (self.open_bus(address) & CONTROLLER_OPEN_BUS_MASK) | bits
+        } else {
+            bits
+        }
+    }
+
+    fn open_bus(&self, address: u16) -> u8 {
+        // After an absolute read the last byte on the bus is the high byte of the address
+        self.open_bus.unwrap_or((address >> 8) as u8)
+    }
+
+    // Makes open bus reads return `value`, as if external hardware had last driven it
+    #[cfg(test)]
+    pub(crate) fn set_open_bus(&mut self, value: u8) {
+        self.open_bus = Some(value);
+    }
+
+    fn write_prg_ram(&mut self, address: u16, value: u8) {
+        let mut mapper = self.mapper.borrow_mut();
+        if mapper.maps_prg_ram() {
+            mapper.prg_write_byte(address, value);
+        } else if let Some(prg_ram) = mapper.prg_ram() {
+            let len = prg_ram.len();
+            prg_ram[(address as usize - 0x6000) % len] = value;
+        }
+    }
+
+    // Starts recording writes by code to its own bank, to be collected with
+    // `take_self_modifications`
+    pub fn enable_self_modification_log(&mut self) {
+        self.self_modifications = Some(Vec::new());
+    }
+
+    // Returns the self-modifying writes recorded since the last call
+    pub fn take_self_modifications(&mut self) -> Option<Vec<SelfModification>> {
+        self.self_modifications.as_mut().map(std::mem::take)
+    }
+
+    fn record_self_modification(&mut self, address: u16, value: u8) {
+        // Only RAM and PRG RAM are writable, and mappers switch them in 8KB banks at the finest
+        let writable = address < 0x2000 || (0x6000..0x8000).contains(&address);
+        let pc = self.opcode_address;
+        if let Some(ref mut log) = self.self_modifications {
+            if writable && (address ^ pc) & 0xE000 == 0 {
+                log.push(SelfModification { pc, address, value });
+            }
+        }
+    }
+
+    fn apply_cheat(&self, address: u16, byte: u8) -> u8 {
+        if let Some(cheat) = self.cheats.get(&address) {
+            let compare = cheat.compare();
+            if compare.is_none() || compare.unwrap() == byte {
+                return cheat.data();
+            }
+        }
+
+        byte
+    }
 }
 
 impl Memory for Interconnect {
@@ -76,23 +209,59 @@ impl Memory for Interconnect {
         } else if address < 0x4016 {
             self.apu.read_byte(address)
         } else if address < 0x4018 {
-            self.input.read_byte(address)
+            self.controller_read = Some(address);
+            let bits = self.input.read_byte(address);
+            self.controller_byte(address, bits)
+        } else if (0x6000..0x8000).contains(&address) {
+            self.read_prg_ram(address)
         } else {
             let mut mapper = self.mapper.borrow_mut();
             mapper.prg_read_byte(address)
         };
 
-        if let Some(cheat) = self.cheats.get(&address) {
-            let compare = cheat.compare();
-            if compare.is_none() || compare.unwrap() == byte {
-                return cheat.data();
+        self.apply_cheat(address, byte)
+    }
+
+    // Reads through the full memory map like `read_byte`, but sees registers as they are rather
+    // than acknowledging flags or advancing addresses and controller shift registers
+    fn peek_byte(&mut self, address: u16) -> u8 {
+        let byte = if address < 0x2000 {
+            self.ram.read_byte(address)
+        } else if address < 0x4000 {
+            self.ppu.peek_byte(address)
+        } else if address < 0x4016 {
+            if address == 0x4015 {
+                self.apu.peek_status()
+            } else {
+                0
             }
-        }
+        } else if address < 0x4018 {
+            self.controller_byte(address, self.input.peek_byte(address))
+        } else if (0x6000..0x8000).contains(&address) {
+            self.read_prg_ram(address)
+        } else {
+            let mut mapper = self.mapper.borrow_mut();
+            mapper.prg_read_byte(address)
+        };
 
-        byte
+        self.apply_cheat(address, byte)
+    }
+
+    // Writes `value` to `address` like `write_byte`, except that a $2007 write doesn't advance
+    // the VRAM address
+    fn poke_byte(&mut self, address: u16, value: u8) {
+        if (0x2000..0x4000).contains(&address) {
+            self.ppu.poke_byte(address, value);
+        } else {
+            self.write_byte(address, value);
+        }
     }
 
     fn write_byte(&mut self, address: u16, value: u8) {
+        if self.self_modifications.is_some() {
+            self.record_self_modification(address, value);
+        }
+
         if address < 0x2000 {
             self.ram.write_byte(address, value);
         } else if address < 0x4000 {
@@ -101,11 +270,36 @@ impl Memory for Interconnect {
             self.apu.write_byte(address, value);
         } else if address < 0x4018 {
             self.input.write_byte(address, value);
+        } else if (0x6000..0x8000).contains(&address) {
+            self.write_prg_ram(address, value);
         } else {
             let mut mapper = self.mapper.borrow_mut();
             mapper.prg_write_byte(address, value);
         }
     }
+
+    fn record_fetch(&mut self, address: u16, fetch: Fetch) {
+        if fetch == Fetch::Opcode {
+            self.opcode_address = address;
+        }
+
+        if let Some(ref mut coverage) = self.coverage {
+            if let Some(offset) = self.mapper.borrow().prg_rom_offset(address) {
+                coverage.record(offset, fetch);
+            }
+        }
+    }
+
+    fn is_data(&mut self, address: u16) -> bool {
+        match self.code_data_log {
+            Some(ref log) => self
+                .mapper
+                .borrow()
+                .prg_rom_offset(address)
+                .is_some_and(|offset| log.is_data(offset)),
+            None => false,
+        }
+    }
 }
 
 impl Interconnect {
@@ -117,22 +311,47 @@ impl Interconnect {
         audio_frame_sink: &mut A,
     ) {
         for _ in 0..cycles {
-            // 3 PPU cycles per CPU cycle
-            for _ in 0..3 {
-                self.ppu.step(cpu, video_frame_sink);
-                let mut mapper = self.mapper.borrow_mut();
-                mapper.step(cpu, &self.ppu);
-            }
+            // 3 PPU cycles per CPU cycle, or 3.2 on PAL
+            let (ppu_cycles, cpu_cycles) = self.region.ppu_clock_ratio();
+            self.ppu_clock_remainder += ppu_cycles;
+            self.ppu_cycles(cpu, self.ppu_clock_remainder / cpu_cycles, video_frame_sink);
+            self.ppu_clock_remainder %= cpu_cycles;
 
             self.apu.step(cpu, audio_frame_sink);
+
+            // The DMA halts the CPU on its read, which is repeated and clocks the controller again.
+            // Instructions aren't stepped cycle by cycle, so any DMA during an instruction that
+            // read a controller is treated as coinciding with the read.
+            if self.apu.take_dmc_dma() && self.dmc_controller_conflicts {
+                if let Some(address) = self.controller_read.take() {
+                    self.input.read_byte(address);
+                }
+            }
+        }
+
+        self.controller_read = None;
+    }
+
+    // Steps the PPU and the mapper alone, without the CPU or APU
+    pub fn ppu_cycles<V: VideoSink>(
+        &mut self,
+        cpu: &mut Cpu,
+        cycles: u32,
+        video_frame_sink: &mut V,
+    ) {
+        for _ in 0..cycles {
+            self.ppu.step(cpu, video_frame_sink);
+            let mut mapper = self.mapper.borrow_mut();
+            mapper.step(cpu, &self.ppu);
         }
     }
 
     pub fn reset(&mut self) {
         self.ram = Ram::default();
+        self.ppu_clock_remainder = 0;
         self.ppu.reset();
         self.apu.reset();
-        self.input = Input::default();
+        self.input.reset();
         let mut mapper = self.mapper.borrow_mut();
         mapper.reset();
     }
@@ -149,3 +368,165 @@ impl Interconnect {
         self.cheats.clear();
     }
 }
+
+#[test]
+fn test_dmc_controller_conflict() {
+    use crate::apu::CountingAudioSink;
+    use crate::cartridge::{nrom_test_rom, Cartridge};
+    use crate::input::Button;
+    use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    // Reads the first three buttons, with a DMC DMA during the instruction reading the first
+    fn read_buttons(dmc_controller_conflicts: bool) -> Vec<u8> {
+        let cartridge = Cartridge::from_bytes(&nrom_test_rom(&[])).unwrap();
+        let mut interconnect =
+            Interconnect::new(Rc::new(RefCell::new(MapperEnum::from_cartridge(cartridge))));
+        interconnect.dmc_controller_conflicts = dmc_controller_conflicts;
+        let mut cpu = Cpu::new();
+        let mut buffer = vec![0u32; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let mut video_sink = Xrgb8888VideoSink::new(&mut buffer);
+        let mut audio_sink = CountingAudioSink(0);
+
+        interconnect
+            .input
+            .game_pad_1
+            .set_button_pressed(Button::A, true);
+        interconnect
+            .input
+            .game_pad_1
+            .set_button_pressed(Button::Select, true);
+        interconnect.write_byte(0x4016, 1);
+        interconnect.write_byte(0x4016, 0);
+
+        // A one byte sample, fetched on the next APU step
+        interconnect.write_byte(0x4013, 0x00);
+        interconnect.write_byte(0x4015, 0x10);
+
+        let mut bits = vec![interconnect.read_byte(0x4016) & 0x01];
+        interconnect.cycles(&mut cpu, 4, &mut video_sink, &mut audio_sink);
+        for _ in 0..2 {
+            bits.push(interconnect.read_byte(0x4016) & 0x01);
+            interconnect.cycles(&mut cpu, 4, &mut video_sink, &mut audio_sink);
+        }
+        bits
+    }
+
+    // A, B, Select
+    assert_eq!(read_buttons(false), vec![1, 0, 1]);
+    // B is deleted, so A, Select, Start
+    assert_eq!(read_buttons(true), vec![1, 1, 0]);
+}
+
+#[test]
+fn test_prg_ram() {
+    use crate::cartridge::{ines_test_rom, Cartridge};
+
+    // CNROM, as NES 2.0 when `prg_ram_shift` is given
+    fn cnrom(prg_ram_shift: Option<u8>) -> Interconnect {
+        let mut rom = ines_test_rom(3, 1, 1);
+        if let Some(shift) = prg_ram_shift {
+            rom[7] |= 0x08;
+            rom[10] = shift;
+        }
+        let cartridge = Cartridge::from_bytes(&rom).unwrap();
+        Interconnect::new(Rc::new(RefCell::new(MapperEnum::from_cartridge(cartridge))))
+    }
+
+    // iNES 1.0 always has PRG RAM, and NES 2.0 with 7 gives 64 << 7 = 8KB
+    for mut interconnect in [cnrom(None), cnrom(Some(7))] {
+        interconnect.write_byte(0x6000, 0x42);
+        interconnect.write_byte(0x7FFF, 0x24);
+        assert_eq!(interconnect.read_byte(0x6000), 0x42);
+        assert_eq!(interconnect.read_byte(0x7FFF), 0x24);
+    }
+
+    let mut interconnect = cnrom(Some(0));
+    interconnect.write_byte(0x6000, 0x42);
+    assert_eq!(interconnect.read_byte(0x6000), 0x60);
+    assert_eq!(interconnect.read_byte(0x7123), 0x71);
+}
+
+#[test]
+fn test_set_open_bus() {
+    use crate::cartridge::{self, Cartridge};
+
+    // NROM as NES 2.0 with no PRG RAM, leaving $6000-$7FFF unmapped
+    let mut rom = cartridge::nrom_test_rom(&[]);
+    rom[7] = 0x08;
+    let cartridge = Cartridge::from_bytes(&rom).unwrap();
+    let mut interconnect =
+        Interconnect::new(Rc::new(RefCell::new(MapperEnum::from_cartridge(cartridge))));
+    assert_eq!(interconnect.read_byte(0x6000), 0x60);
+
+    interconnect.set_open_bus(0xA5);
+    assert_eq!(interconnect.read_byte(0x6000), 0xA5);
+    assert_eq!(interconnect.peek_byte(0x7FFF), 0xA5);
+    assert_eq!(interconnect.read_byte(0x0000), 0x00);
+}
+
+#[test]
+fn test_controller_open_bus() {
+    use crate::cartridge::{nrom_test_rom, Cartridge};
+    use crate::input::Button;
+
+    let cartridge = Cartridge::from_bytes(&nrom_test_rom(&[])).unwrap();
+    let mut interconnect =
+        Interconnect::new(Rc::new(RefCell::new(MapperEnum::from_cartridge(cartridge))));
+    interconnect
+        .input
+        .game_pad_1
+        .set_button_pressed(Button::A, true);
+    interconnect.write_byte(0x4016, 1);
+    interconnect.write_byte(0x4016, 0);
+
+    // The high byte of the address is left on the bus
+    assert_eq!(interconnect.peek_byte(0x4016), 0x41);
+    assert_eq!(interconnect.read_byte(0x4016), 0x41);
+    assert_eq!(interconnect.read_byte(0x4016), 0x40);
+
+    // Only the undriven upper bits come from open bus
+    interconnect.set_open_bus(0xFF);
+    assert_eq!(interconnect.read_byte(0x4016), 0xE0);
+
+    interconnect.controller_open_bus = false;
+    assert_eq!(interconnect.read_byte(0x4016), 0x00);
+}
+
+#[test]
+fn test_self_modification_log() {
+    use crate::cartridge::{nrom_test_rom, Cartridge};
+    use crate::cpu::Cpu;
+
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0xE8,       // $6000: LDA #$E8
+        0x8D, 0x08, 0x60, // $6002: STA $6008
+        0x8D, 0x00, 0x02, // $6005: STA $0200
+        0xEA,             // $6008: NOP, replaced by INX
+    ];
+    let cartridge = Cartridge::from_bytes(&nrom_test_rom(&[])).unwrap();
+    let mut interconnect =
+        Interconnect::new(Rc::new(RefCell::new(MapperEnum::from_cartridge(cartridge))));
+    for (i, &byte) in program.iter().enumerate() {
+        interconnect.write_byte(0x6000 + i as u16, byte);
+    }
+    interconnect.enable_self_modification_log();
+
+    let mut cpu = Cpu::new();
+    cpu.reset(&mut interconnect);
+    cpu.set_pc(0x6000);
+    for _ in 0..3 {
+        cpu.step(&mut interconnect);
+    }
+
+    // The write to RAM is outside the running bank
+    assert_eq!(
+        interconnect.take_self_modifications(),
+        Some(vec![SelfModification {
+            pc: 0x6002,
+            address: 0x6008,
+            value: 0xE8,
+        }])
+    );
+    assert_eq!(interconnect.take_self_modifications(), Some(Vec::new()));
+}
@@ -3,14 +3,18 @@ mod opcode;
 
 pub mod apu;
 pub mod cartridge;
+pub mod coverage;
 pub mod cpu;
 pub mod disassembler;
 pub mod game_genie;
 pub mod input;
+pub mod input_log;
 pub mod interconnect;
 pub mod mapper;
 pub mod memory;
 pub mod nes;
+pub mod opcode_table;
 pub mod ppu;
+pub mod ram_system;
 pub mod serialize;
 pub mod sink;
@@ -20,6 +20,7 @@ use super::ppu::Ppu;
 use enum_dispatch::enum_dispatch;
 use serde_derive::{Deserialize, Serialize};
 
+use std::fmt;
 use std::ptr;
 
 #[enum_dispatch(MapperEnum)]
@@ -29,8 +30,36 @@ pub trait Mapper {
     fn chr_read_byte(&mut self, address: u16) -> u8;
     fn chr_write_byte(&mut self, address: u16, value: u8);
 
+    // Reads CHR without side effects such as switching banks on a latch, for debugging tools.
+    // Only mappers whose CHR reads have side effects need to override it.
+    fn chr_peek_byte(&mut self, address: u16) -> u8 {
+        self.chr_read_byte(address)
+    }
+
     fn mirroring(&self) -> Mirroring;
 
+    // The cartridge's RAM at 0x6000-0x7FFF, which the interconnect maps unless `maps_prg_ram`
+    fn prg_ram(&mut self) -> Option<&mut [u8]>;
+
+    // Whether prg_read_byte and prg_write_byte handle 0x6000-0x7FFF themselves, for mappers that
+    // bank or protect PRG RAM
+    fn maps_prg_ram(&self) -> bool {
+        false
+    }
+
+    // Offset into PRG ROM of the byte mapped at a CPU address, if it's mapped to PRG ROM
+    fn prg_rom_offset(&self, _address: u16) -> Option<usize> {
+        None
+    }
+
+    // Whether the mapper is asserting the IRQ line
+    fn irq_flag(&self) -> bool {
+        false
+    }
+
+    // Acknowledges the mapper's IRQ, as a game's IRQ handler would
+    fn clear_irq_flag(&mut self) {}
+
     // Called for every PPU cycle. Most mappers don't need to do anything.
     fn step(&mut self, _cpu: &mut Cpu, _ppu: &Ppu) {}
 
@@ -73,6 +102,62 @@ impl MapperEnum {
     }
 }
 
+// A mapper `MapperEnum::from_cartridge` implements, with what's known to be missing
+pub struct MapperInfo {
+    pub number: u16,
+    pub name: &'static str,
+    pub notes: &'static str,
+}
+
+impl fmt::Display for MapperInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>3}  {}", self.number, self.name)?;
+        if !self.notes.is_empty() {
+            write!(f, ": {}", self.notes)?;
+        }
+        Ok(())
+    }
+}
+
+// Keep in sync with `MapperEnum::from_cartridge`
+pub static MAPPERS: &[MapperInfo] = &[
+    MapperInfo {
+        number: 0,
+        name: "NROM",
+        notes: "",
+    },
+    MapperInfo {
+        number: 1,
+        name: "MMC1 (SxROM)",
+        notes: "no SUROM/SXROM 512KB PRG or PRG RAM banking, PRG RAM enable bit ignored",
+    },
+    MapperInfo {
+        number: 2,
+        name: "UxROM",
+        notes: "bus conflicts supported",
+    },
+    MapperInfo {
+        number: 3,
+        name: "CNROM",
+        notes: "bus conflicts supported",
+    },
+    MapperInfo {
+        number: 4,
+        name: "MMC3 (TxROM)",
+        notes: "scanline IRQ supported, PRG RAM protect ignored",
+    },
+    MapperInfo {
+        number: 7,
+        name: "AxROM",
+        notes: "",
+    },
+    MapperInfo {
+        number: 9,
+        name: "MMC2 (PxROM)",
+        notes: "",
+    },
+];
+
 #[derive(Deserialize, Serialize)]
 pub enum State {
     State0(mapper0::State),
@@ -83,3 +168,24 @@ pub enum State {
     State7(mapper7::State),
     State9(mapper9::State),
 }
+
+#[test]
+fn test_mappers() {
+    use crate::cartridge::nrom_test_rom;
+
+    let listing: Vec<String> = MAPPERS.iter().map(|info| info.to_string()).collect();
+    assert_eq!(listing[0], "  0  NROM");
+    assert!(listing.iter().any(|line| line.starts_with("  4  MMC3")));
+    // MMC5 isn't implemented
+    assert!(MAPPERS.iter().all(|info| info.number != 5));
+
+    // Every listed mapper can be created
+    for info in MAPPERS {
+        let mut cartridge = Cartridge::from_bytes(&nrom_test_rom(&[])).unwrap();
+        cartridge.mapper = info.number;
+        // MMC2 fixes the last three 8KB banks, so needs at least 32KB of PRG ROM
+        cartridge.prg_rom = vec![0; 0x8000];
+        cartridge.prg_rom_num_banks = 2;
+        MapperEnum::from_cartridge(cartridge);
+    }
+}
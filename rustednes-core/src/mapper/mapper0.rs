@@ -19,10 +19,8 @@ pub struct State {
 
 impl Mapper for Mapper0 {
     fn prg_read_byte(&mut self, address: u16) -> u8 {
-        if address < 0x6000 {
+        if address < 0x8000 {
             0
-        } else if address < 0x8000 {
-            self.cartridge.prg_ram[(address & 0x1FFF) as usize]
         } else if self.cartridge.prg_rom.len() > PRG_ROM_BANK_SIZE as usize {
             self.cartridge.prg_rom[(address & 0x7FFF) as usize]
         } else {
@@ -31,11 +29,7 @@ impl Mapper for Mapper0 {
         }
     }
 
-    fn prg_write_byte(&mut self, address: u16, value: u8) {
-        if (0x6000..0x8000).contains(&address) {
-            self.cartridge.prg_ram[(address & 0x0100) as usize] = value;
-        }
-    }
+    fn prg_write_byte(&mut self, _address: u16, _value: u8) {}
 
     fn chr_read_byte(&mut self, address: u16) -> u8 {
         self.cartridge.chr[address as usize]
@@ -49,6 +43,20 @@ impl Mapper for Mapper0 {
         self.cartridge.mirroring
     }
 
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.cartridge.prg_ram()
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        if address < 0x8000 {
+            None
+        } else if self.cartridge.prg_rom.len() > PRG_ROM_BANK_SIZE as usize {
+            Some((address & 0x7FFF) as usize)
+        } else {
+            Some((address & 0x3FFF) as usize)
+        }
+    }
+
     fn reset(&mut self) {
         // Nothing to reset
     }
@@ -188,6 +188,24 @@ impl Mapper for Mapper1 {
         self.cartridge.mirroring
     }
 
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.cartridge.prg_ram()
+    }
+
+    fn maps_prg_ram(&self) -> bool {
+        true
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        if address < 0x8000 {
+            None
+        } else if address < 0xC000 {
+            Some(self.prg_rom_address(self.prg_rom_bank_first(), address))
+        } else {
+            Some(self.prg_rom_address(self.prg_rom_bank_last(), address))
+        }
+    }
+
     fn sram(&mut self) -> *mut u8 {
         self.cartridge.prg_ram.as_mut_ptr() as *mut _
     }
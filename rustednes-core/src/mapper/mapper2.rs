@@ -49,6 +49,11 @@ impl Mapper for Mapper2 {
 
     fn prg_write_byte(&mut self, address: u16, value: u8) {
         if address >= 0x8000 {
+            let value = if self.cartridge.bus_conflicts {
+                value & self.read_prg_rom(address)
+            } else {
+                value
+            };
             self.switchable_bank =
                 ((value as usize) % (self.cartridge.prg_rom_num_banks) as usize) as u8;
         }
@@ -66,6 +71,21 @@ impl Mapper for Mapper2 {
         self.cartridge.mirroring
     }
 
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.cartridge.prg_ram()
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        if address < 0x8000 {
+            None
+        } else if address < 0xC000 {
+            Some(Mapper2::prg_rom_address(self.switchable_bank, address))
+        } else {
+            let bank = self.cartridge.prg_rom_num_banks - 1;
+            Some(Mapper2::prg_rom_address(bank, address))
+        }
+    }
+
     fn reset(&mut self) {
         self.switchable_bank = 0;
     }
@@ -87,3 +107,52 @@ impl Mapper for Mapper2 {
         }
     }
 }
+
+#[test]
+fn test_bus_conflicts() {
+    use crate::cartridge::test_rom;
+
+    // 4 banks, where every byte of ROM is 0x01
+    let cartridge = |bus_conflicts| {
+        let mut cartridge = Cartridge::from_bytes(&test_rom()).unwrap();
+        cartridge.prg_rom_num_banks = 4;
+        cartridge.prg_rom = vec![0x01; 4 * PRG_ROM_BANK_SIZE as usize];
+        cartridge.bus_conflicts = bus_conflicts;
+        cartridge
+    };
+
+    let mut mapper = Mapper2::new(cartridge(false));
+    mapper.prg_write_byte(0xC000, 0x03);
+    assert_eq!(mapper.switchable_bank, 0x03);
+
+    let mut mapper = Mapper2::new(cartridge(true));
+    mapper.prg_write_byte(0xC000, 0x03);
+    assert_eq!(mapper.switchable_bank, 0x01);
+}
+
+#[test]
+fn test_prg_banks_and_chr_ram() {
+    use crate::cartridge::ines_test_rom;
+
+    // 4 16KB banks, where every byte of each is its bank number, and no CHR ROM
+    let mut rom = ines_test_rom(2, 4, 0);
+    for (i, byte) in rom[16..].iter_mut().enumerate() {
+        *byte = (i / PRG_ROM_BANK_SIZE as usize) as u8;
+    }
+    let mut mapper = Mapper2::new(Cartridge::from_bytes(&rom).unwrap());
+    let windows =
+        |mapper: &mut Mapper2| [mapper.prg_read_byte(0x8000), mapper.prg_read_byte(0xC000)];
+
+    // The last bank is fixed at $C000
+    assert_eq!(windows(&mut mapper), [0, 3]);
+    mapper.prg_write_byte(0x8000, 0x02);
+    assert_eq!(windows(&mut mapper), [2, 3]);
+
+    // Bank numbers wrap around the number of banks
+    mapper.prg_write_byte(0xFFFF, 0x05);
+    assert_eq!(windows(&mut mapper), [1, 3]);
+
+    // Without CHR ROM, the pattern tables are writable RAM
+    mapper.chr_write_byte(0x1234, 0x56);
+    assert_eq!(mapper.chr_read_byte(0x1234), 0x56);
+}
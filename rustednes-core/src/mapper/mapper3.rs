@@ -22,8 +22,20 @@ impl Mapper3 {
         }
     }
 
-    fn chr_address(&self, bank: u8, address: u16) -> usize {
-        (bank as usize * 0x2000) | (address as usize & 0x1FFF)
+    // 16KB of PRG ROM is mirrored into both halves of $8000-$FFFF
+    fn prg_rom_address(&self, address: u16) -> usize {
+        (address as usize - 0x8000) & (self.cartridge.prg_rom.len() - 1)
+    }
+
+    // Wraps within the CHR actually present, so banks past the end of ROM and images whose CHR
+    // isn't a whole number of 8KB banks mirror it rather than reading out of bounds
+    fn chr_address(&self, bank: u8, address: u16) -> Option<usize> {
+        let len = self.cartridge.chr.len();
+        if len == 0 {
+            return None;
+        }
+
+        Some(((bank as usize * 0x2000) | (address as usize & 0x1FFF)) % len)
     }
 }
 
@@ -32,19 +44,27 @@ impl Mapper for Mapper3 {
         if address < 0x8000 {
             0
         } else {
-            self.cartridge.prg_rom[(address - 0x8000) as usize]
+            self.cartridge.prg_rom[self.prg_rom_address(address)]
         }
     }
 
     fn prg_write_byte(&mut self, address: u16, value: u8) {
         if address >= 0x8000 {
-            self.chr_bank = ((value as usize) % (self.cartridge.prg_rom.len() / 0x2000)) as u8;
+            let value = if self.cartridge.bus_conflicts {
+                value & self.prg_read_byte(address)
+            } else {
+                value
+            };
+            self.chr_bank = value;
         }
     }
 
     fn chr_read_byte(&mut self, address: u16) -> u8 {
-        let rom_addr = self.chr_address(self.chr_bank, address);
-        self.cartridge.chr[rom_addr]
+        match self.chr_address(self.chr_bank, address) {
+            Some(rom_addr) => self.cartridge.chr[rom_addr],
+            // Open bus, approximated as 0
+            None => 0,
+        }
     }
 
     fn chr_write_byte(&mut self, _address: u16, _value: u8) {
@@ -55,6 +75,18 @@ impl Mapper for Mapper3 {
         self.cartridge.mirroring
     }
 
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.cartridge.prg_ram()
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        if address < 0x8000 {
+            None
+        } else {
+            Some(self.prg_rom_address(address))
+        }
+    }
+
     fn reset(&mut self) {
         self.chr_bank = 0;
     }
@@ -76,3 +108,67 @@ impl Mapper for Mapper3 {
         }
     }
 }
+
+#[test]
+fn test_chr_bounds() {
+    use crate::cartridge::test_rom;
+
+    // Half an 8KB bank of CHR, where every byte is its offset's high byte
+    let mut cartridge = Cartridge::from_bytes(&test_rom()).unwrap();
+    cartridge.mapper = 3;
+    cartridge.chr = (0..0x1000).map(|i| (i >> 8) as u8).collect();
+    let mut mapper = Mapper3::new(cartridge);
+
+    // Reads past the end of CHR wrap within it
+    assert_eq!(mapper.chr_read_byte(0x0F00), 0x0F);
+    assert_eq!(mapper.chr_read_byte(0x1F00), 0x0F);
+    mapper.prg_write_byte(0x8000, 0x03);
+    assert_eq!(mapper.chr_read_byte(0x0100), 0x01);
+    assert_eq!(mapper.chr_read_byte(0x1FFF), 0x0F);
+
+    // With no CHR at all, reads return 0
+    let mut cartridge = Cartridge::from_bytes(&test_rom()).unwrap();
+    cartridge.chr.clear();
+    let mut mapper = Mapper3::new(cartridge);
+    assert_eq!(mapper.chr_read_byte(0x1234), 0);
+}
+
+#[test]
+fn test_prg_rom_mirroring() {
+    use crate::cartridge::ines_test_rom;
+
+    // A single 16KB bank, where every byte is its offset's high byte
+    let mut cartridge = Cartridge::from_bytes(&ines_test_rom(3, 1, 1)).unwrap();
+    cartridge.prg_rom = (0..0x4000).map(|i| (i >> 8) as u8).collect();
+    let mut mapper = Mapper3::new(cartridge);
+
+    assert_eq!(mapper.prg_rom_offset(0x8123), Some(0x0123));
+    assert_eq!(mapper.prg_rom_offset(0xC123), Some(0x0123));
+    assert_eq!(mapper.prg_rom_offset(0xFFFF), Some(0x3FFF));
+    assert_eq!(mapper.prg_read_byte(0xC100), 0x01);
+    assert_eq!(mapper.prg_read_byte(0xFFFF), 0x3F);
+}
+
+#[test]
+fn test_bus_conflicts() {
+    use crate::cartridge::test_rom;
+
+    // 4 CHR banks, where every byte of each is its bank number, and where every byte of PRG ROM
+    // is 0x01
+    let cartridge = |bus_conflicts| {
+        let mut cartridge = Cartridge::from_bytes(&test_rom()).unwrap();
+        cartridge.mapper = 3;
+        cartridge.prg_rom = vec![0x01; cartridge.prg_rom.len()];
+        cartridge.chr = (0..4 * 0x2000).map(|i| (i / 0x2000) as u8).collect();
+        cartridge.bus_conflicts = bus_conflicts;
+        cartridge
+    };
+
+    let mut mapper = Mapper3::new(cartridge(false));
+    mapper.prg_write_byte(0x8000, 0x03);
+    assert_eq!(mapper.chr_read_byte(0x0000), 0x03);
+
+    let mut mapper = Mapper3::new(cartridge(true));
+    mapper.prg_write_byte(0x8000, 0x03);
+    assert_eq!(mapper.chr_read_byte(0x0000), 0x01);
+}
@@ -17,6 +17,8 @@ pub struct Mapper4 {
     irq_enable: bool,
     irq_counter: u8,
     irq_counter_reload_value: u8,
+    // Set when the IRQ fires, until it's acknowledged by disabling it
+    irq_flag: bool,
 
     prg_rom_bank_offsets: [usize; 4],
     chr_bank_offsets: [usize; 8],
@@ -44,6 +46,8 @@ pub struct State {
     pub irq_enable: bool,
     pub irq_counter: u8,
     pub irq_counter_reload_value: u8,
+    #[serde(default)]
+    pub irq_flag: bool,
     pub prg_rom_bank_offsets: [usize; 4],
     pub chr_bank_offsets: [usize; 8],
 }
@@ -59,6 +63,7 @@ impl Mapper4 {
             irq_enable: false,
             irq_counter: 0,
             irq_counter_reload_value: 0,
+            irq_flag: false,
             prg_rom_bank_offsets: [0; 4],
             chr_bank_offsets: [0; 8],
         };
@@ -164,15 +169,19 @@ impl Mapper4 {
             self.irq_counter -= 1;
 
             if self.irq_counter == 0 && self.irq_enable {
+                self.irq_flag = true;
                 cpu.request_interrupt(Interrupt::Irq);
             }
         }
     }
 
+    fn prg_rom_address(&self, address: u16) -> usize {
+        self.prg_rom_bank_offsets[(address as usize - 0x8000) / 0x2000]
+            | (address as usize & 0x1FFF)
+    }
+
     fn read_prg_rom(&self, address: u16) -> u8 {
-        let addr = self.prg_rom_bank_offsets[(address as usize - 0x8000) / 0x2000]
-            | (address as usize & 0x1FFF);
-        self.cartridge.prg_rom[addr]
+        self.cartridge.prg_rom[self.prg_rom_address(address)]
     }
 
     fn chr_address(&self, address: u16) -> usize {
@@ -224,6 +233,9 @@ impl Mapper for Mapper4 {
             }
         } else {
             self.irq_enable = address & 0x01 != 0;
+            if !self.irq_enable {
+                self.irq_flag = false;
+            }
         }
     }
 
@@ -239,6 +251,22 @@ impl Mapper for Mapper4 {
         self.cartridge.mirroring
     }
 
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.cartridge.prg_ram()
+    }
+
+    fn maps_prg_ram(&self) -> bool {
+        true
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        if address < 0x8000 {
+            None
+        } else {
+            Some(self.prg_rom_address(address))
+        }
+    }
+
     fn step(&mut self, cpu: &mut Cpu, ppu: &Ppu) {
         if ppu.rendering_enabled()
             && ppu.scanline <= ppu::VISIBLE_END_SCANLINE
@@ -265,11 +293,20 @@ impl Mapper for Mapper4 {
         self.irq_enable = false;
         self.irq_counter = 0;
         self.irq_counter_reload_value = 0;
+        self.irq_flag = false;
         self.prg_rom_bank_offsets = [0; 4];
         self.chr_bank_offsets = [0; 8];
         self.update_banks();
     }
 
+    fn irq_flag(&self) -> bool {
+        self.irq_flag
+    }
+
+    fn clear_irq_flag(&mut self) {
+        self.irq_flag = false;
+    }
+
     fn get_state(&self) -> mapper::State {
         mapper::State::State4(State {
             cartridge: self.cartridge.get_state(),
@@ -280,6 +317,7 @@ impl Mapper for Mapper4 {
             irq_enable: self.irq_enable,
             irq_counter: self.irq_counter,
             irq_counter_reload_value: self.irq_counter_reload_value,
+            irq_flag: self.irq_flag,
             prg_rom_bank_offsets: self.prg_rom_bank_offsets,
             chr_bank_offsets: self.chr_bank_offsets,
         })
@@ -296,6 +334,7 @@ impl Mapper for Mapper4 {
                 self.irq_enable = state.irq_enable;
                 self.irq_counter = state.irq_counter;
                 self.irq_counter_reload_value = state.irq_counter_reload_value;
+                self.irq_flag = state.irq_flag;
                 self.prg_rom_bank_offsets = state.prg_rom_bank_offsets;
                 self.chr_bank_offsets = state.chr_bank_offsets;
             }
@@ -303,3 +342,64 @@ impl Mapper for Mapper4 {
         }
     }
 }
+
+#[test]
+fn test_prg_banks() {
+    use crate::cartridge::{test_rom, PRG_ROM_BANK_SIZE};
+
+    // 8 8KB banks, where every byte of each is its bank number
+    let mut cartridge = Cartridge::from_bytes(&test_rom()).unwrap();
+    cartridge.prg_rom_num_banks = 4;
+    cartridge.prg_rom = (0..4 * PRG_ROM_BANK_SIZE as usize)
+        .map(|i| (i / 0x2000) as u8)
+        .collect();
+    let mut mapper = Mapper4::new(cartridge);
+    let windows = |mapper: &mut Mapper4| {
+        [0x8000, 0xA000, 0xC000, 0xE000].map(|address| mapper.prg_read_byte(address))
+    };
+
+    // R6 and R7 select the banks at $8000 and $A000, with the second-last and last banks fixed
+    mapper.prg_write_byte(0x8000, 0x06);
+    mapper.prg_write_byte(0x8001, 0x03);
+    mapper.prg_write_byte(0x8000, 0x07);
+    mapper.prg_write_byte(0x8001, 0x01);
+    assert_eq!(windows(&mut mapper), [3, 1, 6, 7]);
+
+    // PRG ROM mode 1 swaps the R6 bank and the second-last bank
+    mapper.prg_write_byte(0x8000, 0x46);
+    assert_eq!(windows(&mut mapper), [6, 1, 3, 7]);
+
+    // Bank numbers wrap around the size of PRG ROM
+    mapper.prg_write_byte(0x8001, 0x0A);
+    assert_eq!(windows(&mut mapper), [6, 1, 2, 7]);
+}
+
+#[test]
+fn test_scanline_irq() {
+    use crate::cartridge::test_rom;
+
+    let mut mapper = Mapper4::new(Cartridge::from_bytes(&test_rom()).unwrap());
+    let mut cpu = Cpu::new();
+
+    // Latch 2, reload the counter and enable the IRQ
+    mapper.prg_write_byte(0xC000, 0x02);
+    mapper.prg_write_byte(0xC001, 0x00);
+    mapper.prg_write_byte(0xE001, 0x00);
+
+    // The counter reloads on the first scanline, then counts down to 0 on the third
+    mapper.handle_scanline(&mut cpu);
+    mapper.handle_scanline(&mut cpu);
+    assert_eq!(cpu.interrupt(), None);
+    mapper.handle_scanline(&mut cpu);
+    assert_eq!(cpu.interrupt(), Some(Interrupt::Irq));
+    assert!(mapper.irq_flag());
+
+    // Disabling the IRQ acknowledges it and stops it from firing again
+    cpu.clear_interrupt();
+    mapper.prg_write_byte(0xE000, 0x00);
+    assert!(!mapper.irq_flag());
+    for _ in 0..3 {
+        mapper.handle_scanline(&mut cpu);
+    }
+    assert_eq!(cpu.interrupt(), None);
+}
@@ -43,6 +43,11 @@ impl Mapper for Mapper7 {
 
     fn prg_write_byte(&mut self, address: u16, value: u8) {
         if address >= 0x8000 {
+            let value = if self.cartridge.bus_conflicts {
+                value & self.read_prg_rom(address)
+            } else {
+                value
+            };
             self.prg_rom_bank = value & 0x07;
             self.cartridge.mirroring = if value & 0x10 == 0 {
                 Mirroring::OneScreenUpper
@@ -64,6 +69,18 @@ impl Mapper for Mapper7 {
         self.cartridge.mirroring
     }
 
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.cartridge.prg_ram()
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        if address < 0x8000 {
+            None
+        } else {
+            Some(Mapper7::prg_rom_address(self.prg_rom_bank, address))
+        }
+    }
+
     fn reset(&mut self) {
         self.cartridge.mirroring = self.cartridge.default_mirroring;
         self.prg_rom_bank = 0;
@@ -86,3 +103,25 @@ impl Mapper for Mapper7 {
         }
     }
 }
+
+#[test]
+fn test_bus_conflicts() {
+    use crate::cartridge::test_rom;
+
+    // 4 32KB banks, where every byte of ROM is 0x01
+    let cartridge = |bus_conflicts| {
+        let mut cartridge = Cartridge::from_bytes(&test_rom()).unwrap();
+        cartridge.mapper = 7;
+        cartridge.prg_rom = vec![0x01; 4 * 0x8000];
+        cartridge.bus_conflicts = bus_conflicts;
+        cartridge
+    };
+
+    let mut mapper = Mapper7::new(cartridge(false));
+    mapper.prg_write_byte(0x8000, 0x03);
+    assert_eq!(mapper.prg_rom_bank, 0x03);
+
+    let mut mapper = Mapper7::new(cartridge(true));
+    mapper.prg_write_byte(0x8000, 0x03);
+    assert_eq!(mapper.prg_rom_bank, 0x01);
+}
@@ -76,10 +76,8 @@ impl Mapper9 {
 
 impl Mapper for Mapper9 {
     fn prg_read_byte(&mut self, address: u16) -> u8 {
-        if address < 0x6000 {
+        if address < 0x8000 {
             0
-        } else if address < 0x8000 {
-            self.cartridge.prg_ram[(address - 0x6000) as usize]
         } else if address < 0xA000 {
             let rom_addr = Mapper9::prg_rom_address(self.prg_rom_switchable_bank, address);
             self.cartridge.prg_rom[rom_addr]
@@ -96,10 +94,7 @@ impl Mapper for Mapper9 {
     }
 
     fn prg_write_byte(&mut self, address: u16, value: u8) {
-        if address < 0x6000 {
-        } else if address < 0x8000 {
-            self.cartridge.prg_ram[(address - 0x6000) as usize] = value
-        } else if address < 0xA000 {
+        if address < 0xA000 {
         } else if address < 0xB000 {
             self.prg_rom_switchable_bank = value & 0x0F;
         } else if address < 0xC000 {
@@ -135,6 +130,10 @@ impl Mapper for Mapper9 {
         value
     }
 
+    fn chr_peek_byte(&mut self, address: u16) -> u8 {
+        self.cartridge.chr[self.chr_address(address)]
+    }
+
     fn chr_write_byte(&mut self, address: u16, value: u8) {
         let chr_addr = self.chr_address(address);
         self.cartridge.chr[chr_addr] = value
@@ -144,6 +143,28 @@ impl Mapper for Mapper9 {
         self.cartridge.mirroring
     }
 
+    fn prg_ram(&mut self) -> Option<&mut [u8]> {
+        self.cartridge.prg_ram()
+    }
+
+    fn prg_rom_offset(&self, address: u16) -> Option<usize> {
+        if address < 0x8000 {
+            return None;
+        }
+
+        let bank = if address < 0xA000 {
+            self.prg_rom_switchable_bank
+        } else if address < 0xC000 {
+            self.prg_rom_fixed_bank_1
+        } else if address < 0xE000 {
+            self.prg_rom_fixed_bank_2
+        } else {
+            self.prg_rom_fixed_bank_3
+        };
+
+        Some(Mapper9::prg_rom_address(bank, address))
+    }
+
     fn reset(&mut self) {
         self.cartridge.mirroring = self.cartridge.default_mirroring;
         self.prg_rom_switchable_bank = 0;
@@ -190,3 +211,25 @@ impl Mapper for Mapper9 {
         }
     }
 }
+
+#[test]
+fn test_chr_peek_leaves_latches() {
+    use crate::cartridge::ines_test_rom;
+
+    // Every byte of each 4KB CHR bank is its bank number
+    let mut rom = ines_test_rom(9, 8, 2);
+    for (i, byte) in rom[16 + 8 * 0x4000..].iter_mut().enumerate() {
+        *byte = (i / 0x1000) as u8;
+    }
+    let mut mapper = Mapper9::new(Cartridge::from_bytes(&rom).unwrap());
+    // $FD bank 1 and $FE bank 2 at $0000
+    mapper.prg_write_byte(0xB000, 0x01);
+    mapper.prg_write_byte(0xC000, 0x02);
+
+    mapper.chr_read_byte(0x0FD8);
+    assert_eq!(mapper.chr_read_byte(0x0000), 1);
+    assert_eq!(mapper.chr_peek_byte(0x0FE8), 1);
+    assert_eq!(mapper.chr_read_byte(0x0000), 1);
+    mapper.chr_read_byte(0x0FE8);
+    assert_eq!(mapper.chr_read_byte(0x0000), 2);
+}
@@ -1,11 +1,43 @@
 use std::ops::{Deref, DerefMut};
 
+// How the CPU used a byte it read while fetching an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fetch {
+    Opcode,
+    Operand,
+}
+
 pub trait Memory {
     fn read_byte(&mut self, address: u16) -> u8;
     fn write_byte(&mut self, address: u16, value: u8);
 
+    // Called after the CPU reads a byte of an instruction, for recording code coverage
+    fn record_fetch(&mut self, _address: u16, _fetch: Fetch) {}
+
+    // Whether a code/data log marks the byte at `address` as data, so disassemblers don't decode
+    // it as an instruction
+    fn is_data(&mut self, _address: u16) -> bool {
+        false
+    }
+
+    // Reads `address` without the side effects a CPU read would have on memory-mapped registers,
+    // for debuggers and disassemblers inspecting memory
+    fn peek_byte(&mut self, address: u16) -> u8 {
+        self.read_byte(address)
+    }
+
+    // Writes `address` without the side effects a CPU write would have on memory-mapped
+    // registers, for debuggers patching memory
+    fn poke_byte(&mut self, address: u16, value: u8) {
+        self.write_byte(address, value)
+    }
+
+    fn peek_word(&mut self, address: u16) -> u16 {
+        self.peek_byte(address) as u16 | ((self.peek_byte(address.wrapping_add(1)) as u16) << 8)
+    }
+
     fn read_word(&mut self, address: u16) -> u16 {
-        self.read_byte(address) as u16 | ((self.read_byte(address + 1) as u16) << 8)
+        self.read_byte(address) as u16 | ((self.read_byte(address.wrapping_add(1)) as u16) << 8)
     }
 
     fn write_word(&mut self, address: u16, value: u16) {
@@ -14,6 +46,49 @@ pub trait Memory {
     }
 }
 
+// 64KB of RAM covering the whole address space, for running a CPU with no other hardware
+pub struct FlatRam {
+    bytes: Vec<u8>,
+}
+
+impl Default for FlatRam {
+    fn default() -> Self {
+        FlatRam {
+            bytes: vec![0; 0x10000],
+        }
+    }
+}
+
+impl FlatRam {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl Memory for FlatRam {
+    fn read_byte(&mut self, address: u16) -> u8 {
+        self.bytes[address as usize]
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        self.bytes[address as usize] = value
+    }
+}
+
+impl Deref for FlatRam {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.bytes
+    }
+}
+
+impl DerefMut for FlatRam {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.bytes
+    }
+}
+
 // 2KB internal RAM
 const RAM_SIZE: usize = 0x0800;
 
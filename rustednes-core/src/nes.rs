@@ -1,10 +1,14 @@
 use crate::cartridge::Cartridge;
+use crate::coverage::{CodeDataLog, Coverage};
 use crate::cpu;
 use crate::cpu::Cpu;
 use crate::game_genie::Cheat;
+use crate::input::ControllerType;
 use crate::interconnect;
 use crate::interconnect::Interconnect;
-use crate::mapper::MapperEnum;
+use crate::mapper::{Mapper, MapperEnum};
+use crate::memory::Memory;
+use crate::ppu;
 use crate::sink::*;
 
 use serde_derive::{Deserialize, Serialize};
@@ -12,9 +16,39 @@ use serde_derive::{Deserialize, Serialize};
 use std::cell::RefCell;
 use std::rc::Rc;
 
+// Presets for the flags that trade speed for accuracy:
+//
+// | flag                                    | fast | balanced | cycle |
+// |-----------------------------------------|------|----------|-------|
+// | cpu.skip_dummy_reads                    | yes  | no       | no    |
+// | ppu.warm_up                             | no   | yes      | yes   |
+// | ppu.odd_frame_skip                      | yes  | yes      | yes   |
+// | interconnect.dmc_controller_conflicts   | no   | no       | yes   |
+// | interconnect.controller_open_bus        | no   | yes      | yes   |
+//
+// `Balanced` matches the defaults of a new `Nes`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Accuracy {
+    Fast,
+    #[default]
+    Balanced,
+    Cycle,
+}
+
 pub struct Nes {
     pub interconnect: Interconnect,
     pub cpu: Cpu,
+    rom_crc32: u32,
+    prg_rom_len: usize,
+    has_chr_ram: bool,
+    start_pc: Option<u16>,
+    // PPU cycles the PPU runs ahead of the CPU after power on and reset
+    alignment: u8,
+    // Totals since power on, kept across resets
+    elapsed_frames: u64,
+    elapsed_cycles: u64,
+    // `elapsed_cycles` at the last reset
+    reset_cycles: u64,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -25,16 +59,130 @@ pub struct State {
 
 impl Nes {
     pub fn new(cartridge: Cartridge) -> Nes {
+        let rom_crc32 = cartridge.crc32;
+        let prg_rom_len = cartridge.prg_rom.len();
+        let has_chr_ram = cartridge.has_chr_ram;
         let mapper = Rc::new(RefCell::new(MapperEnum::from_cartridge(cartridge)));
         let cpu = Cpu::new();
         let interconnect = Interconnect::new(mapper);
-        let mut nes = Nes { interconnect, cpu };
+        let mut nes = Nes {
+            interconnect,
+            cpu,
+            rom_crc32,
+            prg_rom_len,
+            has_chr_ram,
+            start_pc: None,
+            alignment: 0,
+            elapsed_frames: 0,
+            elapsed_cycles: 0,
+            reset_cycles: 0,
+        };
 
         nes.reset();
 
         nes
     }
 
+    // Starts execution at `pc` after every reset instead of at the reset vector, as test suites
+    // such as Klaus Dormann's 6502 functional test expect
+    pub fn with_start_pc(mut self, pc: u16) -> Nes {
+        self.start_pc = Some(pc);
+        self.cpu.set_pc(pc);
+        self
+    }
+
+    // Starts the PPU `cpu_ppu_offset` of its cycles ahead of the CPU, modulo the 3 it runs per
+    // CPU cycle. Consoles power on at any of these alignments, which can change the outcome of
+    // timing-sensitive code, so pinning one makes a run reproducible. The default is 0.
+    pub fn with_alignment(mut self, cpu_ppu_offset: u8) -> Nes {
+        self.alignment = cpu_ppu_offset % 3;
+        self.reset();
+        self
+    }
+
+    // The CPU's view of memory. Its `Memory` implementation routes through the full memory map
+    // with the side effects of the CPU's own accesses; use `peek_byte` and `poke_byte` to inspect
+    // and patch memory from a debugger without them.
+    pub fn bus(&self) -> &Interconnect {
+        &self.interconnect
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Interconnect {
+        &mut self.interconnect
+    }
+
+    // Writes (address, value) pairs to CPU RAM, for setting up a scenario before the first step
+    pub fn preload_ram(&mut self, values: &[(u16, u8)]) {
+        for &(address, value) in values {
+            self.interconnect.ram.write_byte(address, value);
+        }
+    }
+
+    // Writes (address, value) pairs to the pattern tables through the mapper. Does nothing on
+    // cartridges with CHR ROM, which can't be written.
+    pub fn preload_chr(&mut self, values: &[(u16, u8)]) {
+        if !self.has_chr_ram {
+            return;
+        }
+
+        let mut mapper = self.interconnect.mapper.borrow_mut();
+        for &(address, value) in values {
+            mapper.chr_write_byte(address & 0x1FFF, value);
+        }
+    }
+
+    // Selects the console region, which sets the PPU's clock, frame length and vblank timing, and
+    // the APU's sample rate and output filters
+    pub fn set_region(&mut self, region: cpu::Region) {
+        self.interconnect.set_region(region);
+    }
+
+    pub fn region(&self) -> cpu::Region {
+        self.interconnect.region()
+    }
+
+    // Plugs `controller_type` into `port` 0 ($4016) or 1 ($4017)
+    pub fn set_controller_type(&mut self, port: usize, controller_type: ControllerType) {
+        self.interconnect
+            .input
+            .set_controller_type(port, controller_type);
+    }
+
+    // Sets the individual accuracy flags as `accuracy` presets them
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.cpu.skip_dummy_reads = accuracy == Accuracy::Fast;
+        self.interconnect.ppu.warm_up = accuracy != Accuracy::Fast;
+        self.interconnect.ppu.odd_frame_skip = true;
+        self.interconnect.dmc_controller_conflicts = accuracy == Accuracy::Cycle;
+        self.interconnect.controller_open_bus = accuracy != Accuracy::Fast;
+    }
+
+    // A hash of the last completed frame's palette indexes. Stable across runs and platforms, so
+    // tests can check the picture after running a ROM for some frames.
+    pub fn frame_hash(&self) -> u64 {
+        ppu::frame_hash(self.interconnect.ppu.frame_buffer())
+    }
+
+    // The PPU's current scanline, with -1 for the pre-render scanline, and dot
+    pub fn ppu_position(&self) -> (i16, u16) {
+        self.interconnect.ppu.position()
+    }
+
+    pub fn rom_crc32(&self) -> u32 {
+        self.rom_crc32
+    }
+
+    // Starts recording which PRG ROM bytes are executed, in `interconnect.coverage`
+    pub fn enable_coverage(&mut self) {
+        self.interconnect.coverage = Some(Coverage::new(self.prg_rom_len));
+    }
+
+    // Loads the contents of an FCEUX .cdl file, after which the disassembler shows the bytes it
+    // marks as data as `.byte` directives
+    pub fn load_cdl(&mut self, cdl: &[u8]) {
+        self.interconnect.code_data_log = Some(CodeDataLog::from_cdl(cdl, self.prg_rom_len));
+    }
+
     pub fn get_state(&self) -> State {
         State {
             interconnect: self.interconnect.get_state(),
@@ -48,15 +196,25 @@ impl Nes {
     }
 
     pub fn reset(&mut self) {
+        self.reset_cycles = self.elapsed_cycles;
         self.interconnect.reset();
+        self.interconnect.ppu_cycles(
+            &mut self.cpu,
+            self.alignment as u32,
+            &mut NullVideoSink::new(),
+        );
         self.cpu.reset(&mut self.interconnect);
+        if let Some(pc) = self.start_pc {
+            self.cpu.set_pc(pc);
+        }
     }
 
     pub fn step<A: AudioSink, V: VideoSink + Sized>(
         &mut self,
         video_frame_sink: &mut V,
         audio_frame_sink: &mut A,
-    ) -> (u32, bool) {
+    ) -> (u32, bool, bool) {
+        let frame = self.interconnect.ppu.frame();
         let (cpu_cycles, trigger_watchpoint) = self.cpu.step(&mut self.interconnect);
 
         self.interconnect.cycles(
@@ -66,7 +224,45 @@ impl Nes {
             audio_frame_sink,
         );
 
-        (cpu_cycles, trigger_watchpoint)
+        // A frame has been written to the video sink if the PPU moved on to the next frame
+        let frame_ready = self.interconnect.ppu.frame() != frame;
+
+        self.elapsed_cycles += cpu_cycles as u64;
+        self.elapsed_frames += frame_ready as u64;
+
+        (cpu_cycles, trigger_watchpoint, frame_ready)
+    }
+
+    /// Runs until the current frame is complete and has been written to the video sink, returning
+    /// the number of CPU cycles emulated.
+    pub fn run_until_vblank<A: AudioSink, V: VideoSink + Sized>(
+        &mut self,
+        video_frame_sink: &mut V,
+        audio_frame_sink: &mut A,
+    ) -> u64 {
+        let mut cycles = 0;
+        loop {
+            let (cpu_cycles, _, frame_ready) = self.step(video_frame_sink, audio_frame_sink);
+            cycles += cpu_cycles as u64;
+            if frame_ready {
+                return cycles;
+            }
+        }
+    }
+
+    // Frames completed since power on
+    pub fn elapsed_frames(&self) -> u64 {
+        self.elapsed_frames
+    }
+
+    // CPU cycles emulated since power on
+    pub fn elapsed_cycles(&self) -> u64 {
+        self.elapsed_cycles
+    }
+
+    // CPU cycles emulated since the last reset
+    pub fn total_cycles(&self) -> u64 {
+        self.elapsed_cycles - self.reset_cycles
     }
 
     pub fn add_cheat(&mut self, cheat: Cheat) {
@@ -81,3 +277,626 @@ impl Nes {
         self.interconnect.clear_cheats();
     }
 }
+
+#[test]
+fn test_step_frame_ready() {
+    use crate::apu::CountingAudioSink;
+    use crate::cartridge::nrom_test_rom;
+    use crate::ppu::{CYCLES_PER_FRAME, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    // JMP $8000
+    let cartridge = Cartridge::from_bytes(&nrom_test_rom(&[0x4C, 0x00, 0x80])).unwrap();
+    let mut nes = Nes::new(cartridge);
+    let mut buffer = vec![0u32; SCREEN_WIDTH * SCREEN_HEIGHT];
+    let mut video_sink = Xrgb8888VideoSink::new(&mut buffer);
+    let mut audio_sink = CountingAudioSink(0);
+
+    // Rendering is disabled, so no PPU cycles are skipped on odd frames
+    let mut cycles = 0u64;
+    let mut frames_ready = 0;
+    while cycles * 3 < 10 * CYCLES_PER_FRAME {
+        let (cpu_cycles, _, frame_ready) = nes.step(&mut video_sink, &mut audio_sink);
+        cycles += cpu_cycles as u64;
+        if frame_ready {
+            frames_ready += 1;
+            assert_eq!(nes.interconnect.ppu.frame(), frames_ready);
+        }
+    }
+
+    assert_eq!(frames_ready, 10);
+}
+
+#[test]
+fn test_run_until_vblank() {
+    use crate::apu::CountingAudioSink;
+    use crate::cartridge::nrom_test_rom;
+    use crate::ppu::{CYCLES_PER_FRAME, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    // JMP $8000
+    let cartridge = Cartridge::from_bytes(&nrom_test_rom(&[0x4C, 0x00, 0x80])).unwrap();
+    let mut nes = Nes::new(cartridge);
+    let mut buffer = vec![0u32; SCREEN_WIDTH * SCREEN_HEIGHT];
+    let mut video_sink = Xrgb8888VideoSink::new(&mut buffer);
+    let mut audio_sink = CountingAudioSink(0);
+
+    let mut total_cycles = 0;
+    for frame in 1..=3 {
+        let cycles = nes.run_until_vblank(&mut video_sink, &mut audio_sink);
+        total_cycles += cycles;
+        assert_eq!(nes.interconnect.ppu.frame(), frame);
+        assert!(cycles <= CYCLES_PER_FRAME / 3 + 3);
+        assert!(video_sink.frame_written());
+    }
+    assert!(total_cycles * 3 >= 3 * CYCLES_PER_FRAME);
+}
+
+#[test]
+fn test_coverage() {
+    use crate::apu::CountingAudioSink;
+    use crate::cartridge::nrom_test_rom;
+    use crate::memory::Fetch;
+    use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x01,       // LDA #$01
+        0x4C, 0x07, 0x80, // JMP $8007
+        0xDE, 0xAD,       // data
+        0xE8,             // INX
+        0x4C, 0x07, 0x80, // JMP $8007
+    ];
+    let cartridge = Cartridge::from_bytes(&nrom_test_rom(&program)).unwrap();
+    let mut nes = Nes::new(cartridge);
+    nes.enable_coverage();
+
+    let mut buffer = vec![0u32; SCREEN_WIDTH * SCREEN_HEIGHT];
+    let mut video_sink = Xrgb8888VideoSink::new(&mut buffer);
+    let mut audio_sink = CountingAudioSink(0);
+    for _ in 0..10 {
+        nes.step(&mut video_sink, &mut audio_sink);
+    }
+
+    let coverage = nes.interconnect.coverage.as_ref().unwrap();
+    let expected = [
+        Some(Fetch::Opcode),
+        Some(Fetch::Operand),
+        Some(Fetch::Opcode),
+        Some(Fetch::Operand),
+        Some(Fetch::Operand),
+        None,
+        None,
+        Some(Fetch::Opcode),
+        Some(Fetch::Opcode),
+        Some(Fetch::Operand),
+        Some(Fetch::Operand),
+        None,
+    ];
+    for (offset, &fetch) in expected.iter().enumerate() {
+        assert_eq!(coverage.get(offset), fetch, "offset {}", offset);
+    }
+
+    let mut cdl = Vec::new();
+    coverage.write_cdl(&mut cdl).unwrap();
+    assert_eq!(cdl.len(), 0x4000);
+    assert_eq!(cdl[..8], [1, 1, 1, 1, 1, 0, 0, 1]);
+    assert!(cdl[12..].iter().all(|&b| b == 0));
+}
+
+#[test]
+fn test_load_cdl() {
+    use crate::cartridge::nrom_test_rom;
+    use crate::disassembler::Disassembler;
+
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x01,       // LDA #$01
+        0x4C, 0x07, 0x80, // JMP $8007
+        0xDE, 0xAD,       // data
+        0xE8,             // INX
+    ];
+    let cartridge = Cartridge::from_bytes(&nrom_test_rom(&program)).unwrap();
+    let mut nes = Nes::new(cartridge);
+    let disassemble = |nes: &mut Nes| {
+        let mut d = Disassembler::new(0x8000);
+        (0..5)
+            .map(|_| d.disassemble_next(&mut nes.interconnect))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(disassemble(&mut nes)[2], "dec $e8ad,x");
+
+    nes.load_cdl(&[0x01, 0x01, 0x01, 0x01, 0x01, 0x02, 0x02, 0x01]);
+    assert_eq!(
+        disassemble(&mut nes),
+        ["lda #$01", "jmp $8007", ".byte $de", ".byte $ad", "inx"]
+    );
+}
+
+#[test]
+fn test_ppu_state() {
+    use crate::cartridge::nrom_test_rom;
+
+    // JMP $8000
+    let cartridge = Cartridge::from_bytes(&nrom_test_rom(&[0x4C, 0x00, 0x80])).unwrap();
+    let mut nes = Nes::new(cartridge);
+    nes.interconnect.ppu.warm_up = false;
+    let write = |nes: &mut Nes, values: &[(u16, u8)]| {
+        for &(address, value) in values {
+            nes.interconnect.write_byte(address, value);
+        }
+    };
+
+    write(
+        &mut nes,
+        &[
+            (0x2006, 0x20),
+            (0x2006, 0x00),
+            (0x2007, 0x11),
+            (0x2007, 0x22),
+            (0x2003, 0x00),
+            (0x2004, 0xAA),
+        ],
+    );
+    let snapshot = nes.interconnect.ppu.get_state();
+    let json = serde_json::to_string(&snapshot).unwrap();
+
+    write(
+        &mut nes,
+        &[
+            (0x2006, 0x20),
+            (0x2006, 0x00),
+            (0x2007, 0x33),
+            (0x2004, 0xBB),
+            (0x2006, 0x3F),
+            (0x2006, 0x00),
+            (0x2007, 0x30),
+        ],
+    );
+    let mut video_sink = NullVideoSink::new();
+    let mut audio_sink = crate::apu::CountingAudioSink(0);
+    nes.step(&mut video_sink, &mut audio_sink);
+    let cpu_cycles = nes.cpu.cycles();
+
+    nes.interconnect.ppu.apply_state(&snapshot);
+    let ppu = &mut nes.interconnect.ppu;
+    assert_eq!(serde_json::to_string(&ppu.get_state()).unwrap(), json);
+    assert_eq!(ppu.mem.read_byte(0x2000), 0x11);
+    assert_eq!(ppu.mem.read_byte(0x2001), 0x22);
+    assert_eq!(ppu.mem.read_byte(0x3F00), 0x00);
+    assert_eq!(ppu.oam()[..2], [0xAA, 0x00]);
+
+    // Only the PPU went back
+    assert_eq!(nes.cpu.cycles(), cpu_cycles);
+    assert_eq!(nes.cpu.regs().pc, 0x8000);
+}
+
+#[test]
+fn test_start_pc() {
+    use crate::apu::CountingAudioSink;
+    use crate::cartridge::nrom_test_rom;
+    use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    // INX at $8010, where the reset vector points at $8000
+    let mut program = vec![0xEA; 0x10];
+    program.push(0xE8);
+    let cartridge = Cartridge::from_bytes(&nrom_test_rom(&program)).unwrap();
+    let mut nes = Nes::new(cartridge).with_start_pc(0x8010);
+    assert_eq!(nes.cpu.regs().pc, 0x8010);
+
+    let mut buffer = vec![0u32; SCREEN_WIDTH * SCREEN_HEIGHT];
+    let mut video_sink = Xrgb8888VideoSink::new(&mut buffer);
+    let mut audio_sink = CountingAudioSink(0);
+    nes.step(&mut video_sink, &mut audio_sink);
+    assert_eq!(nes.cpu.regs().x, 1);
+    assert_eq!(nes.cpu.regs().pc, 0x8011);
+
+    // The start PC also applies after a reset
+    nes.reset();
+    assert_eq!(nes.cpu.regs().pc, 0x8010);
+}
+
+#[test]
+fn test_ppu_position() {
+    use crate::apu::CountingAudioSink;
+    use crate::cartridge::nrom_test_rom;
+    use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    // NOPs, which take 2 CPU cycles or 6 dots each
+    let cartridge = Cartridge::from_bytes(&nrom_test_rom(&[])).unwrap();
+    let mut nes = Nes::new(cartridge);
+    let mut buffer = vec![0u32; SCREEN_WIDTH * SCREEN_HEIGHT];
+    let mut video_sink = Xrgb8888VideoSink::new(&mut buffer);
+    let mut audio_sink = CountingAudioSink(0);
+
+    assert_eq!(nes.ppu_position(), (0, 0));
+
+    // 600 dots
+    for _ in 0..100 {
+        nes.step(&mut video_sink, &mut audio_sink);
+    }
+    assert_eq!(nes.ppu_position(), (1, 600 - 341));
+
+    // 89004 dots, 3 into the pre-render scanline
+    for _ in 100..14834 {
+        nes.step(&mut video_sink, &mut audio_sink);
+    }
+    assert_eq!(nes.ppu_position(), (-1, 3));
+
+    nes.reset();
+    assert_eq!(nes.ppu_position(), (0, 0));
+}
+
+#[test]
+fn test_preload() {
+    use crate::apu::CountingAudioSink;
+    use crate::cartridge::nrom_test_rom;
+    use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    // LDA $10
+    let cartridge = Cartridge::from_bytes(&nrom_test_rom(&[0xA5, 0x10])).unwrap();
+    let mut nes = Nes::new(cartridge);
+    let mut buffer = vec![0u32; SCREEN_WIDTH * SCREEN_HEIGHT];
+    let mut video_sink = Xrgb8888VideoSink::new(&mut buffer);
+    let mut audio_sink = CountingAudioSink(0);
+
+    nes.preload_ram(&[(0x0010, 0x42), (0x0700, 0x99)]);
+    // The test ROM has CHR ROM, which is left alone
+    nes.preload_chr(&[(0x0123, 0x5A)]);
+
+    nes.step(&mut video_sink, &mut audio_sink);
+    assert_eq!(nes.cpu.regs().a, 0x42);
+    assert_eq!(nes.interconnect.read_byte(0x0700), 0x99);
+    assert_eq!(
+        nes.interconnect.mapper.borrow_mut().chr_read_byte(0x0123),
+        0x00
+    );
+
+    // The same image without CHR ROM gets CHR RAM, which is preloaded
+    let mut rom = nrom_test_rom(&[0xA5, 0x10]);
+    rom[5] = 0;
+    rom.truncate(rom.len() - crate::cartridge::CHR_ROM_BANK_SIZE as usize);
+    let mut nes = Nes::new(Cartridge::from_bytes(&rom).unwrap());
+    nes.preload_chr(&[(0x0123, 0x5A)]);
+    assert_eq!(
+        nes.interconnect.mapper.borrow_mut().chr_read_byte(0x0123),
+        0x5A
+    );
+}
+
+#[test]
+fn test_oam_dma() {
+    use crate::apu::CountingAudioSink;
+    use crate::cartridge::nrom_test_rom;
+    use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    // Returns the cycles taken by STA $4014 after running `setup`, and OAM afterwards
+    fn run_dma(setup: &[u8]) -> (u32, Vec<u8>) {
+        let mut program = setup.to_vec();
+        // LDA #$02, STA $4014
+        program.extend_from_slice(&[0xA9, 0x02, 0x8D, 0x14, 0x40]);
+        let cartridge = Cartridge::from_bytes(&nrom_test_rom(&program)).unwrap();
+        let mut nes = Nes::new(cartridge);
+        let page: Vec<(u16, u8)> = (0..256u16)
+            .map(|i| (0x0200 + i, (i as u8) ^ 0xA5))
+            .collect();
+        nes.preload_ram(&page);
+
+        let mut buffer = vec![0u32; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let mut video_sink = Xrgb8888VideoSink::new(&mut buffer);
+        let mut audio_sink = CountingAudioSink(0);
+        while nes.cpu.regs().pc != 0x8000 + setup.len() as u16 + 2 {
+            nes.step(&mut video_sink, &mut audio_sink);
+        }
+        let (cycles, _, _) = nes.step(&mut video_sink, &mut audio_sink);
+        (cycles, nes.interconnect.ppu.oam().to_vec())
+    }
+
+    let expected_oam: Vec<u8> = (0..256u16).map(|i| (i as u8) ^ 0xA5).collect();
+
+    // The 4 cycle store, then 513 DMA cycles plus 1 to align with an even cycle
+    let (cycles, oam) = run_dma(&[]);
+    assert_eq!(cycles, 4 + 514);
+    assert_eq!(oam, expected_oam);
+
+    // A 3 cycle LDA $00 first starts the DMA already aligned
+    let (cycles, oam) = run_dma(&[0xA5, 0x00]);
+    assert_eq!(cycles, 4 + 513);
+    assert_eq!(oam, expected_oam);
+}
+
+#[test]
+fn test_peek_ppu_status() {
+    use crate::apu::CountingAudioSink;
+    use crate::cartridge::nrom_test_rom;
+
+    // JMP $8000
+    let cartridge = Cartridge::from_bytes(&nrom_test_rom(&[0x4C, 0x00, 0x80])).unwrap();
+    let mut nes = Nes::new(cartridge);
+    let mut video_sink = NullVideoSink::new();
+    let mut audio_sink = CountingAudioSink(0);
+    // Vblank starts at dot 1 of scanline 241
+    while nes.ppu_position().0 != 241 {
+        nes.step(&mut video_sink, &mut audio_sink);
+    }
+
+    let bus = nes.bus_mut();
+    assert_eq!(bus.peek_byte(0x2002) & 0x80, 0x80);
+    assert_eq!(bus.peek_byte(0x2002) & 0x80, 0x80);
+    assert_eq!(bus.read_byte(0x2002) & 0x80, 0x80);
+    assert_eq!(bus.read_byte(0x2002) & 0x80, 0x00);
+    assert_eq!(bus.peek_byte(0x2002) & 0x80, 0x00);
+}
+
+#[test]
+fn test_frame_hash() {
+    use crate::apu::CountingAudioSink;
+    use crate::cartridge::nrom_test_rom;
+
+    #[rustfmt::skip]
+    let program = [
+        // Wait two frames for the PPU to accept writes
+        0x2C, 0x02, 0x20, // BIT $2002
+        0x10, 0xFB,       // BPL $8000
+        0x2C, 0x02, 0x20, // BIT $2002
+        0x10, 0xFB,       // BPL $8005
+        // Set the backdrop color to $21 and show the background
+        0xA9, 0x3F,       // LDA #$3F
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x00,       // LDA #$00
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x21,       // LDA #$21
+        0x8D, 0x07, 0x20, // STA $2007
+        0xA9, 0x0A,       // LDA #$0A
+        0x8D, 0x01, 0x20, // STA $2001
+        0x4C, 0x1E, 0x80, // JMP $801E
+    ];
+    let run = || {
+        let cartridge = Cartridge::from_bytes(&nrom_test_rom(&program)).unwrap();
+        let mut nes = Nes::new(cartridge);
+        for _ in 0..5 {
+            nes.run_until_vblank(&mut NullVideoSink::new(), &mut CountingAudioSink(0));
+        }
+        nes
+    };
+
+    let nes = run();
+    let frame_buffer = nes.interconnect.ppu.frame_buffer();
+    assert!(frame_buffer.iter().all(|&color| color == 0x21));
+    assert_eq!(nes.frame_hash(), run().frame_hash());
+
+    let mut changed = frame_buffer.to_vec();
+    changed[1234] = 0x22;
+    assert_ne!(ppu::frame_hash(&changed), nes.frame_hash());
+}
+
+#[test]
+fn test_accuracy() {
+    use crate::cartridge::nrom_test_rom;
+
+    let mut nes = Nes::new(Cartridge::from_bytes(&nrom_test_rom(&[])).unwrap());
+    let flags = |nes: &Nes| {
+        (
+            !nes.cpu.skip_dummy_reads,
+            nes.interconnect.ppu.warm_up,
+            nes.interconnect.ppu.odd_frame_skip,
+            nes.interconnect.dmc_controller_conflicts,
+            nes.interconnect.controller_open_bus,
+        )
+    };
+    let defaults = flags(&nes);
+
+    nes.set_accuracy(Accuracy::Cycle);
+    assert_eq!(flags(&nes), (true, true, true, true, true));
+
+    nes.set_accuracy(Accuracy::Fast);
+    assert!(nes.cpu.skip_dummy_reads);
+    assert!(!nes.interconnect.ppu.warm_up);
+    assert!(!nes.interconnect.dmc_controller_conflicts);
+    assert!(!nes.interconnect.controller_open_bus);
+
+    nes.set_accuracy(Accuracy::Balanced);
+    assert_eq!(flags(&nes), defaults);
+}
+
+#[test]
+fn test_controller_type() {
+    use crate::cartridge::nrom_test_rom;
+    use crate::input::Button;
+
+    let mut nes = Nes::new(Cartridge::from_bytes(&nrom_test_rom(&[])).unwrap());
+    nes.interconnect
+        .input
+        .game_pad_2
+        .set_button_pressed(Button::A, true);
+    nes.interconnect.write_byte(0x4016, 1);
+    nes.interconnect.write_byte(0x4016, 0);
+
+    // Port 1 starts out empty, reading open bus
+    assert_eq!(nes.interconnect.read_byte(0x4017), 0x40);
+
+    nes.set_controller_type(1, ControllerType::Zapper);
+    assert_eq!(nes.interconnect.read_byte(0x4017), 0x48);
+    nes.interconnect.input.zapper.light_sensed = true;
+    nes.interconnect.input.zapper.trigger_pulled = true;
+    // The zapper has no shift register, so repeated reads see the same bits
+    for _ in 0..2 {
+        assert_eq!(nes.interconnect.read_byte(0x4017), 0x50);
+    }
+
+    // The port's controller type survives a reset
+    nes.reset();
+    assert_eq!(nes.interconnect.read_byte(0x4017), 0x48);
+
+    nes.set_controller_type(1, ControllerType::Standard);
+    nes.interconnect
+        .input
+        .game_pad_2
+        .set_button_pressed(Button::A, true);
+    nes.interconnect.write_byte(0x4016, 1);
+    nes.interconnect.write_byte(0x4016, 0);
+    assert_eq!(nes.interconnect.read_byte(0x4017), 0x41);
+    assert_eq!(nes.interconnect.read_byte(0x4017), 0x40);
+}
+
+#[test]
+fn test_cycles_with_dmc_dma() {
+    use crate::apu::CountingAudioSink;
+    use crate::cartridge::nrom_test_rom;
+    use crate::sink::NullVideoSink;
+
+    // JMP $8000
+    let cartridge = Cartridge::from_bytes(&nrom_test_rom(&[0x4C, 0x00, 0x80])).unwrap();
+    let mut nes = Nes::new(cartridge);
+    let mut video_sink = NullVideoSink::new();
+    let mut audio_sink = CountingAudioSink(0);
+
+    // Play the longest sample from $C000 at the highest rate, so the DMC keeps stealing cycles
+    nes.interconnect.write_byte(0x4010, 0x0F);
+    nes.interconnect.write_byte(0x4012, 0x00);
+    nes.interconnect.write_byte(0x4013, 0xFF);
+    nes.interconnect.write_byte(0x4015, 0x10);
+
+    let mut stalls = 0;
+    for _ in 0..10_000 {
+        let (cycles, _, _) = nes.step(&mut video_sink, &mut audio_sink);
+        // Every JMP takes 3 cycles, so single cycles are the CPU stalled by DMA
+        if cycles == 1 {
+            stalls += 1;
+        }
+    }
+    assert!(stalls > 0);
+    assert_eq!(nes.cpu.cycles(), nes.total_cycles());
+}
+
+#[test]
+fn test_elapsed() {
+    use crate::apu::CountingAudioSink;
+    use crate::cartridge::nrom_test_rom;
+    use crate::ppu::{CYCLES_PER_FRAME, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    // JMP $8000
+    let cartridge = Cartridge::from_bytes(&nrom_test_rom(&[0x4C, 0x00, 0x80])).unwrap();
+    let mut nes = Nes::new(cartridge);
+    let mut buffer = vec![0u32; SCREEN_WIDTH * SCREEN_HEIGHT];
+    let mut video_sink = Xrgb8888VideoSink::new(&mut buffer);
+    let mut audio_sink = CountingAudioSink(0);
+    assert_eq!((nes.elapsed_frames(), nes.elapsed_cycles()), (0, 0));
+
+    // JMP absolute takes 3 cycles
+    nes.step(&mut video_sink, &mut audio_sink);
+    assert_eq!((nes.cpu.cycles(), nes.total_cycles()), (3, 3));
+
+    let mut cycles = 3;
+    for _ in 0..5 {
+        cycles += nes.run_until_vblank(&mut video_sink, &mut audio_sink);
+    }
+    assert_eq!(nes.elapsed_frames(), 5);
+    assert_eq!(nes.elapsed_cycles(), cycles);
+    assert_eq!(nes.total_cycles(), cycles);
+    assert_eq!(nes.cpu.cycles(), cycles);
+    // Rendering is disabled, so every frame is the same length
+    let frame_cycles = CYCLES_PER_FRAME / 3;
+    assert!(cycles.abs_diff(5 * frame_cycles) <= 5);
+
+    // The totals carry on across a reset, while `total_cycles` starts over
+    nes.reset();
+    assert_eq!(nes.total_cycles(), 0);
+    let frame = nes.run_until_vblank(&mut video_sink, &mut audio_sink);
+    assert_eq!(nes.elapsed_frames(), 6);
+    assert_eq!(nes.total_cycles(), frame);
+    assert_eq!(nes.elapsed_cycles(), cycles + frame);
+}
+
+#[test]
+fn test_region_frame_timing() {
+    use crate::apu::CountingAudioSink;
+    use crate::cpu::Region;
+
+    // (region, PPU cycles per 5 CPU cycles, vblank scanline)
+    let regions = [
+        (Region::Ntsc, 15, 241),
+        (Region::Pal, 16, 241),
+        (Region::Dendy, 15, 291),
+    ];
+    for (region, ppu_cycles_per_5, vblank_scanline) in regions {
+        // JMP $8000
+        let cartridge =
+            Cartridge::from_bytes(&crate::cartridge::nrom_test_rom(&[0x4C, 0x00, 0x80])).unwrap();
+        let mut nes = Nes::new(cartridge);
+        nes.set_region(region);
+        let mut video_sink = NullVideoSink::new();
+        let mut audio_sink = CountingAudioSink(0);
+        nes.run_until_vblank(&mut video_sink, &mut audio_sink);
+
+        // Rendering is disabled, so every frame is the full length
+        let frame = nes.run_until_vblank(&mut video_sink, &mut audio_sink);
+        let frame_ppu_cycles = 341 * region.scanlines_per_frame() as u64;
+        assert!((frame * ppu_cycles_per_5 / 5).abs_diff(frame_ppu_cycles) <= 16);
+
+        // The last scanline before the pre-render one is the frame's last, and vblank starts at
+        // dot 1 of the region's vblank scanline
+        let mut last_scanline = 0;
+        let mut vblank_started_at = None;
+        while nes.ppu_position().0 != -1 {
+            nes.step(&mut video_sink, &mut audio_sink);
+            let (scanline, _) = nes.ppu_position();
+            last_scanline = last_scanline.max(scanline);
+            if vblank_started_at.is_none() && nes.bus_mut().peek_byte(0x2002) & 0x80 != 0 {
+                vblank_started_at = Some(scanline);
+            }
+        }
+        assert_eq!(last_scanline as u16, region.scanlines_per_frame() - 2);
+        assert_eq!(vblank_started_at, Some(vblank_scanline as i16));
+    }
+}
+
+#[test]
+fn test_alignment() {
+    use crate::apu::CountingAudioSink;
+    use crate::cartridge::nrom_test_rom;
+    use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    // Waits out the PPU warm-up, sets the backdrop color and then toggles greyscale as fast as it
+    // can, so where the stripes fall depends on the PPU's phase relative to the CPU
+    #[rustfmt::skip]
+    let program = [
+        0x2C, 0x02, 0x20, // bit $2002
+        0x10, 0xFB,       // bpl $8000
+        0x2C, 0x02, 0x20, // bit $2002
+        0x10, 0xFB,       // bpl $8005
+        0xA9, 0x3F,       // lda #$3F
+        0x8D, 0x06, 0x20, // sta $2006
+        0xA9, 0x00,       // lda #$00
+        0x8D, 0x06, 0x20, // sta $2006
+        0xA9, 0x21,       // lda #$21
+        0x8D, 0x07, 0x20, // sta $2007
+        0xA9, 0x0A,       // lda #$0A
+        0x8D, 0x01, 0x20, // sta $2001
+        0xA9, 0x0B,       // lda #$0B
+        0x8D, 0x01, 0x20, // sta $2001
+        0x4C, 0x19, 0x80, // jmp $8019
+    ];
+    let frame_hash = |nes: Nes| {
+        let mut nes = nes;
+        let mut buffer = vec![0u32; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let mut video_sink = Xrgb8888VideoSink::new(&mut buffer);
+        let mut audio_sink = CountingAudioSink(0);
+        for _ in 0..4 {
+            nes.run_until_vblank(&mut video_sink, &mut audio_sink);
+        }
+        nes.frame_hash()
+    };
+    let new_nes = || Nes::new(Cartridge::from_bytes(&nrom_test_rom(&program)).unwrap());
+
+    assert_eq!(
+        frame_hash(new_nes()),
+        frame_hash(new_nes().with_alignment(0))
+    );
+    assert_ne!(
+        frame_hash(new_nes().with_alignment(0)),
+        frame_hash(new_nes().with_alignment(1))
+    );
+    assert_ne!(
+        frame_hash(new_nes().with_alignment(1)),
+        frame_hash(new_nes().with_alignment(2))
+    );
+}
@@ -256,8 +256,10 @@ macro_rules! handle_opcode {
             0x9F => $this.ahx($mem, AddressMode::AbsoluteIndexed(Register8::Y)),
             0x9C => $this.sya($mem),
             0x9E => $this.sxa($mem),
+            opcode @ (0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xB2
+            | 0xD2 | 0xF2) => $this.jam($mem, opcode),
 
-            _ => panic!("Unimplemented op code {:02X}", $opcode),
+            opcode => $this.unknown($mem, opcode),
         }
     };
 }
@@ -0,0 +1,406 @@
+// Static description of every 6502 opcode, for tools that want to introspect the CPU's coverage
+// without decoding instructions. Mnemonics follow the names used by the CPU and disassembler.
+
+use self::AddressingMode::*;
+use self::OpKind::*;
+
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndexedIndirect,
+    IndirectIndexed,
+    Relative,
+}
+
+impl AddressingMode {
+    // Instruction length including the opcode byte
+    pub const fn bytes(self) -> u8 {
+        match self {
+            Implied | Accumulator => 1,
+            Immediate | ZeroPage | ZeroPageX | ZeroPageY | IndexedIndirect | IndirectIndexed
+            | Relative => 2,
+            Absolute | AbsoluteX | AbsoluteY | Indirect => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpKind {
+    Official,
+    Unofficial,
+    // Unofficial opcodes the CPU doesn't execute as instructions: the ones that jam the CPU, and
+    // unstable ones it traps on
+    Unimplemented,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpInfo {
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    pub bytes: u8,
+    // Base cycle count, not including the extra cycles for page crossings and taken branches
+    pub cycles: u8,
+    pub kind: OpKind,
+}
+
+impl OpInfo {
+    // Cycles the instruction can take: a taken branch adds one and a branch to another page one
+    // more, and reads through an indexed address add one when the index crosses a page. Stores
+    // and read-modify-write instructions always spend that cycle, so it's in their base count.
+    pub fn cycle_range(&self) -> RangeInclusive<u32> {
+        let base = self.cycles as u32;
+        let extra = match self.mode {
+            Relative => 2,
+            AbsoluteX | AbsoluteY | IndirectIndexed => 1,
+            _ => 0,
+        };
+        base..=base + extra
+    }
+}
+
+const fn op(mnemonic: &'static str, mode: AddressingMode, cycles: u8, kind: OpKind) -> OpInfo {
+    OpInfo {
+        mnemonic,
+        mode,
+        bytes: mode.bytes(),
+        cycles,
+        kind,
+    }
+}
+
+// BRK is implied, but skips a signature byte after its opcode
+const fn brk() -> OpInfo {
+    OpInfo {
+        bytes: 2,
+        ..op("BRK", Implied, 7, Official)
+    }
+}
+
+// Indexed by opcode
+static OPCODE_TABLE: [OpInfo; 256] = [
+    brk(),                                     // 0x00
+    op("ORA", IndexedIndirect, 6, Official),   // 0x01
+    op("JAM", Implied, 0, Unimplemented),      // 0x02
+    op("SLO", IndexedIndirect, 8, Unofficial), // 0x03
+    op("IGN", ZeroPage, 3, Unofficial),        // 0x04
+    op("ORA", ZeroPage, 3, Official),          // 0x05
+    op("ASL", ZeroPage, 5, Official),          // 0x06
+    op("SLO", ZeroPage, 5, Unofficial),        // 0x07
+    op("PHP", Implied, 3, Official),           // 0x08
+    op("ORA", Immediate, 2, Official),         // 0x09
+    op("ASL", Accumulator, 2, Official),       // 0x0A
+    op("ANC", Immediate, 2, Unofficial),       // 0x0B
+    op("IGN", Absolute, 4, Unofficial),        // 0x0C
+    op("ORA", Absolute, 4, Official),          // 0x0D
+    op("ASL", Absolute, 6, Official),          // 0x0E
+    op("SLO", Absolute, 6, Unofficial),        // 0x0F
+    op("BPL", Relative, 2, Official),          // 0x10
+    op("ORA", IndirectIndexed, 5, Official),   // 0x11
+    op("JAM", Implied, 0, Unimplemented),      // 0x12
+    op("SLO", IndirectIndexed, 8, Unofficial), // 0x13
+    op("IGN", ZeroPageX, 4, Unofficial),       // 0x14
+    op("ORA", ZeroPageX, 4, Official),         // 0x15
+    op("ASL", ZeroPageX, 6, Official),         // 0x16
+    op("SLO", ZeroPageX, 6, Unofficial),       // 0x17
+    op("CLC", Implied, 2, Official),           // 0x18
+    op("ORA", AbsoluteY, 4, Official),         // 0x19
+    op("NOP", Implied, 2, Unofficial),         // 0x1A
+    op("SLO", AbsoluteY, 7, Unofficial),       // 0x1B
+    op("IGN", AbsoluteX, 4, Unofficial),       // 0x1C
+    op("ORA", AbsoluteX, 4, Official),         // 0x1D
+    op("ASL", AbsoluteX, 7, Official),         // 0x1E
+    op("SLO", AbsoluteX, 7, Unofficial),       // 0x1F
+    op("JSR", Absolute, 6, Official),          // 0x20
+    op("AND", IndexedIndirect, 6, Official),   // 0x21
+    op("JAM", Implied, 0, Unimplemented),      // 0x22
+    op("RLA", IndexedIndirect, 8, Unofficial), // 0x23
+    op("BIT", ZeroPage, 3, Official),          // 0x24
+    op("AND", ZeroPage, 3, Official),          // 0x25
+    op("ROL", ZeroPage, 5, Official),          // 0x26
+    op("RLA", ZeroPage, 5, Unofficial),        // 0x27
+    op("PLP", Implied, 4, Official),           // 0x28
+    op("AND", Immediate, 2, Official),         // 0x29
+    op("ROL", Accumulator, 2, Official),       // 0x2A
+    op("ANC", Immediate, 2, Unofficial),       // 0x2B
+    op("BIT", Absolute, 4, Official),          // 0x2C
+    op("AND", Absolute, 4, Official),          // 0x2D
+    op("ROL", Absolute, 6, Official),          // 0x2E
+    op("RLA", Absolute, 6, Unofficial),        // 0x2F
+    op("BMI", Relative, 2, Official),          // 0x30
+    op("AND", IndirectIndexed, 5, Official),   // 0x31
+    op("JAM", Implied, 0, Unimplemented),      // 0x32
+    op("RLA", IndirectIndexed, 8, Unofficial), // 0x33
+    op("IGN", ZeroPageX, 4, Unofficial),       // 0x34
+    op("AND", ZeroPageX, 4, Official),         // 0x35
+    op("ROL", ZeroPageX, 6, Official),         // 0x36
+    op("RLA", ZeroPageX, 6, Unofficial),       // 0x37
+    op("SEC", Implied, 2, Official),           // 0x38
+    op("AND", AbsoluteY, 4, Official),         // 0x39
+    op("NOP", Implied, 2, Unofficial),         // 0x3A
+    op("RLA", AbsoluteY, 7, Unofficial),       // 0x3B
+    op("IGN", AbsoluteX, 4, Unofficial),       // 0x3C
+    op("AND", AbsoluteX, 4, Official),         // 0x3D
+    op("ROL", AbsoluteX, 7, Official),         // 0x3E
+    op("RLA", AbsoluteX, 7, Unofficial),       // 0x3F
+    op("RTI", Implied, 6, Official),           // 0x40
+    op("EOR", IndexedIndirect, 6, Official),   // 0x41
+    op("JAM", Implied, 0, Unimplemented),      // 0x42
+    op("SRE", IndexedIndirect, 8, Unofficial), // 0x43
+    op("IGN", ZeroPage, 3, Unofficial),        // 0x44
+    op("EOR", ZeroPage, 3, Official),          // 0x45
+    op("LSR", ZeroPage, 5, Official),          // 0x46
+    op("SRE", ZeroPage, 5, Unofficial),        // 0x47
+    op("PHA", Implied, 3, Official),           // 0x48
+    op("EOR", Immediate, 2, Official),         // 0x49
+    op("LSR", Accumulator, 2, Official),       // 0x4A
+    op("ALR", Immediate, 2, Unofficial),       // 0x4B
+    op("JMP", Absolute, 3, Official),          // 0x4C
+    op("EOR", Absolute, 4, Official),          // 0x4D
+    op("LSR", Absolute, 6, Official),          // 0x4E
+    op("SRE", Absolute, 6, Unofficial),        // 0x4F
+    op("BVC", Relative, 2, Official),          // 0x50
+    op("EOR", IndirectIndexed, 5, Official),   // 0x51
+    op("JAM", Implied, 0, Unimplemented),      // 0x52
+    op("SRE", IndirectIndexed, 8, Unofficial), // 0x53
+    op("IGN", ZeroPageX, 4, Unofficial),       // 0x54
+    op("EOR", ZeroPageX, 4, Official),         // 0x55
+    op("LSR", ZeroPageX, 6, Official),         // 0x56
+    op("SRE", ZeroPageX, 6, Unofficial),       // 0x57
+    op("CLI", Implied, 2, Official),           // 0x58
+    op("EOR", AbsoluteY, 4, Official),         // 0x59
+    op("NOP", Implied, 2, Unofficial),         // 0x5A
+    op("SRE", AbsoluteY, 7, Unofficial),       // 0x5B
+    op("IGN", AbsoluteX, 4, Unofficial),       // 0x5C
+    op("EOR", AbsoluteX, 4, Official),         // 0x5D
+    op("LSR", AbsoluteX, 7, Official),         // 0x5E
+    op("SRE", AbsoluteX, 7, Unofficial),       // 0x5F
+    op("RTS", Implied, 6, Official),           // 0x60
+    op("ADC", IndexedIndirect, 6, Official),   // 0x61
+    op("JAM", Implied, 0, Unimplemented),      // 0x62
+    op("RRA", IndexedIndirect, 8, Unofficial), // 0x63
+    op("IGN", ZeroPage, 3, Unofficial),        // 0x64
+    op("ADC", ZeroPage, 3, Official),          // 0x65
+    op("ROR", ZeroPage, 5, Official),          // 0x66
+    op("RRA", ZeroPage, 5, Unofficial),        // 0x67
+    op("PLA", Implied, 4, Official),           // 0x68
+    op("ADC", Immediate, 2, Official),         // 0x69
+    op("ROR", Accumulator, 2, Official),       // 0x6A
+    op("ARR", Immediate, 2, Unofficial),       // 0x6B
+    op("JMP", Indirect, 5, Official),          // 0x6C
+    op("ADC", Absolute, 4, Official),          // 0x6D
+    op("ROR", Absolute, 6, Official),          // 0x6E
+    op("RRA", Absolute, 6, Unofficial),        // 0x6F
+    op("BVS", Relative, 2, Official),          // 0x70
+    op("ADC", IndirectIndexed, 5, Official),   // 0x71
+    op("JAM", Implied, 0, Unimplemented),      // 0x72
+    op("RRA", IndirectIndexed, 8, Unofficial), // 0x73
+    op("IGN", ZeroPageX, 4, Unofficial),       // 0x74
+    op("ADC", ZeroPageX, 4, Official),         // 0x75
+    op("ROR", ZeroPageX, 6, Official),         // 0x76
+    op("RRA", ZeroPageX, 6, Unofficial),       // 0x77
+    op("SEI", Implied, 2, Official),           // 0x78
+    op("ADC", AbsoluteY, 4, Official),         // 0x79
+    op("NOP", Implied, 2, Unofficial),         // 0x7A
+    op("RRA", AbsoluteY, 7, Unofficial),       // 0x7B
+    op("IGN", AbsoluteX, 4, Unofficial),       // 0x7C
+    op("ADC", AbsoluteX, 4, Official),         // 0x7D
+    op("ROR", AbsoluteX, 7, Official),         // 0x7E
+    op("RRA", AbsoluteX, 7, Unofficial),       // 0x7F
+    op("SKB", Immediate, 2, Unofficial),       // 0x80
+    op("STA", IndexedIndirect, 6, Official),   // 0x81
+    op("SKB", Immediate, 2, Unofficial),       // 0x82
+    op("SAX", IndexedIndirect, 6, Unofficial), // 0x83
+    op("STY", ZeroPage, 3, Official),          // 0x84
+    op("STA", ZeroPage, 3, Official),          // 0x85
+    op("STX", ZeroPage, 3, Official),          // 0x86
+    op("SAX", ZeroPage, 3, Unofficial),        // 0x87
+    op("DEY", Implied, 2, Official),           // 0x88
+    op("SKB", Immediate, 2, Unofficial),       // 0x89
+    op("TXA", Implied, 2, Official),           // 0x8A
+    op("XAA", Immediate, 2, Unofficial),       // 0x8B
+    op("STY", Absolute, 4, Official),          // 0x8C
+    op("STA", Absolute, 4, Official),          // 0x8D
+    op("STX", Absolute, 4, Official),          // 0x8E
+    op("SAX", Absolute, 4, Unofficial),        // 0x8F
+    op("BCC", Relative, 2, Official),          // 0x90
+    op("STA", IndirectIndexed, 6, Official),   // 0x91
+    op("JAM", Implied, 0, Unimplemented),      // 0x92
+    op("AHX", IndirectIndexed, 6, Unofficial), // 0x93
+    op("STY", ZeroPageX, 4, Official),         // 0x94
+    op("STA", ZeroPageX, 4, Official),         // 0x95
+    op("STX", ZeroPageY, 4, Official),         // 0x96
+    op("SAX", ZeroPageY, 4, Unofficial),       // 0x97
+    op("TYA", Implied, 2, Official),           // 0x98
+    op("STA", AbsoluteY, 5, Official),         // 0x99
+    op("TXS", Implied, 2, Official),           // 0x9A
+    op("TAS", AbsoluteY, 5, Unimplemented),    // 0x9B
+    op("SYA", AbsoluteX, 5, Unofficial),       // 0x9C
+    op("STA", AbsoluteX, 5, Official),         // 0x9D
+    op("SXA", AbsoluteY, 5, Unofficial),       // 0x9E
+    op("AHX", AbsoluteY, 5, Unofficial),       // 0x9F
+    op("LDY", Immediate, 2, Official),         // 0xA0
+    op("LDA", IndexedIndirect, 6, Official),   // 0xA1
+    op("LDX", Immediate, 2, Official),         // 0xA2
+    op("LAX", IndexedIndirect, 6, Unofficial), // 0xA3
+    op("LDY", ZeroPage, 3, Official),          // 0xA4
+    op("LDA", ZeroPage, 3, Official),          // 0xA5
+    op("LDX", ZeroPage, 3, Official),          // 0xA6
+    op("LAX", ZeroPage, 3, Unofficial),        // 0xA7
+    op("TAY", Implied, 2, Official),           // 0xA8
+    op("LDA", Immediate, 2, Official),         // 0xA9
+    op("TAX", Implied, 2, Official),           // 0xAA
+    op("LAX", Immediate, 2, Unofficial),       // 0xAB
+    op("LDY", Absolute, 4, Official),          // 0xAC
+    op("LDA", Absolute, 4, Official),          // 0xAD
+    op("LDX", Absolute, 4, Official),          // 0xAE
+    op("LAX", Absolute, 4, Unofficial),        // 0xAF
+    op("BCS", Relative, 2, Official),          // 0xB0
+    op("LDA", IndirectIndexed, 5, Official),   // 0xB1
+    op("JAM", Implied, 0, Unimplemented),      // 0xB2
+    op("LAX", IndirectIndexed, 5, Unofficial), // 0xB3
+    op("LDY", ZeroPageX, 4, Official),         // 0xB4
+    op("LDA", ZeroPageX, 4, Official),         // 0xB5
+    op("LDX", ZeroPageY, 4, Official),         // 0xB6
+    op("LAX", ZeroPageY, 4, Unofficial),       // 0xB7
+    op("CLV", Implied, 2, Official),           // 0xB8
+    op("LDA", AbsoluteY, 4, Official),         // 0xB9
+    op("TSX", Implied, 2, Official),           // 0xBA
+    op("LAS", AbsoluteY, 4, Unimplemented),    // 0xBB
+    op("LDY", AbsoluteX, 4, Official),         // 0xBC
+    op("LDA", AbsoluteX, 4, Official),         // 0xBD
+    op("LDX", AbsoluteY, 4, Official),         // 0xBE
+    op("LAX", AbsoluteY, 4, Unofficial),       // 0xBF
+    op("CPY", Immediate, 2, Official),         // 0xC0
+    op("CMP", IndexedIndirect, 6, Official),   // 0xC1
+    op("SKB", Immediate, 2, Unofficial),       // 0xC2
+    op("DCP", IndexedIndirect, 8, Unofficial), // 0xC3
+    op("CPY", ZeroPage, 3, Official),          // 0xC4
+    op("CMP", ZeroPage, 3, Official),          // 0xC5
+    op("DEC", ZeroPage, 5, Official),          // 0xC6
+    op("DCP", ZeroPage, 5, Unofficial),        // 0xC7
+    op("INY", Implied, 2, Official),           // 0xC8
+    op("CMP", Immediate, 2, Official),         // 0xC9
+    op("DEX", Implied, 2, Official),           // 0xCA
+    op("AXS", Immediate, 2, Unofficial),       // 0xCB
+    op("CPY", Absolute, 4, Official),          // 0xCC
+    op("CMP", Absolute, 4, Official),          // 0xCD
+    op("DEC", Absolute, 6, Official),          // 0xCE
+    op("DCP", Absolute, 6, Unofficial),        // 0xCF
+    op("BNE", Relative, 2, Official),          // 0xD0
+    op("CMP", IndirectIndexed, 5, Official),   // 0xD1
+    op("JAM", Implied, 0, Unimplemented),      // 0xD2
+    op("DCP", IndirectIndexed, 8, Unofficial), // 0xD3
+    op("IGN", ZeroPageX, 4, Unofficial),       // 0xD4
+    op("CMP", ZeroPageX, 4, Official),         // 0xD5
+    op("DEC", ZeroPageX, 6, Official),         // 0xD6
+    op("DCP", ZeroPageX, 6, Unofficial),       // 0xD7
+    op("CLD", Implied, 2, Official),           // 0xD8
+    op("CMP", AbsoluteY, 4, Official),         // 0xD9
+    op("NOP", Implied, 2, Unofficial),         // 0xDA
+    op("DCP", AbsoluteY, 7, Unofficial),       // 0xDB
+    op("IGN", AbsoluteX, 4, Unofficial),       // 0xDC
+    op("CMP", AbsoluteX, 4, Official),         // 0xDD
+    op("DEC", AbsoluteX, 7, Official),         // 0xDE
+    op("DCP", AbsoluteX, 7, Unofficial),       // 0xDF
+    op("CPX", Immediate, 2, Official),         // 0xE0
+    op("SBC", IndexedIndirect, 6, Official),   // 0xE1
+    op("SKB", Immediate, 2, Unofficial),       // 0xE2
+    op("ISC", IndexedIndirect, 8, Unofficial), // 0xE3
+    op("CPX", ZeroPage, 3, Official),          // 0xE4
+    op("SBC", ZeroPage, 3, Official),          // 0xE5
+    op("INC", ZeroPage, 5, Official),          // 0xE6
+    op("ISC", ZeroPage, 5, Unofficial),        // 0xE7
+    op("INX", Implied, 2, Official),           // 0xE8
+    op("SBC", Immediate, 2, Official),         // 0xE9
+    op("NOP", Implied, 2, Official),           // 0xEA
+    op("SBC", Immediate, 2, Unofficial),       // 0xEB
+    op("CPX", Absolute, 4, Official),          // 0xEC
+    op("SBC", Absolute, 4, Official),          // 0xED
+    op("INC", Absolute, 6, Official),          // 0xEE
+    op("ISC", Absolute, 6, Unofficial),        // 0xEF
+    op("BEQ", Relative, 2, Official),          // 0xF0
+    op("SBC", IndirectIndexed, 5, Official),   // 0xF1
+    op("JAM", Implied, 0, Unimplemented),      // 0xF2
+    op("ISC", IndirectIndexed, 8, Unofficial), // 0xF3
+    op("IGN", ZeroPageX, 4, Unofficial),       // 0xF4
+    op("SBC", ZeroPageX, 4, Official),         // 0xF5
+    op("INC", ZeroPageX, 6, Official),         // 0xF6
+    op("ISC", ZeroPageX, 6, Unofficial),       // 0xF7
+    op("SED", Implied, 2, Official),           // 0xF8
+    op("SBC", AbsoluteY, 4, Official),         // 0xF9
+    op("NOP", Implied, 2, Unofficial),         // 0xFA
+    op("ISC", AbsoluteY, 7, Unofficial),       // 0xFB
+    op("IGN", AbsoluteX, 4, Unofficial),       // 0xFC
+    op("SBC", AbsoluteX, 4, Official),         // 0xFD
+    op("INC", AbsoluteX, 7, Official),         // 0xFE
+    op("ISC", AbsoluteX, 7, Unofficial),       // 0xFF
+];
+
+pub fn opcode_table() -> &'static [OpInfo; 256] {
+    &OPCODE_TABLE
+}
+
+#[test]
+fn test_opcode_table() {
+    let table = opcode_table();
+    assert_eq!(table.len(), 256);
+
+    let lda = table[0xA9];
+    assert_eq!(lda.mnemonic, "LDA");
+    assert_eq!(lda.mode, Immediate);
+    assert_eq!(lda.bytes, 2);
+    assert_eq!(lda.cycles, 2);
+    assert_eq!(lda.kind, Official);
+
+    assert_eq!(table[0x00].bytes, 2);
+    assert_eq!(table[0x6C].mode, Indirect);
+    assert_eq!(table[0xA7].kind, Unofficial);
+    assert_eq!(table[0x02].kind, Unimplemented);
+    assert_eq!(
+        table.iter().filter(|info| info.kind == Official).count(),
+        151
+    );
+}
+
+#[test]
+fn test_opcode_table_matches_disassembler() {
+    use crate::cpu::TestMemory;
+    use crate::disassembler::Disassembler;
+
+    for (opcode, info) in opcode_table().iter().enumerate() {
+        let mut mem = TestMemory(vec![0; 0x10000]);
+        mem.0[0] = opcode as u8;
+        let disassembly = Disassembler::new(0).disassemble_next(&mut mem);
+        let mnemonic = disassembly.split(' ').next().unwrap();
+
+        if info.kind == Unimplemented {
+            assert_eq!(mnemonic, ".byte", "opcode {:02X}", opcode);
+        } else {
+            assert_eq!(
+                mnemonic,
+                info.mnemonic.to_lowercase(),
+                "opcode {:02X}",
+                opcode
+            );
+        }
+    }
+}
+
+#[test]
+fn test_cycle_range() {
+    let table = opcode_table();
+    assert_eq!(table[0xA9].cycle_range(), 2..=2);
+    assert_eq!(table[0xBD].cycle_range(), 4..=5);
+    assert_eq!(table[0xD0].cycle_range(), 2..=4);
+}
@@ -1,4 +1,4 @@
-use crate::cpu::{Cpu, Interrupt};
+use crate::cpu::{Cpu, Interrupt, Region};
 use crate::mapper::{Mapper, MapperEnum};
 use crate::memory::Memory;
 use crate::sink::*;
@@ -16,11 +16,45 @@ pub const SCREEN_WIDTH: usize = 256;
 pub const SCREEN_HEIGHT: usize = 240;
 
 const CYCLES_PER_SCANLINE: u64 = 341;
+// PPU cycles after power-on in which some register writes are ignored, ~29658 CPU cycles
+const WARM_UP_CYCLES: u64 = 3 * 29658;
+// NTSC's; PAL and Dendy frames are `Region::scanlines_per_frame` long
+pub const SCANLINES_PER_FRAME: u16 = 262;
+#[cfg(test)]
+pub(crate) const CYCLES_PER_FRAME: u64 = CYCLES_PER_SCANLINE * SCANLINES_PER_FRAME as u64;
 
 const VISIBLE_START_SCANLINE: u16 = 0;
 pub const VISIBLE_END_SCANLINE: u16 = 239;
-const VBLANK_START_SCANLINE: u16 = 241;
-const PRE_RENDER_SCANLINE: u16 = 261;
+
+// 64-bit FNV-1a hash of a palette-indexed frame, for comparing frames against known good ones
+pub fn frame_hash(frame_buffer: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01B3;
+
+    frame_buffer.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    })
+}
+
+// The background tile that produced a screen pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileInfo {
+    pub nametable_addr: u16,
+    pub tile_index: u8,
+    pub attribute: u8,
+    // Background palette 0-3 selected by the attribute byte
+    pub palette: u8,
+}
+
+// Sprites in each row of a `SpriteSheet`
+pub const SPRITE_SHEET_COLUMNS: usize = 8;
+
+// Palette indexes of the sprites in OAM, laid out in a grid by `Ppu::sprite_sheet`
+pub struct SpriteSheet {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
 
 // Memory-mapped register addresses
 const PPUCTRL_ADDRESS: u16 = 0x2000;
@@ -91,6 +125,14 @@ pub struct Ppu {
 
     nmi_occurred: bool,
     nmi_output: bool,
+
+    // Ignore writes to PPUCTRL, PPUMASK, PPUSCROLL and PPUADDR for the first WARM_UP_CYCLES after
+    // power-on, as the PPU does. Some games only work with or without it.
+    pub warm_up: bool,
+    // Skip the last dot of the pre-render scanline on odd frames while rendering is enabled
+    pub odd_frame_skip: bool,
+    // Decides the frame length and when vblank starts
+    region: Region,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -150,6 +192,9 @@ impl Ppu {
             sprite_0_on_scanline: false,
             nmi_occurred: false,
             nmi_output: false,
+            warm_up: true,
+            odd_frame_skip: true,
+            region: Region::default(),
         }
     }
 
@@ -211,8 +256,71 @@ impl Ppu {
         self.nmi_output = state.nmi_output;
     }
 
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    fn pre_render_scanline(&self) -> u16 {
+        self.region.scanlines_per_frame() - 1
+    }
+
+    // Palette indexes of the frame being rendered, which is complete once `frame` has moved on
+    pub fn frame_buffer(&self) -> &[u8] {
+        &self.frame_buffer
+    }
+
+    // Primary OAM, 4 bytes for each of the 64 sprites
+    pub fn oam(&self) -> &[u8] {
+        &self.oam
+    }
+
+    // The current scanline, with -1 for the pre-render scanline, and the dot within it
+    pub fn position(&self) -> (i16, u16) {
+        let scanline = if self.scanline == self.pre_render_scanline() {
+            -1
+        } else {
+            self.scanline as i16
+        };
+        (scanline, self.scanline_cycle() as u16)
+    }
+
+    // The background tile at screen pixel (`x`, `y`), using the scroll set through $2000/$2005
+    // for the top of the frame. Mid-frame scroll changes are not taken into account.
+    pub fn tile_at_pixel(&mut self, x: u8, y: u8) -> TileInfo {
+        let t = self.regs.t;
+        let scroll_x = ((t & 0x001F) << 3) | self.regs.x as u16;
+        let scroll_y = (((t >> 5) & 0x001F) << 3) | ((t >> 12) & 0x07);
+        let x = scroll_x + ((t >> 10) & 0x01) * 256 + x as u16;
+        // Coarse Y values past 29 wrap into the same nametable rather than the next one
+        let y = scroll_y.min(239) + ((t >> 11) & 0x01) * 240 + y as u16;
+        let (x, y) = (x % 512, y % 480);
+
+        let name_table = (x / 256) | ((y / 240) << 1);
+        let coarse_x = (x % 256) / 8;
+        let coarse_y = (y % 240) / 8;
+
+        let nametable_addr = 0x2000 | (name_table << 10) | (coarse_y << 5) | coarse_x;
+        let attribute_addr = 0x23C0 | (name_table << 10) | ((coarse_y >> 2) << 3) | (coarse_x >> 2);
+        let tile_index = self.mem.read_byte(nametable_addr);
+        let attribute = self.mem.read_byte(attribute_addr);
+        let shift = ((coarse_y & 0x02) << 1) | (coarse_x & 0x02);
+
+        TileInfo {
+            nametable_addr,
+            tile_index,
+            attribute,
+            palette: (attribute >> shift) & 0x03,
+        }
+    }
+
     pub fn reset(&mut self) {
         self.cycles = 0;
+        self.scanline = VISIBLE_START_SCANLINE;
+        self.scanline_start_cycle = 0;
         self.frame = 0;
         *self.regs.ppu_ctrl = 0;
         self.regs.ppu_mask = PpuMask::NONE;
@@ -224,14 +332,18 @@ impl Ppu {
         // http://wiki.nesdev.com/w/index.php/PPU_scrolling#.242002_read
         self.regs.w = WriteToggle::FirstWrite;
 
-        let vblank = if self.nmi_occurred { 0x80 } else { 0x00 };
-        let status = vblank | (self.regs.ppu_status.bits() & 0x60) | (self.ppu_gen_latch & 0x1F);
+        let status = self.ppu_status();
 
         self.nmi_occurred = false;
 
         status
     }
 
+    fn ppu_status(&self) -> u8 {
+        let vblank = if self.nmi_occurred { 0x80 } else { 0x00 };
+        vblank | (self.regs.ppu_status.bits() & 0x60) | (self.ppu_gen_latch & 0x1F)
+    }
+
     fn read_oam_byte(&self) -> u8 {
         // http://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
         if self.scanline <= VISIBLE_END_SCANLINE {
@@ -284,7 +396,8 @@ impl Ppu {
     fn inc_ppu_addr(&mut self) {
         // http://wiki.nesdev.com/w/index.php/PPU_scrolling#.242007_reads_and_writes
         if self.rendering_enabled()
-            && (self.scanline == PRE_RENDER_SCANLINE || self.scanline <= VISIBLE_END_SCANLINE)
+            && (self.scanline == self.pre_render_scanline()
+                || self.scanline <= VISIBLE_END_SCANLINE)
         {
             self.inc_coarse_x_with_wrap();
             self.inc_y_with_wrap();
@@ -460,10 +573,26 @@ impl Ppu {
         let sprite =
             Sprite::from_oam_bytes(&self.oam.secondary[index..(index + Oam::BYTES_PER_SPRITE)]);
 
-        let size = self.regs.ppu_ctrl.sprite_size();
+        let row = self.scanline - sprite.y as u16;
+        let (pattern_lo, pattern_hi) = self.sprite_pattern_row(sprite, row);
 
-        let mut row = self.scanline - sprite.y as u16;
-        let pattern_addr = match size {
+        self.sprite_pattern_shifts_lo[sprite_index] = pattern_lo;
+        self.sprite_pattern_shifts_hi[sprite_index] = pattern_hi;
+        self.sprite_attribute_latches[sprite_index] = sprite.attributes;
+        self.sprite_x_counters[sprite_index] = sprite.x;
+    }
+
+    // The low and high pattern bytes of `row` of `sprite` as drawn, flipped as the sprite is
+    fn sprite_pattern_row(&mut self, sprite: Sprite, row: u16) -> (u8, u8) {
+        let pattern_addr = self.sprite_pattern_address(sprite, row);
+        let pattern_lo = self.mem.read_byte(pattern_addr);
+        let pattern_hi = self.mem.read_byte(pattern_addr + 8);
+        sprite.pattern_row_as_drawn(pattern_lo, pattern_hi)
+    }
+
+    // Address of the low pattern byte of `row` of `sprite`, after vertical flipping
+    fn sprite_pattern_address(&self, sprite: Sprite, mut row: u16) -> u16 {
+        match self.regs.ppu_ctrl.sprite_size() {
             SpriteSize::Size8x8 => {
                 if sprite.flip_vertically() {
                     row = 7 - row;
@@ -492,20 +621,53 @@ impl Ppu {
 
                 table + (tile_index as u16) * 16 + row
             }
-        };
-
-        let mut pattern_lo = self.mem.read_byte(pattern_addr);
-        let mut pattern_hi = self.mem.read_byte(pattern_addr + 8);
+        }
+    }
 
-        if sprite.flip_horizontally() {
-            pattern_lo = pattern_lo.swap_bits();
-            pattern_hi = pattern_hi.swap_bits();
+    // Draws the sprites in OAM that are on screen into a grid of `SPRITE_SHEET_COLUMNS` cells of
+    // one sprite each, in OAM order, with transparent pixels in the backdrop color
+    pub fn sprite_sheet(&mut self) -> SpriteSheet {
+        let sprites: Vec<Sprite> = self
+            .oam
+            .primary
+            .chunks(Oam::BYTES_PER_SPRITE)
+            .map(Sprite::from_oam_bytes)
+            .filter(|sprite| sprite.y < 0xEF)
+            .collect();
+
+        let cell_height = self.regs.ppu_ctrl.sprite_size().height() as usize;
+        let width = SPRITE_SHEET_COLUMNS * 8;
+        let height = sprites.len().div_ceil(SPRITE_SHEET_COLUMNS) * cell_height;
+        let backdrop = self.color_from_palette_index(0);
+        let mut pixels = vec![backdrop; width * height];
+
+        for (i, &sprite) in sprites.iter().enumerate() {
+            let left = (i % SPRITE_SHEET_COLUMNS) * 8;
+            let top = (i / SPRITE_SHEET_COLUMNS) * cell_height;
+            for row in 0..cell_height {
+                // Peek, so dumping sprites can't flip mappers' CHR latches
+                let address = self.sprite_pattern_address(sprite, row as u16);
+                let (pattern_lo, pattern_hi) = sprite.pattern_row_as_drawn(
+                    self.mem.peek_byte(address),
+                    self.mem.peek_byte(address + 8),
+                );
+                for column in 0..8 {
+                    let color = ((pattern_lo >> (7 - column)) & 0x01)
+                        | (((pattern_hi >> (7 - column)) & 0x01) << 1);
+                    if color != 0 {
+                        let index = (sprite.attributes.palette() << 2) | color;
+                        pixels[(top + row) * width + left + column] =
+                            self.color_from_palette_index(index);
+                    }
+                }
+            }
         }
 
-        self.sprite_pattern_shifts_lo[sprite_index] = pattern_lo;
-        self.sprite_pattern_shifts_hi[sprite_index] = pattern_hi;
-        self.sprite_attribute_latches[sprite_index] = sprite.attributes;
-        self.sprite_x_counters[sprite_index] = sprite.x;
+        SpriteSheet {
+            width,
+            height,
+            pixels,
+        }
     }
 
     fn update_sprite_rendering_registers(&mut self) {
@@ -560,7 +722,14 @@ impl Ppu {
             }
         };
 
-        self.frame_buffer[(y as usize * SCREEN_WIDTH) + x as usize] = color & 0x3F;
+        // Greyscale forces the color into the grey column of the palette
+        let color = if self.regs.ppu_mask.contains(PpuMask::GREYSCALE) {
+            color & 0x30
+        } else {
+            color & 0x3F
+        };
+
+        self.frame_buffer[(y as usize * SCREEN_WIDTH) + x as usize] = color;
     }
 
     // Set pixel to black.
@@ -651,7 +820,7 @@ impl Ppu {
     pub fn step<V: VideoSink>(&mut self, cpu: &mut Cpu, video_frame_sink: &mut V) {
         let scanline_cycle = self.scanline_cycle();
 
-        let on_prerender_scanline = self.scanline == PRE_RENDER_SCANLINE;
+        let on_prerender_scanline = self.scanline == self.pre_render_scanline();
         let on_visible_scanline = self.scanline <= VISIBLE_END_SCANLINE;
         let on_visible_cycle = (1..=256).contains(&scanline_cycle);
 
@@ -708,7 +877,7 @@ impl Ppu {
                     }
                 }
 
-                if self.scanline == PRE_RENDER_SCANLINE && (280..=304).contains(&scanline_cycle) {
+                if on_prerender_scanline && (280..=304).contains(&scanline_cycle) {
                     // Copy bits related to vertical position from t to v
                     self.regs.v = (self.regs.v & !0x7BE0) | (self.regs.t & 0x7BE0);
                 }
@@ -751,32 +920,29 @@ impl Ppu {
             }
         }
 
-        match self.scanline {
-            VBLANK_START_SCANLINE => {
-                if scanline_cycle == 1 {
-                    self.set_vblank();
-                    if self.nmi_output && self.nmi_occurred {
-                        cpu.request_interrupt(Interrupt::Nmi);
-                    }
+        if scanline_cycle == 1 {
+            if self.scanline == self.region.vblank_scanline() {
+                self.set_vblank();
+                if self.nmi_output && self.nmi_occurred {
+                    cpu.request_interrupt(Interrupt::Nmi);
                 }
+            } else if on_prerender_scanline {
+                self.clear_vblank();
+                self.regs.ppu_status.set(PpuStatus::SPRITE_OVERFLOW, false);
+                self.regs.ppu_status.set(PpuStatus::SPRITE_ZERO_HIT, false);
             }
-            PRE_RENDER_SCANLINE => {
-                if scanline_cycle == 1 {
-                    self.clear_vblank();
-                    self.regs.ppu_status.set(PpuStatus::SPRITE_OVERFLOW, false);
-                    self.regs.ppu_status.set(PpuStatus::SPRITE_ZERO_HIT, false);
-                }
-            }
-            _ => (),
         }
 
         self.cycles += 1;
 
         // End of scanline
         if scanline_cycle >= CYCLES_PER_SCANLINE - 1 ||
-            // On pre-render scanline, for odd frames, the cycle at the end of the scanline is skipped
-            (self.rendering_enabled() &&
-            self.scanline == PRE_RENDER_SCANLINE &&
+            // On pre-render scanline, for odd frames, the cycle at the end of the scanline is skipped.
+            // PAL and Dendy PPUs render every frame in full.
+            (self.odd_frame_skip &&
+            self.region == Region::Ntsc &&
+            self.rendering_enabled() &&
+            on_prerender_scanline &&
             scanline_cycle == CYCLES_PER_SCANLINE - 2 &&
             self.frame % 2 != 0)
         {
@@ -785,7 +951,8 @@ impl Ppu {
         }
 
         // End of frame
-        if self.scanline > PRE_RENDER_SCANLINE {
+        if self.scanline > self.pre_render_scanline() {
+            video_frame_sink.set_emphasis(self.regs.ppu_mask.bits() >> 5);
             video_frame_sink.write_frame(&self.frame_buffer);
             self.scanline = VISIBLE_START_SCANLINE;
             self.frame += 1;
@@ -817,6 +984,35 @@ impl Memory for Ppu {
         val
     }
 
+    // Returns what a read of the register at `address` would, without clearing the vblank flag
+    // or write toggle, advancing the VRAM address or refilling the read buffer
+    fn peek_byte(&mut self, address: u16) -> u8 {
+        match address & 0x2007 {
+            PPUSTATUS_ADDRESS => self.ppu_status(),
+            OAMDATA_ADDRESS => self.read_oam_byte(),
+            PPUDATA_ADDRESS => {
+                let address = self.regs.v;
+                if address < PaletteRam::START_ADDRESS {
+                    self.ppu_data_read_buffer
+                } else {
+                    self.mem.read_byte(address)
+                }
+            }
+            _ => self.ppu_gen_latch,
+        }
+    }
+
+    // Writes the register at `address` as the CPU would, except that $2007 writes leave the
+    // VRAM address where it was
+    fn poke_byte(&mut self, address: u16, value: u8) {
+        if address & 0x2007 == PPUDATA_ADDRESS {
+            let address = self.regs.v;
+            self.mem.write_byte(address, value);
+        } else {
+            self.write_byte(address, value);
+        }
+    }
+
     fn write_byte(&mut self, address: u16, value: u8) {
         if !((0x2000..0x4000).contains(&address)) {
             panic!(
@@ -829,7 +1025,8 @@ impl Memory for Ppu {
 
         // Writes to the following registers are ignored if earlier than
         // ~29658 CPU clocks after reset: PPUCTRL, PPUMASK, PPUSCROLL, PPUADDR
-        if self.cycles < 3 * 29658
+        if self.warm_up
+            && self.cycles < WARM_UP_CYCLES
             && (address == PPUCTRL_ADDRESS
                 || address == PPUMASK_ADDRESS
                 || address == PPUSCROLL_ADDRESS
@@ -1115,10 +1312,11 @@ impl PaletteRam {
     }
 
     fn index(address: u16) -> usize {
+        // Palette RAM is 32 bytes, mirrored every 32 bytes up to 0x3FFF
         let index = (address & 0x001F) as usize;
 
         // Addresses 0x3F10/0x3F14/0x3F18/0x3F1C are mirrors of 0x3F00/0x3F04/0x3F08/0x3F0C
-        if index >= 0x10 && index % 4 == 0 {
+        if index >= 0x10 && index.is_multiple_of(4) {
             index - 0x10
         } else {
             index
@@ -1153,6 +1351,16 @@ impl MemMap {
 }
 
 impl Memory for MemMap {
+    fn peek_byte(&mut self, address: u16) -> u8 {
+        let address = address & 0x3FFF;
+
+        if address < 0x2000 {
+            self.mapper.borrow_mut().chr_peek_byte(address)
+        } else {
+            self.read_byte(address)
+        }
+    }
+
     fn read_byte(&mut self, address: u16) -> u8 {
         let address = address & 0x3FFF;
 
@@ -1252,4 +1460,286 @@ impl Sprite {
     fn flip_vertically(self) -> bool {
         self.attributes.flip_vertically()
     }
+
+    // Mirrors a row's pattern bytes if the sprite is flipped horizontally
+    fn pattern_row_as_drawn(self, pattern_lo: u8, pattern_hi: u8) -> (u8, u8) {
+        if self.flip_horizontally() {
+            (pattern_lo.swap_bits(), pattern_hi.swap_bits())
+        } else {
+            (pattern_lo, pattern_hi)
+        }
+    }
+}
+
+#[test]
+fn test_palette_ram_mirroring() {
+    let mut palette_ram = PaletteRam::new();
+
+    for &(mirror, base) in [
+        (0x3F10, 0x3F00),
+        (0x3F14, 0x3F04),
+        (0x3F18, 0x3F08),
+        (0x3F1C, 0x3F0C),
+    ]
+    .iter()
+    {
+        palette_ram.write_byte(mirror, 0x2A);
+        assert_eq!(palette_ram.read_byte(base), 0x2A);
+
+        palette_ram.write_byte(base, 0x15);
+        assert_eq!(palette_ram.read_byte(mirror), 0x15);
+    }
+
+    palette_ram.write_byte(0x3F01, 0x30);
+    assert_eq!(palette_ram.read_byte(0x3F21), 0x30);
+    assert_eq!(palette_ram.read_byte(0x3FE1), 0x30);
+    assert_eq!(palette_ram.read_byte(0x3F11), 0x00);
+}
+
+#[cfg(test)]
+fn test_ppu(chr: Vec<u8>) -> Ppu {
+    use crate::cartridge::{Cartridge, Mirroring};
+
+    let cartridge = Cartridge {
+        mapper: 0,
+        sub_mapper: 0,
+        mirroring: Mirroring::Horizontal,
+        default_mirroring: Mirroring::Horizontal,
+        prg_rom_num_banks: 1,
+        prg_rom: vec![0; 0x4000],
+        chr_num_banks: 1,
+        chr,
+        has_chr_ram: false,
+        prg_ram: vec![0; 0x2000],
+        misc_rom: Vec::new(),
+        has_prg_ram: true,
+        is_battery_backed: false,
+        crc32: 0,
+        crc32_with_header: 0,
+        bus_conflicts: false,
+    };
+
+    Ppu::new(Rc::new(RefCell::new(MapperEnum::from_cartridge(cartridge))))
+}
+
+#[cfg(test)]
+fn render_frame(ppu: &mut Ppu) {
+    let mut cpu = Cpu::new();
+    let mut buffer = vec![0u32; SCREEN_WIDTH * SCREEN_HEIGHT];
+    let mut video_sink = Xrgb8888VideoSink::new(&mut buffer);
+
+    let frame = ppu.frame;
+    while ppu.frame == frame {
+        ppu.step(&mut cpu, &mut video_sink);
+    }
+}
+
+#[test]
+fn test_8x16_sprites() {
+    // Tile 0 of the right pattern table uses color 1, tile 1 uses color 2
+    let mut chr = vec![0u8; 0x2000];
+    chr[0x1000..0x1008].fill(0xFF);
+    chr[0x1018..0x1020].fill(0xFF);
+    let mut ppu = test_ppu(chr);
+
+    ppu.mem.write_byte(0x3F00, 0x0F);
+    ppu.mem.write_byte(0x3F11, 0x11);
+    ppu.mem.write_byte(0x3F12, 0x12);
+
+    // Odd tile index selects the pattern table at 0x1000
+    ppu.oam[0] = 9;
+    ppu.oam[1] = 0x01;
+    ppu.oam[2] = 0x00;
+    ppu.oam[3] = 16;
+    for i in 4..256 {
+        ppu.oam[i] = 0xFF;
+    }
+
+    ppu.write_ppu_ctrl(0x20);
+    ppu.regs.ppu_mask = PpuMask::SHOW_SPRITES | PpuMask::SHOW_SPRITES_LEFT_8;
+    render_frame(&mut ppu);
+
+    let pixel = |x: usize, y: usize| ppu.frame_buffer[y * SCREEN_WIDTH + x];
+    for x in 16..24 {
+        assert_eq!(pixel(x, 9), 0x0F);
+        for y in 10..18 {
+            assert_eq!(pixel(x, y), 0x11);
+        }
+        for y in 18..26 {
+            assert_eq!(pixel(x, y), 0x12);
+        }
+        assert_eq!(pixel(x, 26), 0x0F);
+    }
+    assert_eq!(pixel(15, 10), 0x0F);
+    assert_eq!(pixel(24, 25), 0x0F);
+}
+
+#[test]
+fn test_left_column_clipping() {
+    // Tile 0 of the left pattern table uses color 1, and the nametable is all tile 0
+    let mut chr = vec![0u8; 0x2000];
+    chr[0x0000..0x0008].fill(0xFF);
+    let mut ppu = test_ppu(chr);
+
+    ppu.mem.write_byte(0x3F00, 0x0F);
+    ppu.mem.write_byte(0x3F01, 0x21);
+
+    ppu.regs.ppu_mask = PpuMask::SHOW_BACKGROUND;
+    render_frame(&mut ppu);
+    render_frame(&mut ppu);
+
+    for y in [0, 100, 239] {
+        for x in 0..8 {
+            assert_eq!(ppu.frame_buffer[y * SCREEN_WIDTH + x], 0x0F);
+        }
+        for x in 8..SCREEN_WIDTH {
+            assert_eq!(ppu.frame_buffer[y * SCREEN_WIDTH + x], 0x21);
+        }
+    }
+
+    ppu.regs.ppu_mask = PpuMask::SHOW_BACKGROUND | PpuMask::SHOW_BACKGROUND_LEFT_8;
+    render_frame(&mut ppu);
+
+    for x in 0..8 {
+        assert_eq!(ppu.frame_buffer[100 * SCREEN_WIDTH + x], 0x21);
+    }
+}
+
+#[test]
+fn test_greyscale() {
+    // The left half of tile 0 of the left pattern table uses color 1, and the right half the
+    // backdrop. The nametable is all tile 0.
+    let mut chr = vec![0u8; 0x2000];
+    chr[0x0000..0x0008].fill(0xF0);
+    let mut ppu = test_ppu(chr);
+    ppu.warm_up = false;
+
+    ppu.mem.write_byte(0x3F00, 0x16);
+    ppu.mem.write_byte(0x3F01, 0x2A);
+
+    // Show the background, including the left 8 pixels
+    ppu.write_byte(0x2001, 0x0A);
+    render_frame(&mut ppu);
+    render_frame(&mut ppu);
+    assert_eq!(ppu.frame_buffer[100 * SCREEN_WIDTH + 8], 0x2A);
+    assert_eq!(ppu.frame_buffer[100 * SCREEN_WIDTH + 12], 0x16);
+
+    // Setting bit 0 keeps only the brightness of each color
+    ppu.write_byte(0x2001, 0x0B);
+    render_frame(&mut ppu);
+    for y in [0, 100, 239] {
+        for x in (0..SCREEN_WIDTH).step_by(8) {
+            assert_eq!(ppu.frame_buffer[y * SCREEN_WIDTH + x], 0x20);
+            assert_eq!(ppu.frame_buffer[y * SCREEN_WIDTH + x + 4], 0x10);
+        }
+    }
+}
+
+#[test]
+fn test_peek_ppu_status() {
+    let mut ppu = test_ppu(vec![0; 0x2000]);
+    // Past the warm-up period in which $2006 writes are ignored
+    ppu.cycles = WARM_UP_CYCLES;
+    ppu.set_vblank();
+
+    // Leave the address latch waiting for the second $2006 write
+    ppu.write_byte(0x2006, 0x21);
+    for _ in 0..4 {
+        assert_eq!(ppu.peek_byte(0x2002) & 0x80, 0x80);
+    }
+    ppu.write_byte(0x2006, 0x08);
+    assert_eq!(ppu.regs.v, 0x2108);
+
+    assert_eq!(ppu.read_byte(0x2002) & 0x80, 0x80);
+    assert_eq!(ppu.peek_byte(0x2002) & 0x80, 0x00);
+}
+
+#[test]
+fn test_poke_ppu_data() {
+    let mut ppu = test_ppu(vec![0; 0x2000]);
+    ppu.cycles = WARM_UP_CYCLES;
+    ppu.write_byte(0x2006, 0x21);
+    ppu.write_byte(0x2006, 0x08);
+
+    // A poke lands where a $2007 write would, but leaves the VRAM address alone
+    ppu.poke_byte(0x2007, 0x5A);
+    assert_eq!(ppu.regs.v, 0x2108);
+    assert_eq!(ppu.mem.read_byte(0x2108), 0x5A);
+    ppu.write_byte(0x2007, 0xA5);
+    assert_eq!(ppu.regs.v, 0x2109);
+    assert_eq!(ppu.mem.read_byte(0x2108), 0xA5);
+}
+
+#[test]
+fn test_warm_up() {
+    let mut ppu = test_ppu(vec![0; 0x2000]);
+    ppu.write_byte(0x2000, 0x80);
+    assert!(!ppu.nmi_output);
+
+    ppu.cycles = WARM_UP_CYCLES;
+    ppu.write_byte(0x2000, 0x80);
+    assert!(ppu.nmi_output);
+
+    let mut ppu = test_ppu(vec![0; 0x2000]);
+    ppu.warm_up = false;
+    ppu.write_byte(0x2000, 0x80);
+    assert!(ppu.nmi_output);
+}
+
+#[test]
+fn test_tile_at_pixel() {
+    let mut ppu = test_ppu(vec![0; 0x2000]);
+    ppu.cycles = WARM_UP_CYCLES;
+
+    // Tile (3, 2) of the first nametable, in the bottom right quadrant of its attribute byte
+    ppu.mem.write_byte(0x2043, 0x42);
+    ppu.mem.write_byte(0x23C0, 0b10_01_11_00);
+
+    let tile = ppu.tile_at_pixel(25, 17);
+    assert_eq!(
+        tile,
+        TileInfo {
+            nametable_addr: 0x2043,
+            tile_index: 0x42,
+            attribute: 0b10_01_11_00,
+            palette: 2,
+        }
+    );
+
+    // Scrolled 8 pixels right and 4 down, the same tile is drawn 8 pixels left and 4 up
+    ppu.write_byte(0x2005, 8);
+    ppu.write_byte(0x2005, 4);
+    assert_eq!(ppu.tile_at_pixel(17, 13), tile);
+    assert_eq!(ppu.tile_at_pixel(25, 17).nametable_addr, 0x2044);
+}
+
+#[test]
+fn test_sprite_sheet() {
+    // Tile 1 of the left pattern table is solid color 1, tile 2 is solid color 3
+    let mut chr = vec![0u8; 0x2000];
+    chr[0x0010..0x0018].fill(0xFF);
+    chr[0x0020..0x0030].fill(0xFF);
+    let mut ppu = test_ppu(chr);
+
+    ppu.mem.write_byte(0x3F00, 0x0F);
+    ppu.mem.write_byte(0x3F11, 0x11);
+    ppu.mem.write_byte(0x3F1B, 0x2B);
+
+    ppu.oam.fill(0xFF);
+    // Sprite 3 uses tile 1 with palette 0, and sprite 9 tile 2 with palette 2
+    ppu.oam[12..16].copy_from_slice(&[40, 0x01, 0x00, 100]);
+    ppu.oam[36..40].copy_from_slice(&[80, 0x02, 0x02, 20]);
+
+    let sheet = ppu.sprite_sheet();
+    assert_eq!(sheet.width, SPRITE_SHEET_COLUMNS * 8);
+    assert_eq!(sheet.height, 8);
+
+    let pixel = |x: usize, y: usize| sheet.pixels[y * sheet.width + x];
+    for y in 0..8 {
+        for x in 0..8 {
+            assert_eq!(pixel(x, y), 0x11);
+            assert_eq!(pixel(8 + x, y), 0x2B);
+            assert_eq!(pixel(16 + x, y), 0x0F);
+        }
+    }
 }
@@ -0,0 +1,139 @@
+use crate::cpu::{Cpu, Trap};
+use crate::memory::{FlatRam, Memory};
+
+// How many instructions a RamSystem runs for before giving up, when nothing else stops it. Tens
+// of seconds of 6502 time, but only a moment to emulate.
+pub const INSTRUCTION_LIMIT: u64 = 10_000_000;
+
+// A 6502 with no cartridge, PPU or APU, over a flat 64KB of RAM. Raw programs can be poked into
+// RAM and run from any address, for CPU experiments and developing 6502 code without a ROM.
+pub struct RamSystem {
+    pub cpu: Cpu,
+    pub ram: FlatRam,
+}
+
+impl RamSystem {
+    pub fn new(start_pc: u16) -> Self {
+        let mut ram = FlatRam::new();
+        let mut cpu = Cpu::standalone();
+        cpu.reset(&mut ram);
+        cpu.set_pc(start_pc);
+        RamSystem { cpu, ram }
+    }
+
+    // Loads a flat binary, such as the raw output of an assembler, at `load_address` and starts
+    // it at `start_pc`
+    pub fn from_flat(binary: &[u8], load_address: u16, start_pc: u16) -> Self {
+        let mut system = RamSystem::new(start_pc);
+        system.load(load_address, binary);
+        system
+    }
+
+    // Copies `program` into RAM starting at `address`, wrapping around the end of the address
+    // space
+    pub fn load(&mut self, address: u16, program: &[u8]) {
+        for (offset, &byte) in program.iter().enumerate() {
+            self.ram
+                .write_byte(address.wrapping_add(offset as u16), byte);
+        }
+    }
+
+    // Executes one instruction, returning the cycles it took
+    pub fn step(&mut self) -> u32 {
+        self.cpu.step(&mut self.ram).0
+    }
+
+    // Runs until the CPU traps, such as on a JAM opcode, or `max_instructions` have executed.
+    // Returns the trap, if any.
+    pub fn run(&mut self, max_instructions: u64) -> Option<Trap> {
+        for _ in 0..max_instructions {
+            if self.cpu.trap().is_some() {
+                break;
+            }
+            self.step();
+        }
+        self.cpu.trap()
+    }
+}
+
+#[test]
+fn test_ram_system() {
+    #[rustfmt::skip]
+    let program = [
+        0xA2, 0x05,       // ldx #5
+        0xA9, 0x00,       // lda #0
+        0x18,             // clc
+        0x69, 0x03,       // adc #3
+        0xCA,             // dex
+        0xD0, 0xFA,       // bne $0404
+        0x8D, 0x00, 0x02, // sta $0200
+        0x02,             // jam
+    ];
+    let mut system = RamSystem::new(0x0400);
+    system.load(0x0400, &program);
+
+    assert_eq!(
+        system.run(1000),
+        Some(Trap::Jam {
+            pc: 0x040D,
+            opcode: 0x02
+        })
+    );
+    assert_eq!(system.ram[0x0200], 15);
+    assert_eq!(system.cpu.regs().x, 0);
+}
+
+#[test]
+fn test_flat_binary() {
+    #[rustfmt::skip]
+    let binary = [
+        0xE6, 0x10,       // inc $10
+        0x4C, 0x00, 0x03, // jmp $0300
+    ];
+    let mut system = RamSystem::from_flat(&binary, 0x0300, 0x0300);
+
+    for _ in 0..10 {
+        system.step();
+    }
+    assert_eq!(system.ram[0x0010], 5);
+    assert_eq!(system.cpu.regs().pc, 0x0300);
+}
+
+#[test]
+fn test_flat_test_suite_image() {
+    const START_PC: u16 = 0x0400;
+    const FAIL_TRAP: u16 = 0x0200;
+    const SUCCESS_TRAP: u16 = 0x040D;
+
+    // A headerless 64KB image laid out like Klaus Dormann's functional test: loaded at $0000 and
+    // started at $0400, with the reset vector pointing at a failure trap instead
+    let mut image = vec![0u8; 0x10000];
+    image[FAIL_TRAP as usize..FAIL_TRAP as usize + 3].copy_from_slice(&[0x4C, 0x00, 0x02]);
+    #[rustfmt::skip]
+    let program = [
+        0xA9, 0x05,       // lda #$05
+        0x18,             // clc
+        0x69, 0x03,       // adc #$03
+        0xC9, 0x08,       // cmp #$08
+        0xF0, 0x03,       // beq success
+        0x4C, 0x00, 0x02, // jmp fail
+        0xEA,             // nop
+        0x4C, 0x0D, 0x04, // success: jmp success
+    ];
+    image[START_PC as usize..START_PC as usize + program.len()].copy_from_slice(&program);
+    image[0xFFFC..0xFFFE].copy_from_slice(&FAIL_TRAP.to_le_bytes());
+
+    let mut system = RamSystem::from_flat(&image, 0x0000, START_PC);
+
+    // Run until the PC stops changing, which is how these suites signal the end of a test
+    let mut previous_pc = None;
+    for _ in 0..100 {
+        let pc = system.cpu.regs().pc;
+        if previous_pc == Some(pc) {
+            break;
+        }
+        previous_pc = Some(pc);
+        system.step();
+    }
+    assert_eq!(previous_pc, Some(SUCCESS_TRAP));
+}
@@ -1,7 +1,26 @@
 use super::nes;
 use super::nes::Nes;
 
+use byteorder::{ByteOrder, LittleEndian};
 use serde_derive::{Deserialize, Serialize};
+use thiserror::Error;
+
+// Bump whenever a change to the state structs makes older save states incompatible
+pub const STATE_VERSION: u32 = 1;
+
+// The header at the start of every save state: the version followed by the ROM CRC32, both
+// little-endian u32s
+pub const HEADER_SIZE: usize = 8;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum StateError {
+    #[error("save state is missing its header")]
+    MissingHeader,
+    #[error("save state version {found} is not supported, expected version {expected}")]
+    VersionMismatch { expected: u32, found: u32 },
+    #[error("save state is for a different ROM (CRC32 {found:08X}, expected {expected:08X})")]
+    RomMismatch { expected: u32, found: u32 },
+}
 
 #[derive(Deserialize, Serialize)]
 pub enum VersionedState {
@@ -18,3 +37,70 @@ pub fn apply_state(nes: &mut Nes, state: VersionedState) {
         Version1(ref state) => nes.apply_state(state),
     }
 }
+
+// Returns the header to write before the serialized state.
+pub fn header(nes: &Nes) -> [u8; HEADER_SIZE] {
+    let mut header = [0u8; HEADER_SIZE];
+    LittleEndian::write_u32(&mut header[0..4], STATE_VERSION);
+    LittleEndian::write_u32(&mut header[4..8], nes.rom_crc32());
+    header
+}
+
+// Checks that the header at the start of `data` matches this build and the loaded ROM, and
+// returns the serialized state that follows it.
+pub fn check_header<'a>(nes: &Nes, data: &'a [u8]) -> Result<&'a [u8], StateError> {
+    if data.len() < HEADER_SIZE {
+        return Err(StateError::MissingHeader);
+    }
+
+    let version = LittleEndian::read_u32(&data[0..4]);
+    if version != STATE_VERSION {
+        return Err(StateError::VersionMismatch {
+            expected: STATE_VERSION,
+            found: version,
+        });
+    }
+
+    let rom_crc32 = LittleEndian::read_u32(&data[4..8]);
+    if rom_crc32 != nes.rom_crc32() {
+        return Err(StateError::RomMismatch {
+            expected: nes.rom_crc32(),
+            found: rom_crc32,
+        });
+    }
+
+    Ok(&data[HEADER_SIZE..])
+}
+
+#[test]
+fn test_check_header() {
+    use crate::cartridge::{test_rom, Cartridge};
+
+    let nes = Nes::new(Cartridge::from_bytes(&test_rom()).unwrap());
+
+    let mut data = header(&nes).to_vec();
+    data.extend_from_slice(b"state");
+    assert_eq!(check_header(&nes, &data), Ok(&b"state"[..]));
+
+    let mut bad_version = data.clone();
+    bad_version[0] ^= 0xFF;
+    assert_eq!(
+        check_header(&nes, &bad_version),
+        Err(StateError::VersionMismatch {
+            expected: STATE_VERSION,
+            found: STATE_VERSION ^ 0xFF
+        })
+    );
+
+    let mut bad_crc32 = data.clone();
+    bad_crc32[4] ^= 0xFF;
+    assert!(matches!(
+        check_header(&nes, &bad_crc32),
+        Err(StateError::RomMismatch { .. })
+    ));
+
+    assert_eq!(
+        check_header(&nes, &data[..HEADER_SIZE - 1]),
+        Err(StateError::MissingHeader)
+    );
+}
@@ -13,48 +13,62 @@ impl<A: AudioSink + ?Sized> AudioSink for Box<A> {
     }
 }
 
-pub struct AudioSinkF32<'a> {
-    buffer: &'a mut [(f32, f32)],
-    buffer_pos: usize,
+// An output sample format. The APU emits f32 samples in [-1.0, 1.0], which are converted to the
+// output format once, when written to the sink.
+pub trait Sample: Copy {
+    fn from_f32(sample: f32) -> Self;
+    fn to_f32(self) -> f32;
 }
 
-impl<'a> AudioSinkF32<'a> {
-    pub fn new(buffer: &'a mut [(f32, f32)]) -> Self {
-        AudioSinkF32 {
-            buffer,
-            buffer_pos: 0,
-        }
+impl Sample for f32 {
+    fn from_f32(sample: f32) -> Self {
+        sample
+    }
+
+    fn to_f32(self) -> f32 {
+        self
     }
 }
 
-impl<'a> AudioSink for AudioSinkF32<'a> {
-    fn write_sample(&mut self, sample: f32) {
-        self.buffer[self.buffer_pos] = (sample, sample);
-        self.buffer_pos += 1;
+// Out of range samples saturate, since float to int casts do
+impl Sample for i16 {
+    fn from_f32(sample: f32) -> Self {
+        (sample * 32768.0) as i16
     }
 
-    fn samples_written(&self) -> usize {
-        self.buffer_pos
+    fn to_f32(self) -> f32 {
+        self as f32 / 32768.0
     }
 }
 
-pub struct AudioSinkI16<'a> {
-    buffer: &'a mut [(i16, i16)],
+impl Sample for u16 {
+    fn from_f32(sample: f32) -> Self {
+        ((sample * 32768.0) + 32768.0) as u16
+    }
+
+    fn to_f32(self) -> f32 {
+        (self as f32 - 32768.0) / 32768.0
+    }
+}
+
+// Writes stereo frames, with the mono sample in both channels
+pub struct BufferAudioSink<'a, S: Sample> {
+    buffer: &'a mut [(S, S)],
     buffer_pos: usize,
 }
 
-impl<'a> AudioSinkI16<'a> {
-    pub fn new(buffer: &'a mut [(i16, i16)]) -> Self {
-        AudioSinkI16 {
+impl<'a, S: Sample> BufferAudioSink<'a, S> {
+    pub fn new(buffer: &'a mut [(S, S)]) -> Self {
+        BufferAudioSink {
             buffer,
             buffer_pos: 0,
         }
     }
 }
 
-impl<'a> AudioSink for AudioSinkI16<'a> {
+impl<'a, S: Sample> AudioSink for BufferAudioSink<'a, S> {
     fn write_sample(&mut self, sample: f32) {
-        let sample = (sample * 32768.0) as i16;
+        let sample = S::from_f32(sample);
         self.buffer[self.buffer_pos] = (sample, sample);
         self.buffer_pos += 1;
     }
@@ -64,28 +78,31 @@ impl<'a> AudioSink for AudioSinkI16<'a> {
     }
 }
 
-pub struct AudioSinkU16<'a> {
-    buffer: &'a mut [(u16, u16)],
-    buffer_pos: usize,
-}
+pub type AudioSinkF32<'a> = BufferAudioSink<'a, f32>;
+pub type AudioSinkI16<'a> = BufferAudioSink<'a, i16>;
+pub type AudioSinkU16<'a> = BufferAudioSink<'a, u16>;
 
-impl<'a> AudioSinkU16<'a> {
-    pub fn new(buffer: &'a mut [(u16, u16)]) -> Self {
-        AudioSinkU16 {
-            buffer,
-            buffer_pos: 0,
-        }
+#[test]
+fn test_sample_round_trip() {
+    fn round_trip<S: Sample>(sample: f32) -> f32 {
+        let mut buffer = [(S::from_f32(0.0), S::from_f32(0.0)); 1];
+        let mut sink = BufferAudioSink::new(&mut buffer);
+        sink.write_sample(sample);
+        assert_eq!(sink.samples_written(), 1);
+        let (left, right) = buffer[0];
+        assert_eq!(left.to_f32(), right.to_f32());
+        left.to_f32()
     }
-}
 
-impl<'a> AudioSink for AudioSinkU16<'a> {
-    fn write_sample(&mut self, sample: f32) {
-        let sample = ((sample * 32768.0) + 32768.0) as u16;
-        self.buffer[self.buffer_pos] = (sample, sample);
-        self.buffer_pos += 1;
-    }
+    // One step of a 16-bit sample
+    const QUANTIZATION_ERROR: f32 = 1.0 / 32768.0;
 
-    fn samples_written(&self) -> usize {
-        self.buffer_pos
+    for &sample in &[-1.0, -0.5, -0.123_456, 0.0, 0.25, 0.987_654, 0.999_9] {
+        assert_eq!(round_trip::<f32>(sample), sample);
+        assert!((round_trip::<i16>(sample) - sample).abs() <= QUANTIZATION_ERROR);
+        assert!((round_trip::<u16>(sample) - sample).abs() <= QUANTIZATION_ERROR);
     }
+
+    // Full scale positive saturates to the largest representable value
+    assert_eq!(i16::from_f32(1.0), i16::MAX);
 }
@@ -6,6 +6,10 @@ pub trait VideoSink {
     fn write_frame(&mut self, frame_buffer: &[u8]);
     fn frame_written(&self) -> bool;
     fn pixel_size(&self) -> usize;
+
+    // Called before write_frame with the PPUMASK color emphasis bits (bit 0: red, bit 1: green,
+    // bit 2: blue) in effect for the frame. Sinks that don't support emphasis can ignore it.
+    fn set_emphasis(&mut self, _emphasis: u8) {}
 }
 
 impl<S: VideoSink + ?Sized> VideoSink for Box<S> {
@@ -20,6 +24,74 @@ impl<S: VideoSink + ?Sized> VideoSink for Box<S> {
     fn pixel_size(&self) -> usize {
         (**self).pixel_size()
     }
+
+    fn set_emphasis(&mut self, emphasis: u8) {
+        (**self).set_emphasis(emphasis);
+    }
+}
+
+// Writes each frame to every one of `sinks`, for example to both display and record it
+pub struct MultiVideoSink<'a> {
+    sinks: Vec<Box<dyn VideoSink + 'a>>,
+}
+
+impl<'a> MultiVideoSink<'a> {
+    pub fn new(sinks: Vec<Box<dyn VideoSink + 'a>>) -> Self {
+        MultiVideoSink { sinks }
+    }
+
+    pub fn push(&mut self, sink: Box<dyn VideoSink + 'a>) {
+        self.sinks.push(sink);
+    }
+}
+
+impl<'a> VideoSink for MultiVideoSink<'a> {
+    fn write_frame(&mut self, frame_buffer: &[u8]) {
+        for sink in &mut self.sinks {
+            sink.write_frame(frame_buffer);
+        }
+    }
+
+    fn frame_written(&self) -> bool {
+        self.sinks.iter().any(|sink| sink.frame_written())
+    }
+
+    // The pixel size of the first sink
+    fn pixel_size(&self) -> usize {
+        self.sinks.first().map_or(0, |sink| sink.pixel_size())
+    }
+
+    fn set_emphasis(&mut self, emphasis: u8) {
+        for sink in &mut self.sinks {
+            sink.set_emphasis(emphasis);
+        }
+    }
+}
+
+// Discards frames, for running without video output
+#[derive(Default)]
+pub struct NullVideoSink {
+    frame_written: bool,
+}
+
+impl NullVideoSink {
+    pub fn new() -> Self {
+        NullVideoSink::default()
+    }
+}
+
+impl VideoSink for NullVideoSink {
+    fn write_frame(&mut self, _frame_buffer: &[u8]) {
+        self.frame_written = true;
+    }
+
+    fn frame_written(&self) -> bool {
+        self.frame_written
+    }
+
+    fn pixel_size(&self) -> usize {
+        0
+    }
 }
 
 pub struct Rgb565VideoSink<'a> {
@@ -147,6 +219,180 @@ impl<'a> VideoSink for Xrgb8888VideoSink<'a> {
     }
 }
 
+pub struct Xrgb8888EmphasisVideoSink<'a> {
+    buffer: &'a mut [u32],
+    emphasis: u8,
+    frame_written: bool,
+}
+
+impl<'a> Xrgb8888EmphasisVideoSink<'a> {
+    pub fn new(buffer: &'a mut [u32]) -> Self {
+        Xrgb8888EmphasisVideoSink {
+            buffer,
+            emphasis: 0,
+            frame_written: false,
+        }
+    }
+}
+
+impl<'a> VideoSink for Xrgb8888EmphasisVideoSink<'a> {
+    fn write_frame(&mut self, frame_buffer: &[u8]) {
+        let palette = &XRGB8888_EMPHASIS_PALETTES[self.emphasis as usize];
+        for (i, palette_index) in frame_buffer.iter().enumerate() {
+            self.buffer[i] = palette[*palette_index as usize];
+        }
+        self.frame_written = true;
+    }
+
+    fn frame_written(&self) -> bool {
+        self.frame_written
+    }
+
+    fn pixel_size(&self) -> usize {
+        mem::size_of::<u32>()
+    }
+
+    fn set_emphasis(&mut self, emphasis: u8) {
+        self.emphasis = emphasis & 0x07;
+    }
+}
+
+// Outputs the raw 6-bit palette indices with the frame's 3 emphasis bits in bits 6-8, the
+// 9-bit format expected by NTSC filters and shaders that do their own color decoding.
+pub struct RawIndexVideoSink<'a> {
+    buffer: &'a mut [u16],
+    emphasis: u8,
+    frame_written: bool,
+}
+
+impl<'a> RawIndexVideoSink<'a> {
+    pub fn new(buffer: &'a mut [u16]) -> Self {
+        RawIndexVideoSink {
+            buffer,
+            emphasis: 0,
+            frame_written: false,
+        }
+    }
+}
+
+impl<'a> VideoSink for RawIndexVideoSink<'a> {
+    fn write_frame(&mut self, frame_buffer: &[u8]) {
+        let emphasis = (self.emphasis as u16) << 6;
+        for (i, palette_index) in frame_buffer.iter().enumerate() {
+            self.buffer[i] = emphasis | *palette_index as u16;
+        }
+        self.frame_written = true;
+    }
+
+    fn frame_written(&self) -> bool {
+        self.frame_written
+    }
+
+    fn pixel_size(&self) -> usize {
+        mem::size_of::<u16>()
+    }
+
+    fn set_emphasis(&mut self, emphasis: u8) {
+        self.emphasis = emphasis & 0x07;
+    }
+}
+
+// Averages each XRGB8888 frame with the previous one, which softens the 30Hz flicker games use
+// to show more sprites than fit on a scanline
+#[derive(Default)]
+pub struct FrameBlender {
+    previous: Vec<u32>,
+}
+
+impl FrameBlender {
+    pub fn new() -> Self {
+        FrameBlender::default()
+    }
+
+    // Replaces `frame` with its average with the previous frame. The first frame, or a frame of a
+    // different size, is left as is.
+    pub fn blend(&mut self, frame: &mut [u32]) {
+        if self.previous.len() != frame.len() {
+            self.previous = frame.to_vec();
+            return;
+        }
+
+        for (pixel, previous) in frame.iter_mut().zip(self.previous.iter_mut()) {
+            let current = *pixel;
+            *pixel = average_xrgb8888(current, *previous);
+            *previous = current;
+        }
+    }
+}
+
+// Per-channel average, rounding down
+fn average_xrgb8888(a: u32, b: u32) -> u32 {
+    ((a & b) + (((a ^ b) & 0xFEFEFE) >> 1)) & 0xFFFFFF
+}
+
+// Grayscale levels for displays that can't show the full palette
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorDepth {
+    // Black and white
+    OneBit,
+    // 4 shades of gray
+    TwoBit,
+    // 16 shades of gray
+    Gray4,
+}
+
+impl ColorDepth {
+    pub fn levels(self) -> u32 {
+        match self {
+            ColorDepth::OneBit => 2,
+            ColorDepth::TwoBit => 4,
+            ColorDepth::Gray4 => 16,
+        }
+    }
+}
+
+// 4x4 Bayer matrix, giving the order in which pixels of a flat area round up
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+// Reduces XRGB8888 frames to the gray levels of a `ColorDepth` by the luma of each pixel,
+// optionally with ordered dithering to approximate the shades in between
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quantizer {
+    depth: ColorDepth,
+    dither: bool,
+}
+
+impl Quantizer {
+    pub fn new(depth: ColorDepth, dither: bool) -> Self {
+        Quantizer { depth, dither }
+    }
+
+    // Quantizes `frame` in place, where `width` is the number of pixels in each row
+    pub fn quantize(&self, frame: &mut [u32], width: usize) {
+        let max_level = self.depth.levels() - 1;
+        for (i, pixel) in frame.iter_mut().enumerate() {
+            let threshold = if self.dither {
+                let (x, y) = (i % width, i / width);
+                (BAYER_4X4[y % 4][x % 4] as u32 * 2 + 1) * 255 / 32
+            } else {
+                127
+            };
+
+            let level = ((luma(*pixel) * max_level + threshold) / 255).min(max_level);
+            let gray = level * 255 / max_level;
+            *pixel = (gray << 16) | (gray << 8) | gray;
+        }
+    }
+}
+
+// ITU-R BT.601 luma, from 0 to 255
+fn luma(color: u32) -> u32 {
+    let r = (color >> 16) & 0xFF;
+    let g = (color >> 8) & 0xFF;
+    let b = color & 0xFF;
+    (r * 299 + g * 587 + b * 114) / 1000
+}
+
 #[allow(clippy::unreadable_literal)]
 pub static XRGB8888_PALETTE: &[u32] = &[
     0x666666, 0x002A88, 0x1412A7, 0x3B00A4, 0x5C007E, 0x6E0040, 0x6C0600, 0x561D00, 0x333500,
@@ -194,3 +440,132 @@ static WEB_PALETTE: Lazy<[u32; 64]> = Lazy::new(|| {
     }
     palette
 });
+
+// Each emphasis bit darkens the two color channels it doesn't emphasize
+const EMPHASIS_ATTENUATION: f32 = 0.75;
+
+// One palette per combination of the three emphasis bits
+pub static XRGB8888_EMPHASIS_PALETTES: Lazy<[[u32; 64]; 8]> = Lazy::new(|| {
+    let mut palettes = [[0; 64]; 8];
+    for (emphasis, palette) in palettes.iter_mut().enumerate() {
+        // Attenuation applied to the red, green and blue channels
+        let mut attenuation = [1.0f32; 3];
+        for bit in 0..3 {
+            if emphasis & (1 << bit) != 0 {
+                for (channel, a) in attenuation.iter_mut().enumerate() {
+                    if channel != bit {
+                        *a *= EMPHASIS_ATTENUATION;
+                    }
+                }
+            }
+        }
+
+        for n in 0..64 {
+            let color = XRGB8888_PALETTE[n];
+            let r = (((color >> 16) & 0xFF) as f32 * attenuation[0]) as u32;
+            let g = (((color >> 8) & 0xFF) as f32 * attenuation[1]) as u32;
+            let b = ((color & 0xFF) as f32 * attenuation[2]) as u32;
+            palette[n] = (r << 16) | (g << 8) | b;
+        }
+    }
+    palettes
+});
+
+#[test]
+fn test_emphasis_palettes() {
+    let base = &XRGB8888_EMPHASIS_PALETTES[0];
+    assert_eq!(&base[..], XRGB8888_PALETTE);
+
+    // Red emphasis leaves red alone and darkens green and blue
+    let red = &XRGB8888_EMPHASIS_PALETTES[0x01];
+    for n in 0..64 {
+        let (base_color, red_color) = (base[n], red[n]);
+        assert_eq!(red_color & 0xFF0000, base_color & 0xFF0000);
+        assert!(red_color & 0x00FF00 <= base_color & 0x00FF00);
+        assert!(red_color & 0x0000FF <= base_color & 0x0000FF);
+    }
+
+    // White (0x30) is strictly darker in green and blue
+    assert!(red[0x30] & 0x00FF00 < base[0x30] & 0x00FF00);
+    assert!(red[0x30] & 0x0000FF < base[0x30] & 0x0000FF);
+}
+
+#[test]
+fn test_raw_index_video_sink() {
+    let frame_buffer: Vec<u8> = (0..256).map(|i| (i % 64) as u8).collect();
+    let mut buffer = vec![0u16; frame_buffer.len()];
+
+    let mut sink = RawIndexVideoSink::new(&mut buffer);
+    sink.write_frame(&frame_buffer);
+    assert!(sink.frame_written());
+    for (raw, &index) in buffer.iter().zip(frame_buffer.iter()) {
+        assert_eq!(*raw, index as u16);
+    }
+
+    let mut sink = RawIndexVideoSink::new(&mut buffer);
+    sink.set_emphasis(0x05);
+    sink.write_frame(&frame_buffer);
+    for (raw, &index) in buffer.iter().zip(frame_buffer.iter()) {
+        assert_eq!(*raw & 0x3F, index as u16);
+        assert_eq!(*raw >> 6, 0x05);
+    }
+}
+
+#[test]
+fn test_frame_blender() {
+    let first = [0x000000, 0xFF0000, 0x102030, 0x0F0F0F];
+    let second = [0xFFFFFF, 0x00FF00, 0x302010, 0x0F0F0F];
+
+    let mut blender = FrameBlender::new();
+    let mut frame = first;
+    blender.blend(&mut frame);
+    assert_eq!(frame, first);
+
+    let mut frame = second;
+    blender.blend(&mut frame);
+    for (i, &blended) in frame.iter().enumerate() {
+        for shift in [0, 8, 16] {
+            let a = (first[i] >> shift) & 0xFF;
+            let b = (second[i] >> shift) & 0xFF;
+            assert_eq!((blended >> shift) & 0xFF, (a + b) / 2);
+        }
+    }
+}
+
+#[test]
+fn test_quantizer_levels() {
+    use std::collections::HashSet;
+
+    // A gray gradient 256 pixels wide and 4 rows tall, covering every dither threshold
+    let gradient: Vec<u32> = (0..256 * 4).map(|i| (i % 256) as u32 * 0x010101).collect();
+
+    for depth in [ColorDepth::OneBit, ColorDepth::TwoBit, ColorDepth::Gray4] {
+        for dither in [false, true] {
+            let mut frame = gradient.clone();
+            Quantizer::new(depth, dither).quantize(&mut frame, 256);
+
+            let levels: HashSet<u32> = frame.iter().copied().collect();
+            assert_eq!(levels.len() as u32, depth.levels(), "{:?}", depth);
+            assert!(levels.contains(&0x000000));
+            assert!(levels.contains(&0xFFFFFF));
+        }
+    }
+}
+
+#[test]
+fn test_multi_video_sink() {
+    let frame_buffer: Vec<u8> = (0..256).map(|i| (i % 64) as u8).collect();
+    let mut first = vec![0u32; frame_buffer.len()];
+    let mut second = vec![0u32; frame_buffer.len()];
+
+    let mut sink = MultiVideoSink::new(vec![Box::new(Xrgb8888VideoSink::new(&mut first))]);
+    sink.push(Box::new(Xrgb8888VideoSink::new(&mut second)));
+    assert!(!sink.frame_written());
+    assert_eq!(sink.pixel_size(), mem::size_of::<u32>());
+    sink.write_frame(&frame_buffer);
+    assert!(sink.frame_written());
+    drop(sink);
+
+    assert_eq!(first, second);
+    assert_eq!(first[0x30], XRGB8888_PALETTE[0x30]);
+}
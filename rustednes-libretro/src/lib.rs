@@ -36,7 +36,6 @@ use system_av_info::*;
 use system_info::*;
 
 use std::ffi::CStr;
-use std::io::Cursor;
 use std::slice;
 use std::{mem, ptr};
 
@@ -54,9 +53,10 @@ struct System {
 
 impl System {
     fn new(cartridge: Cartridge) -> System {
-        System {
-            nes: Nes::new(cartridge),
-        }
+        let mut nes = Nes::new(cartridge);
+        // Libretro always reports a second joypad
+        nes.set_controller_type(1, ControllerType::Standard);
+        System { nes }
     }
 
     fn reset(&mut self) {
@@ -110,7 +110,7 @@ impl Context {
                 PixelFormat::Xrgb8888 => OutputBuffer::Xrgb8888(vec![0; DISPLAY_PIXELS]),
             };
 
-            match Cartridge::load(&mut Cursor::new(game_info.data_ref())) {
+            match Cartridge::from_bytes(game_info.data_ref()) {
                 Ok(cartridge) => {
                     self.system = Some(System::new(cartridge));
                     true
@@ -137,7 +137,9 @@ impl Context {
             },
             timing: SystemTiming {
                 fps: 60.1,
-                sample_rate: f64::from(SAMPLE_RATE),
+                sample_rate: f64::from(self.system.as_ref().map_or(SAMPLE_RATE, |system| {
+                    system.nes.interconnect.apu.sample_rate()
+                })),
             },
         }
     }
@@ -169,10 +171,12 @@ impl Context {
                             ),
                             1 => (
                                 fb.data,
-                                Box::new(Xrgb8888VideoSink::new(slice::from_raw_parts_mut(
-                                    fb.data as *mut _,
-                                    (fb.height as usize) * fb.pitch / mem::size_of::<u32>(),
-                                ))),
+                                Box::new(Xrgb8888EmphasisVideoSink::new(
+                                    slice::from_raw_parts_mut(
+                                        fb.data as *mut _,
+                                        (fb.height as usize) * fb.pitch / mem::size_of::<u32>(),
+                                    ),
+                                )),
                             ),
                             2 => (
                                 fb.data,
@@ -192,7 +196,7 @@ impl Context {
                             ),
                             OutputBuffer::Xrgb8888(ref mut buffer) => (
                                 buffer.as_mut_ptr() as *mut c_void,
-                                Box::new(Xrgb8888VideoSink::new(buffer)),
+                                Box::new(Xrgb8888EmphasisVideoSink::new(buffer)),
                             ),
                             OutputBuffer::Rgb565(ref mut buffer) => (
                                 buffer.as_mut_ptr() as *mut c_void,
@@ -204,10 +208,13 @@ impl Context {
                 let rendered_audio_frames = {
                     let mut audio_output_sink = AudioSinkI16::new(&mut self.audio_frame_buffer);
 
-                    while !video_output_sink.frame_written() {
-                        system
+                    loop {
+                        let (_, _, frame_ready) = system
                             .nes
                             .step(&mut video_output_sink, &mut audio_output_sink);
+                        if frame_ready {
+                            break;
+                        }
                     }
 
                     audio_output_sink.samples_written()
@@ -425,11 +432,13 @@ pub unsafe extern "C" fn retro_serialize_size() -> size_t {
         let state = system.get_nes_state();
 
         // Serialize to get size and cache result to use in retro_serialize
-        (*CONTEXT).serialized = match (*CONTEXT).serialized {
+        let serialized = match (*CONTEXT).serialized {
             Some(_) => serde_cbor::ser::to_vec_packed(&state).ok(),
             // The first time we get the size return non packed version to make sure we have enough room for all future save states
             None => serde_cbor::ser::to_vec(&state).ok(),
         };
+        (*CONTEXT).serialized =
+            serialized.map(|s| [&serialize::header(&system.nes)[..], &s].concat());
 
         if let Some(ref s) = (*CONTEXT).serialized {
             return s.len();
@@ -460,7 +469,16 @@ pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: size_t) -> boo
 #[no_mangle]
 pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: size_t) -> bool {
     if let Some(ref mut system) = (*CONTEXT).system {
-        match serde_cbor::from_slice(slice::from_raw_parts(data as _, size)) {
+        let data =
+            match serialize::check_header(&system.nes, slice::from_raw_parts(data as _, size)) {
+                Ok(data) => data,
+                Err(e) => {
+                    println!("Unable to deserialize data. {}", e);
+                    return false;
+                }
+            };
+
+        match serde_cbor::from_slice(data) {
             Ok(state) => {
                 system.apply_nes_state(state);
                 return true;
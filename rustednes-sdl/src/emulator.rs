@@ -1,14 +1,18 @@
+use crate::rom_list::RomList;
+
 use rustednes_common::debugger::{DebugEmulator, Debugger};
 use rustednes_common::emulation_mode::EmulationMode;
 use rustednes_common::state::StateManager;
 use rustednes_common::time::TimeSource;
 
+use rustednes_core::apu::{write_register_log, CPU_CYCLES_PER_SAMPLE};
 use rustednes_core::cartridge::Cartridge;
-use rustednes_core::cpu::CPU_FREQUENCY;
-use rustednes_core::input::Button;
+use rustednes_core::cpu::{IllegalMode, Region};
+use rustednes_core::input::{Button, Turbo, TurboMode};
+use rustednes_core::input_log::InputLog;
 use rustednes_core::mapper::Mapper;
 use rustednes_core::memory::Memory;
-use rustednes_core::nes::Nes;
+use rustednes_core::nes::{Accuracy, Nes};
 use rustednes_core::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
 use rustednes_core::sink::*;
 
@@ -19,13 +23,21 @@ use sdl2::rect::Rect;
 use sdl2::render::{Canvas, Texture};
 use sdl2::video::{FullscreenType, Window};
 use sdl2::{EventPump, Sdl};
-use tracing::error;
-
-use std::path::PathBuf;
-use std::time::Duration;
+use tracing::{error, info, warn};
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 use std::{mem, thread};
 
-const CPU_CYCLE_TIME_NS: u64 = (1e9_f64 / CPU_FREQUENCY as f64) as u64 + 1;
+const MIN_SPEED_PERCENT: u32 = 10;
+const MAX_SPEED_PERCENT: u32 = 1000;
+const SPEED_STEP_PERCENT: u32 = 10;
+// Roughly a tenth of a second of emulation, far more than any frame needs
+const DEFAULT_INSTRUCTION_BUDGET: u64 = 100_000;
 const DEBUG_WIDTH: u32 = 256;
 const DEBUG_HEIGHT: u32 = 176;
 const NUMBER_KEYCODES: &[Keycode] = &[
@@ -41,8 +53,40 @@ const NUMBER_KEYCODES: &[Keycode] = &[
     Keycode::Num9,
 ];
 
+/// The process exit status when the CPU traps with `set_exit_on_trap` enabled.
+pub const TRAP_EXIT_CODE: i32 = 3;
+
+/// The process exit status when a frame hash assertion fails.
+pub const HASH_MISMATCH_EXIT_CODE: i32 = 4;
+
+/// Save state slots 0 to 7, with Shift to load.
+const STATE_SLOT_KEYCODES: &[Keycode] = &[
+    Keycode::F1,
+    Keycode::F2,
+    Keycode::F3,
+    Keycode::F4,
+    Keycode::F5,
+    Keycode::F6,
+    Keycode::F7,
+    Keycode::F8,
+];
+
+const TITLE: &str = "RustedNES";
+
+/// How long a message such as a save state confirmation stays in the title bar.
+const TITLE_MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+/// Loads the cartridge at a path, for switching between ROMs.
+pub type RomLoader = Box<dyn Fn(&Path) -> Result<Cartridge, Box<dyn Error>>>;
+
+/// Moves audio playback to the next output device, returning its name.
+pub type AudioDeviceSwitcher = Box<dyn FnMut() -> Result<String, String>>;
+
 pub struct Emulator<A: AudioSink, T: TimeSource> {
     nes: Nes,
+    rom_list: RomList,
+    rom_loader: Option<RomLoader>,
+    audio_device_switcher: Option<AudioDeviceSwitcher>,
 
     sdl_context: Sdl,
 
@@ -50,14 +94,46 @@ pub struct Emulator<A: AudioSink, T: TimeSource> {
     audio_frame_sink: A,
     time_source: T,
     start_time_ns: u64,
+    start_cycles: u64,
 
     emulated_cycles: u64,
     emulated_instructions: u64,
+    speed_percent: u32,
+    fixed_step_cycles: Option<u64>,
+    instruction_budget: u64,
+    crop: Crop,
+    pixel_aspect: PixelAspect,
+    blender: Option<FrameBlender>,
+    quantizer: Option<Quantizer>,
+    frame_advance: bool,
+    turbo: Turbo,
+    coverage_path: Option<PathBuf>,
+    register_log_path: Option<PathBuf>,
+    log_self_modifications: bool,
+    warn_decimal: bool,
+    input_recording: Option<(PathBuf, InputLog)>,
+    input_playback: Option<InputLog>,
+    // Frame the input log was last synced at
+    input_log_frame: Option<u32>,
+    // Keyboard buttons waiting for the next frame while recording
+    pending_buttons: Option<u8>,
+    accuracy: Accuracy,
+    region: Region,
+    cpu_cycles_per_sample: NonZeroU64,
+    exit_on_trap: bool,
+    // Frames rendering may stay off for before the watchdog trips, with exit on trap
+    watchdog_frames: Option<u64>,
+    // The last frame rendering was on during, or the first frame of the ROM
+    last_rendered_frame: u64,
+    exit_code: Option<i32>,
+    title_message_until: Option<Instant>,
 
     debugging_graphics: bool,
     debug_palette_selector: usize,
 
     state_manager: StateManager,
+    // Where the cartridge's battery-backed PRG RAM is kept, if it has any
+    battery_path: Option<PathBuf>,
 }
 
 impl<A, T> Emulator<A, T>
@@ -76,8 +152,12 @@ where
         A: AudioSink,
         T: TimeSource,
     {
-        Emulator {
+        let battery_path = battery_path(&cartridge, &rom_path);
+        let mut emulator = Emulator {
             nes: Nes::new(cartridge),
+            rom_list: RomList::new(vec![rom_path.clone()]),
+            rom_loader: None,
+            audio_device_switcher: None,
 
             sdl_context,
 
@@ -85,15 +165,190 @@ where
             audio_frame_sink,
             time_source,
             start_time_ns: 0,
+            start_cycles: 0,
 
             emulated_cycles: 0,
             emulated_instructions: 0,
+            speed_percent: 100,
+            fixed_step_cycles: None,
+            instruction_budget: DEFAULT_INSTRUCTION_BUDGET,
+            crop: Crop::default(),
+            pixel_aspect: PixelAspect::default(),
+            blender: None,
+            quantizer: None,
+            frame_advance: false,
+            turbo: Turbo::new(),
+            coverage_path: None,
+            register_log_path: None,
+            log_self_modifications: false,
+            warn_decimal: false,
+            input_recording: None,
+            input_playback: None,
+            input_log_frame: None,
+            pending_buttons: None,
+            accuracy: Accuracy::default(),
+            region: Region::default(),
+            cpu_cycles_per_sample: NonZeroU64::new(CPU_CYCLES_PER_SAMPLE).unwrap(),
+            exit_on_trap: false,
+            watchdog_frames: None,
+            last_rendered_frame: 0,
+            exit_code: None,
+            title_message_until: None,
 
             debugging_graphics: false,
             debug_palette_selector: 0,
 
-            state_manager: StateManager::new(rom_path, NUMBER_KEYCODES.len()),
-        }
+            state_manager: state_manager(rom_path),
+            battery_path,
+        };
+        emulator.load_battery_ram();
+        emulator
+    }
+
+    /// Sets the ROMs that can be switched between, the first of which must be the one already
+    /// loaded, and how to load them.
+    pub fn set_rom_list(&mut self, rom_list: RomList, rom_loader: RomLoader) {
+        self.rom_list = rom_list;
+        self.rom_loader = Some(rom_loader);
+    }
+
+    /// Sets how to switch to the next audio output device, such as after headphones are plugged
+    /// in.
+    pub fn set_audio_device_switcher(&mut self, audio_device_switcher: AudioDeviceSwitcher) {
+        self.audio_device_switcher = Some(audio_device_switcher);
+    }
+
+    /// Records which PRG ROM bytes are executed, and writes them to `path` as an FCEUX code/data
+    /// log on exit.
+    pub fn enable_coverage(&mut self, path: PathBuf) {
+        self.nes.enable_coverage();
+        self.coverage_path = Some(path);
+    }
+
+    /// Records every APU register write, and writes them to `path` with their CPU cycle on exit.
+    pub fn enable_register_log(&mut self, path: PathBuf) {
+        self.nes.interconnect.apu.enable_register_log();
+        self.register_log_path = Some(path);
+    }
+
+    /// Records controller input from power-on, and writes it to `path` as an input log on exit.
+    /// Keyboard input takes effect at the start of the next frame, so the log replays exactly.
+    pub fn record_input(&mut self, path: PathBuf) {
+        self.input_recording = Some((path, InputLog::new()));
+    }
+
+    /// Drives the controllers from `log` instead of the keyboard.
+    pub fn play_input(&mut self, log: InputLog) {
+        self.input_playback = Some(log);
+    }
+
+    /// Logs every write by code running from RAM or PRG RAM into the bank it's running from.
+    pub fn enable_self_modification_log(&mut self) {
+        self.nes.interconnect.enable_self_modification_log();
+        self.log_self_modifications = true;
+    }
+
+    /// Warns about ADC and SBC instructions run with the decimal flag set, which the NES ignores.
+    pub fn enable_decimal_warnings(&mut self) {
+        self.nes.cpu.enable_decimal_warnings();
+        self.warn_decimal = true;
+    }
+
+    /// Sets the number of pixels cropped from each edge of the picture when presenting it.
+    pub fn set_crop(&mut self, crop: Crop) {
+        self.crop = crop;
+    }
+
+    /// Sets the shape of each pixel when presenting the picture.
+    pub fn set_pixel_aspect(&mut self, pixel_aspect: PixelAspect) {
+        self.pixel_aspect = pixel_aspect;
+    }
+
+    /// Enables or disables averaging each frame with the previous one to soften sprite flicker.
+    pub fn set_blend(&mut self, enabled: bool) {
+        self.blender = if enabled {
+            Some(FrameBlender::new())
+        } else {
+            None
+        };
+    }
+
+    /// Reduces the picture to the gray levels of `depth`, with ordered dithering if `dither` is
+    /// set, or shows full color when `depth` is `None`.
+    pub fn set_color_depth(&mut self, depth: Option<ColorDepth>, dither: bool) {
+        self.quantizer = depth.map(|depth| Quantizer::new(depth, dither));
+    }
+
+    /// Sets whether the turbo key for `button` fires while held or toggles turbo on and off.
+    pub fn set_turbo_mode(&mut self, button: Button, mode: TurboMode) {
+        self.turbo.set_mode(button, mode);
+    }
+
+    /// Sets the emulation speed as a percentage of real NES speed.
+    ///
+    /// Audio is not time-stretched, so at speeds other than 100% it will be choppy or
+    /// drop samples.
+    pub fn set_speed_percent(&mut self, speed_percent: u32) {
+        self.speed_percent = speed_percent.clamp(MIN_SPEED_PERCENT, MAX_SPEED_PERCENT);
+        self.reset_start_time();
+    }
+
+    /// Emulates exactly `cycles` CPU cycles per loop iteration instead of pacing emulation by
+    /// real time, so profiles of the emulation loop aren't skewed by waiting.
+    pub fn set_fixed_step(&mut self, cycles: u64) {
+        self.fixed_step_cycles = Some(cycles);
+    }
+
+    /// Caps the number of instructions emulated between presents, so a ROM that falls far behind
+    /// real time can't keep the loop from handling input or starting the debugger.
+    pub fn set_instruction_budget(&mut self, instructions: u64) {
+        self.instruction_budget = instructions.max(1);
+    }
+
+    /// Stops emulation with `TRAP_EXIT_CODE` when the CPU traps, instead of starting the
+    /// debugger.
+    pub fn set_exit_on_trap(&mut self, enabled: bool) {
+        self.exit_on_trap = enabled;
+    }
+
+    /// With exit on trap, also stops with `TRAP_EXIT_CODE` when rendering stays off for `frames`
+    /// frames, which is how a hung game usually looks.
+    pub fn set_watchdog_frames(&mut self, frames: u64) {
+        self.watchdog_frames = Some(frames.max(1));
+    }
+
+    /// Presets the flags that trade speed for accuracy.
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.nes.set_accuracy(accuracy);
+        self.accuracy = accuracy;
+    }
+
+    /// Selects the console region, which sets the CPU clock emulation is paced by along with the
+    /// frame timing and audio sample rate.
+    pub fn set_region(&mut self, region: Region) {
+        self.nes.set_region(region);
+        self.region = region;
+        self.reset_start_time();
+    }
+
+    /// Generates an APU sample every `cpu_cycles_per_sample` CPU cycles. The audio sink must
+    /// resample from the matching `apu::sample_rate`.
+    pub fn set_internal_rate(&mut self, cpu_cycles_per_sample: NonZeroU64) {
+        self.nes
+            .interconnect
+            .apu
+            .set_internal_rate(cpu_cycles_per_sample);
+        self.cpu_cycles_per_sample = cpu_cycles_per_sample;
+    }
+
+    /// Whether unofficial opcodes run or trap.
+    pub fn set_illegal_mode(&mut self, illegal_mode: IllegalMode) {
+        self.nes.cpu.set_illegal_mode(illegal_mode);
+    }
+
+    /// The status the process should exit with once `run` or `run_headless` returns, if any.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
     }
 
     pub fn run(&mut self, start_debugger: bool) {
@@ -116,11 +371,7 @@ where
         debug_canvas.present();
 
         let window = video_subsystem
-            .window(
-                "RustedNES",
-                SCREEN_WIDTH as u32 * 2,
-                SCREEN_HEIGHT as u32 * 2,
-            )
+            .window(TITLE, SCREEN_WIDTH as u32 * 2, SCREEN_HEIGHT as u32 * 2)
             .position_centered()
             .resizable()
             .maximized()
@@ -143,7 +394,8 @@ where
             )
             .unwrap();
 
-        self.start_time_ns = self.time_source.time_ns();
+        self.reset_start_time();
+        let started = (Instant::now(), self.nes.elapsed_cycles());
 
         let mut debugger = Debugger::new();
 
@@ -164,28 +416,47 @@ where
                 break;
             }
 
+            // While paused, emulate a single frame per frame advance request, sampling input once
+            // for it.
+            let advance_frame = self.take_frame_advance();
+            if advance_frame {
+                self.update_gamepad(event_pump.keyboard_state());
+            }
+
             let mut frame_written = false;
             let mut quit = false;
+            // The debugger borrows the whole emulator, so the sink owns the blender meanwhile
+            let mut blender = self.blender.take();
             canvas
                 .with_texture_canvas(&mut texture, |canvas| {
                     // Run enough emulator cycles to catch up with the time that has passed since the
                     // previous loop iteration.
-                    let mut video_frame_sink = CanvasVideoSink::new(canvas);
-                    let target_time_ns = self.time_source.time_ns() - self.start_time_ns;
-                    let target_cycles = target_time_ns / CPU_CYCLE_TIME_NS;
+                    let mut video_frame_sink =
+                        CanvasVideoSink::new(canvas, blender.as_mut(), self.quantizer);
+                    let target_cycles = self.next_target_cycles();
 
                     match self.mode {
                         EmulationMode::Running => {
                             let mut start_debugger = false;
+                            let start_instructions = self.emulated_instructions;
                             while self.emulated_cycles < target_cycles && !start_debugger {
-                                let (cycles, trigger_watchpoint) = self
-                                    .nes
-                                    .step(&mut video_frame_sink, &mut self.audio_frame_sink);
+                                if self.over_instruction_budget(start_instructions) {
+                                    break;
+                                }
 
-                                self.emulated_cycles += cycles as u64;
-                                self.emulated_instructions += 1;
+                                let regs = self.nes.cpu.regs();
+                                let (_, trigger_watchpoint) = self.step(&mut video_frame_sink);
+
+                                if self.exit_code.is_some() {
+                                    quit = true;
+                                    return;
+                                }
 
-                                if trigger_watchpoint || debugger.at_breakpoint(&self.nes) {
+                                if trigger_watchpoint
+                                    || debugger.at_breakpoint(&self.nes)
+                                    || debugger.at_register_breakpoint(regs, self.nes.cpu.regs())
+                                    || self.nes.cpu.trap().is_some()
+                                {
                                     start_debugger = true;
                                 }
                             }
@@ -195,6 +466,11 @@ where
                                 debugger.start(&mut self.nes);
                             }
                         }
+                        EmulationMode::Paused => {
+                            if advance_frame {
+                                self.run_frame(&mut video_frame_sink);
+                            }
+                        }
                         EmulationMode::Debugging => {
                             if debugger.run_commands(self, &mut video_frame_sink) {
                                 quit = true;
@@ -206,6 +482,7 @@ where
                     frame_written = video_frame_sink.frame_written();
                 })
                 .unwrap();
+            self.blender = blender;
 
             if quit {
                 break;
@@ -222,22 +499,301 @@ where
                 self.render_debug_window(&mut debug_canvas);
             }
 
-            thread::sleep(Duration::new(0, 1_000_000_000 / 60));
+            self.update_title(&mut canvas);
+
+            self.sleep();
         }
 
+        self.log_speed(started);
         self.cleanup(&mut canvas);
     }
 
+    /// Runs without creating any windows, discarding video and pacing emulation by the time
+    /// source, until interrupted or the CPU traps with exit on trap enabled. SDL turns SIGINT and
+    /// SIGTERM into quit events, so Ctrl-C returns normally and saves are still written.
+    pub fn run_headless(&mut self) {
+        let mut video_frame_sink = NullVideoSink::new();
+        let mut event_pump = self.sdl_context.event_pump().unwrap();
+
+        self.reset_start_time();
+        let started = (Instant::now(), self.nes.elapsed_cycles());
+
+        while self.exit_code.is_none() {
+            if event_pump
+                .poll_iter()
+                .any(|event| matches!(event, Event::Quit { .. }))
+            {
+                info!("Interrupted, exiting");
+                break;
+            }
+
+            self.run_headless_to_now(&mut video_frame_sink);
+            self.sleep();
+        }
+
+        self.log_speed(started);
+    }
+
+    /// Logs how many CPU cycles were emulated per second of real time since `started`, the time
+    /// and elapsed cycle count when emulation began.
+    fn log_speed(&self, (start_time, start_cycles): (Instant, u64)) {
+        let cycles = self.nes.elapsed_cycles() - start_cycles;
+        info!(
+            "CPU clock: {} cycles per second measured over {} cycles",
+            cycles_per_second(cycles, start_time.elapsed()),
+            cycles
+        );
+    }
+
+    /// Runs without windows or pacing until `assertion.frame` frames have been rendered, then
+    /// compares the last frame's hash against the expected one, exiting with
+    /// `HASH_MISMATCH_EXIT_CODE` if they differ.
+    pub fn run_hash_assertion(&mut self, assertion: HashAssertion) {
+        let mut video_frame_sink = NullVideoSink::new();
+        while self.nes.interconnect.ppu.frame() < assertion.frame && self.exit_code.is_none() {
+            self.step(&mut video_frame_sink);
+        }
+        if self.exit_code.is_some() {
+            return;
+        }
+
+        let hash = self.nes.frame_hash();
+        if hash == assertion.hash {
+            info!("Frame {} hash {:016x} matches", assertion.frame, hash);
+        } else {
+            error!(
+                "Frame {} hash {:016x} doesn't match the expected {:016x}",
+                assertion.frame, hash, assertion.hash
+            );
+            self.exit_code = Some(HASH_MISMATCH_EXIT_CODE);
+        }
+    }
+
+    /// Runs enough emulator cycles to catch up with the time source, ignoring breakpoints and
+    /// watchpoints.
+    fn run_headless_to_now(&mut self, video_frame_sink: &mut NullVideoSink) {
+        let target_cycles = self.next_target_cycles();
+        let start_instructions = self.emulated_instructions;
+        while self.emulated_cycles < target_cycles && self.exit_code.is_none() {
+            if self.over_instruction_budget(start_instructions) {
+                break;
+            }
+
+            self.step(video_frame_sink);
+        }
+    }
+
+    /// Whether this loop iteration has used up its instruction budget. When it has, the cycles
+    /// still owed are dropped so the next iteration doesn't try to catch up on them. A fixed step
+    /// isn't paced by real time, so it always runs in full.
+    fn over_instruction_budget(&mut self, start_instructions: u64) -> bool {
+        if self.fixed_step_cycles.is_some()
+            || self.emulated_instructions - start_instructions < self.instruction_budget
+        {
+            return false;
+        }
+
+        self.reset_start_time();
+        true
+    }
+
+    /// The number of cycles that should have been emulated by the end of this loop iteration.
+    /// With audio enabled the time source counts the samples the device has played, so
+    /// emulation is paced by the audio clock and can't drift from it.
+    fn next_target_cycles(&mut self) -> u64 {
+        match self.fixed_step_cycles {
+            Some(step_cycles) => {
+                // Any cycles emulated past the previous target count towards this one
+                self.start_cycles += step_cycles;
+                self.start_cycles
+            }
+            None => {
+                let target_time_ns = self.time_source.time_ns() - self.start_time_ns;
+                self.start_cycles + target_time_ns / cycle_time_ns(self.region, self.speed_percent)
+            }
+        }
+    }
+
+    /// Waits between loop iterations, unless running with a fixed step.
+    fn sleep(&self) {
+        if self.fixed_step_cycles.is_none() {
+            thread::sleep(Duration::new(0, 1_000_000_000 / 60));
+        }
+    }
+
+    /// Switches to the next ROM in the list, or the previous one if `forward` is false, keeping
+    /// the window.
+    fn switch_rom(&mut self, forward: bool) {
+        let Some(ref rom_loader) = self.rom_loader else {
+            return;
+        };
+        if self.rom_list.len() < 2 {
+            return;
+        }
+
+        let rom_path = if forward {
+            self.rom_list.next()
+        } else {
+            self.rom_list.prev()
+        }
+        .to_path_buf();
+        match rom_loader(&rom_path) {
+            Ok(cartridge) => {
+                info!(
+                    "Switching to ROM {} of {}: {}",
+                    self.rom_list.index() + 1,
+                    self.rom_list.len(),
+                    rom_path.display()
+                );
+                self.load_cartridge(cartridge, rom_path);
+            }
+            Err(e) => {
+                error!("Unable to load {}: {}", rom_path.display(), e);
+                // Keep the index on the ROM that's still running
+                if forward {
+                    self.rom_list.prev();
+                } else {
+                    self.rom_list.next();
+                }
+            }
+        }
+    }
+
+    fn load_cartridge(&mut self, cartridge: Cartridge, rom_path: PathBuf) {
+        // Save states and battery RAM are kept per ROM, so write out the previous ROM's first
+        self.state_manager.write_state_to_files();
+        self.write_battery_ram();
+        self.battery_path = battery_path(&cartridge, &rom_path);
+        self.state_manager = state_manager(rom_path);
+
+        let illegal_mode = self.nes.cpu.illegal_mode();
+        self.nes = Nes::new(cartridge);
+        self.nes.cpu.set_illegal_mode(illegal_mode);
+        self.nes.set_accuracy(self.accuracy);
+        self.nes.set_region(self.region);
+        self.nes
+            .interconnect
+            .apu
+            .set_internal_rate(self.cpu_cycles_per_sample);
+        self.load_battery_ram();
+        self.input_log_frame = None;
+        self.last_rendered_frame = 0;
+        if self.coverage_path.is_some() {
+            self.nes.enable_coverage();
+        }
+        if self.register_log_path.is_some() {
+            self.nes.interconnect.apu.enable_register_log();
+        }
+        if self.log_self_modifications {
+            self.nes.interconnect.enable_self_modification_log();
+        }
+        if self.warn_decimal {
+            self.nes.cpu.enable_decimal_warnings();
+        }
+        if let Some(ref mut blender) = self.blender {
+            *blender = FrameBlender::new();
+        }
+        self.reset_start_time();
+    }
+
     fn step<V: VideoSink>(&mut self, video_frame_sink: &mut V) -> (u32, bool) {
-        let (cycles, trigger_watchpoint) =
+        self.sync_input_log();
+
+        let trapped = self.nes.cpu.trap().is_some();
+        let (cycles, trigger_watchpoint, _) =
             self.nes.step(video_frame_sink, &mut self.audio_frame_sink);
 
         self.emulated_cycles += cycles as u64;
         self.emulated_instructions += 1;
 
+        if let Some(writes) = self.nes.interconnect.take_self_modifications() {
+            for write in writes {
+                info!(
+                    "Self-modifying write of ${:02X} to ${:04X} at ${:04X}",
+                    write.value, write.address, write.pc
+                );
+            }
+        }
+
+        if let Some(addresses) = self.nes.cpu.take_decimal_warnings() {
+            for address in addresses {
+                warn!(
+                    "Decimal mode arithmetic at ${:04X}, which the NES does in binary",
+                    address
+                );
+            }
+        }
+
+        if let (false, Some(trap)) = (trapped, self.nes.cpu.trap()) {
+            error!("CPU trapped: {}", trap);
+            if self.exit_on_trap {
+                self.exit_code = Some(TRAP_EXIT_CODE);
+            }
+        }
+
+        if self.exit_on_trap {
+            self.check_watchdog();
+        }
+
         (cycles, trigger_watchpoint)
     }
 
+    fn check_watchdog(&mut self) {
+        let ppu = &self.nes.interconnect.ppu;
+        if ppu.rendering_enabled() {
+            self.last_rendered_frame = ppu.frame();
+            return;
+        }
+
+        let Some(watchdog_frames) = self.watchdog_frames else {
+            return;
+        };
+        let dark_frames = ppu.frame().saturating_sub(self.last_rendered_frame);
+        if dark_frames >= watchdog_frames && self.exit_code.is_none() {
+            error!(
+                "Watchdog: rendering has been off for {} frames, at ${:04X}",
+                dark_frames,
+                self.nes.cpu.regs().pc
+            );
+            self.exit_code = Some(TRAP_EXIT_CODE);
+        }
+    }
+
+    /// Whether a frame advance was requested while paused, clearing the request so each one runs a
+    /// single frame.
+    fn take_frame_advance(&mut self) -> bool {
+        self.mode == EmulationMode::Paused && mem::take(&mut self.frame_advance)
+    }
+
+    /// Runs until the current frame is complete. Steps through `step`, so that frame advance goes
+    /// through the input log like any other frame.
+    fn run_frame<V: VideoSink>(&mut self, video_frame_sink: &mut V) {
+        let frame = self.nes.interconnect.ppu.frame();
+        while self.nes.interconnect.ppu.frame() == frame && self.exit_code.is_none() {
+            self.step(video_frame_sink);
+        }
+    }
+
+    /// Plays back or records the controllers once at the start of each frame.
+    fn sync_input_log(&mut self) {
+        let frame = self.nes.interconnect.ppu.frame() as u32;
+        if self.input_log_frame == Some(frame) {
+            return;
+        }
+        self.input_log_frame = Some(frame);
+
+        let input = &mut self.nes.interconnect.input;
+        if let Some(ref mut log) = self.input_playback {
+            log.play(frame, input);
+        }
+        if let Some((_, ref mut log)) = self.input_recording {
+            if let Some(buttons) = self.pending_buttons.take() {
+                input.game_pad_1.set_buttons(buttons);
+            }
+            log.record(frame, input);
+        }
+    }
+
     /// Returns false to signal to end emulation.
     fn handle_events(
         &mut self,
@@ -285,6 +841,27 @@ where
                         (Keycode::F12, Mod::LSHIFTMOD | Mod::RSHIFTMOD) if main_window => {
                             self.toggle_debugging(debug_canvas);
                         }
+                        (Keycode::Pause, Mod::NOMOD) => match self.mode {
+                            EmulationMode::Running => {
+                                self.mode = EmulationMode::Paused;
+                                info!("Paused");
+                            }
+                            EmulationMode::Paused => {
+                                self.mode = EmulationMode::Running;
+                                self.reset_start_time();
+                                info!("Resumed");
+                            }
+                            EmulationMode::Debugging => {}
+                        },
+                        (Keycode::Period, Mod::NOMOD) if self.mode == EmulationMode::Paused => {
+                            self.frame_advance = true;
+                        }
+                        (Keycode::PageDown, Mod::NOMOD) => {
+                            self.switch_rom(true);
+                        }
+                        (Keycode::PageUp, Mod::NOMOD) => {
+                            self.switch_rom(false);
+                        }
                         (Keycode::Space, Mod::NOMOD) if debug_window => {
                             self.cycle_debug_palette_selector();
                         }
@@ -296,6 +873,16 @@ where
                             let settings = &mut self.nes.interconnect.apu.settings;
                             settings.pulse_2_enabled = !settings.pulse_2_enabled;
                         }
+                        (Keycode::LeftBracket, Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                            self.set_speed_percent(
+                                self.speed_percent.saturating_sub(SPEED_STEP_PERCENT),
+                            );
+                            info!("Speed: {}%", self.speed_percent);
+                        }
+                        (Keycode::RightBracket, Mod::LCTRLMOD | Mod::RCTRLMOD) => {
+                            self.set_speed_percent(self.speed_percent + SPEED_STEP_PERCENT);
+                            info!("Speed: {}%", self.speed_percent);
+                        }
                         (Keycode::T, Mod::NOMOD) => {
                             let settings = &mut self.nes.interconnect.apu.settings;
                             settings.triangle_enabled = !settings.triangle_enabled;
@@ -312,6 +899,14 @@ where
                             let settings = &mut self.nes.interconnect.apu.settings;
                             settings.filter_enabled = !settings.filter_enabled;
                         }
+                        (Keycode::O, Mod::NOMOD) => {
+                            if let Some(ref mut switch_audio_device) = self.audio_device_switcher {
+                                match switch_audio_device() {
+                                    Ok(name) => info!("Audio output: {}", name),
+                                    Err(e) => error!("Unable to switch audio output: {}", e),
+                                }
+                            }
+                        }
                         _ => {}
                     }
 
@@ -319,9 +914,20 @@ where
                     for (slot, &num_keycode) in NUMBER_KEYCODES.iter().enumerate() {
                         if keycode == num_keycode {
                             if ctrl_mod {
-                                self.state_manager.load_state(&mut self.nes, slot);
+                                self.load_state(canvas, slot);
+                            } else {
+                                self.save_state(canvas, slot);
+                            }
+                        }
+                    }
+
+                    let shift_mod = matches!(keymod, Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                    for (slot, &slot_keycode) in STATE_SLOT_KEYCODES.iter().enumerate() {
+                        if keycode == slot_keycode {
+                            if shift_mod {
+                                self.load_state(canvas, slot);
                             } else {
-                                self.state_manager.save_state(&self.nes, slot);
+                                self.save_state(canvas, slot);
                             }
                         }
                     }
@@ -344,8 +950,7 @@ where
                         _ => {}
                     }
 
-                    self.start_time_ns =
-                        self.time_source.time_ns() - (self.emulated_cycles * CPU_CYCLE_TIME_NS);
+                    self.reset_start_time();
                 }
                 Event::Quit { .. } => return false,
                 _ => {}
@@ -355,8 +960,47 @@ where
         true
     }
 
+    fn reset_start_time(&mut self) {
+        self.start_time_ns = self.time_source.time_ns();
+        self.start_cycles = self.emulated_cycles;
+    }
+
+    fn save_state(&mut self, canvas: &mut Canvas<Window>, slot: usize) {
+        if self.state_manager.save_state(&self.nes, slot) {
+            self.show_title_message(canvas, &format!("Saved state {}", slot));
+        }
+    }
+
+    fn load_state(&mut self, canvas: &mut Canvas<Window>, slot: usize) {
+        if self.state_manager.load_state(&mut self.nes, slot) {
+            self.show_title_message(canvas, &format!("Loaded state {}", slot));
+        }
+    }
+
+    /// Shows `message` in the title bar for `TITLE_MESSAGE_DURATION`.
+    fn show_title_message(&mut self, canvas: &mut Canvas<Window>, message: &str) {
+        let _ = canvas
+            .window_mut()
+            .set_title(&format!("{} - {}", TITLE, message));
+        self.title_message_until = Some(Instant::now() + TITLE_MESSAGE_DURATION);
+    }
+
+    fn update_title(&mut self, canvas: &mut Canvas<Window>) {
+        if let Some(until) = self.title_message_until {
+            if Instant::now() >= until {
+                let _ = canvas.window_mut().set_title(TITLE);
+                self.title_message_until = None;
+            }
+        }
+    }
+
     fn update_gamepad(&mut self, keyboard_state: KeyboardState) {
-        let game_pad_1 = &mut self.nes.interconnect.input.game_pad_1;
+        if self.input_playback.is_some() {
+            return;
+        }
+
+        let mut game_pad = self.nes.interconnect.input.game_pad_1;
+        let game_pad_1 = &mut game_pad;
 
         game_pad_1.set_button_pressed(Button::A, keyboard_state.is_scancode_pressed(Scancode::X));
         game_pad_1.set_button_pressed(Button::B, keyboard_state.is_scancode_pressed(Scancode::Z));
@@ -381,6 +1025,18 @@ where
             Button::Right,
             keyboard_state.is_scancode_pressed(Scancode::Right),
         );
+
+        self.turbo.update(game_pad_1, |button| match button {
+            Button::A => keyboard_state.is_scancode_pressed(Scancode::S),
+            Button::B => keyboard_state.is_scancode_pressed(Scancode::A),
+            _ => false,
+        });
+
+        if self.input_recording.is_some() {
+            self.pending_buttons = Some(game_pad.buttons());
+        } else {
+            self.nes.interconnect.input.game_pad_1 = game_pad;
+        }
     }
 
     fn set_fullscreen(&mut self, canvas: &mut Canvas<Window>, fullscreen: bool) {
@@ -417,16 +1073,20 @@ where
     /// Render a frame of emulation.
     fn render_frame(&mut self, canvas: &mut Canvas<Window>, texture: &mut Texture) {
         let (canvas_width, canvas_height) = canvas.window().drawable_size();
+        let src_rect = self.crop.source_rect();
         let dest_rect = scale_to_canvas(
-            SCREEN_WIDTH as u32,
-            SCREEN_HEIGHT as u32,
+            src_rect.width(),
+            src_rect.height(),
+            self.pixel_aspect,
             canvas_width,
             canvas_height,
         );
 
         canvas.set_draw_color(Color::BLACK);
         canvas.clear();
-        canvas.copy(texture, None, Some(dest_rect)).unwrap();
+        canvas
+            .copy(texture, Some(src_rect), Some(dest_rect))
+            .unwrap();
         canvas.present();
     }
 
@@ -530,7 +1190,13 @@ where
             .unwrap();
 
         let (canvas_width, canvas_height) = debug_canvas.window().drawable_size();
-        let dest_rect = scale_to_canvas(DEBUG_WIDTH, DEBUG_HEIGHT, canvas_width, canvas_height);
+        let dest_rect = scale_to_canvas(
+            DEBUG_WIDTH,
+            DEBUG_HEIGHT,
+            PixelAspect::Square,
+            canvas_width,
+            canvas_height,
+        );
         debug_canvas.set_draw_color(Color::BLACK);
         debug_canvas.clear();
         debug_canvas.copy(&texture, None, Some(dest_rect)).unwrap();
@@ -543,31 +1209,253 @@ where
 
     fn cleanup(&mut self, canvas: &mut Canvas<Window>) {
         self.set_fullscreen(canvas, false);
+    }
+
+    /// Writes out everything kept until exit: save states, battery RAM, the code/data log, the
+    /// APU register log and the input recording. Runs when the emulator is dropped, so every way
+    /// of exiting is covered.
+    fn shutdown(&mut self) {
         self.state_manager.write_state_to_files();
+        self.write_battery_ram();
+
+        if let (Some(path), Some(coverage)) = (&self.coverage_path, &self.nes.interconnect.coverage)
+        {
+            let result = File::create(path).and_then(|mut file| coverage.write_cdl(&mut file));
+            match result {
+                Ok(()) => info!("Wrote code/data log to {}", path.display()),
+                Err(e) => error!("Unable to write code/data log to {}: {}", path.display(), e),
+            }
+        }
+
+        if let (Some(path), Some(log)) = (
+            &self.register_log_path,
+            self.nes.interconnect.apu.take_register_log(),
+        ) {
+            let result =
+                File::create(path).and_then(|mut file| write_register_log(&log, &mut file));
+            match result {
+                Ok(()) => info!("Wrote APU register log to {}", path.display()),
+                Err(e) => error!(
+                    "Unable to write APU register log to {}: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+
+        if let Some((ref path, ref log)) = self.input_recording {
+            let result = File::create(path).and_then(|mut file| log.write(&mut file));
+            match result {
+                Ok(()) => info!("Wrote input log to {}", path.display()),
+                Err(e) => error!("Unable to write input log to {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    fn load_battery_ram(&mut self) {
+        let Some(ref path) = self.battery_path else {
+            return;
+        };
+        let Ok(data) = fs::read(path) else {
+            return;
+        };
+        let mut mapper = self.nes.interconnect.mapper.borrow_mut();
+        if let Some(prg_ram) = mapper.prg_ram() {
+            let len = prg_ram.len().min(data.len());
+            prg_ram[..len].copy_from_slice(&data[..len]);
+            info!("Loaded battery RAM from {}", path.display());
+        }
+    }
+
+    fn write_battery_ram(&mut self) {
+        let Some(ref path) = self.battery_path else {
+            return;
+        };
+        let mut mapper = self.nes.interconnect.mapper.borrow_mut();
+        if let Some(prg_ram) = mapper.prg_ram() {
+            match fs::write(path, &*prg_ram) {
+                Ok(()) => info!("Wrote battery RAM to {}", path.display()),
+                Err(e) => error!("Unable to write battery RAM to {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+impl<A: AudioSink, T: TimeSource> Drop for Emulator<A, T> {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Whether the ROM was read from stdin, given as `-` on the command line. There's no file to keep
+/// its saves next to, so they aren't written.
+fn is_stdin(rom_path: &Path) -> bool {
+    rom_path == Path::new("-")
+}
+
+/// The `.sav` file next to the ROM that battery-backed PRG RAM is kept in, if the cartridge has
+/// a battery.
+fn battery_path(cartridge: &Cartridge, rom_path: &Path) -> Option<PathBuf> {
+    (cartridge.is_battery_backed && !is_stdin(rom_path)).then(|| rom_path.with_extension("sav"))
+}
+
+/// Save states are written next to the ROM when switching ROMs or exiting, or only kept in memory
+/// for a ROM read from stdin.
+fn state_manager(rom_path: PathBuf) -> StateManager {
+    if is_stdin(&rom_path) {
+        StateManager::in_memory(NUMBER_KEYCODES.len())
+    } else {
+        StateManager::new(rom_path, NUMBER_KEYCODES.len())
+    }
+}
+
+/// Real time in nanoseconds that each emulated CPU cycle should take in the region at the given
+/// speed.
+fn cycle_time_ns(region: Region, speed_percent: u32) -> u64 {
+    region.cpu_cycle_time_ns() * 100 / speed_percent as u64
+}
+
+/// Emulated cycles per second of real time, or 0 if no time has passed.
+fn cycles_per_second(cycles: u64, elapsed: Duration) -> u64 {
+    match elapsed.as_nanos() {
+        0 => 0,
+        elapsed_ns => (cycles as u128 * 1_000_000_000 / elapsed_ns) as u64,
+    }
+}
+
+/// Number of pixels cropped from each edge of the picture, parsed from "top,bottom,left,right".
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    pub top: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub right: u32,
+}
+
+impl Crop {
+    /// The part of the NES picture that is presented.
+    fn source_rect(&self) -> Rect {
+        Rect::new(
+            self.left as i32,
+            self.top as i32,
+            SCREEN_WIDTH as u32 - self.left - self.right,
+            SCREEN_HEIGHT as u32 - self.top - self.bottom,
+        )
+    }
+}
+
+/// The frame buffer hash expected once a number of frames have been rendered, given as
+/// `<hex hash>@<frame>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashAssertion {
+    pub hash: u64,
+    pub frame: u64,
+}
+
+impl FromStr for HashAssertion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hash, frame) = s
+            .split_once('@')
+            .ok_or_else(|| "expected <hex hash>@<frame>".to_string())?;
+        let hash = hash.trim_start_matches("0x");
+        let hash = u64::from_str_radix(hash, 16).map_err(|e| format!("invalid hash: {}", e))?;
+        let frame = frame
+            .parse()
+            .map_err(|e| format!("invalid frame number: {}", e))?;
+
+        Ok(HashAssertion { hash, frame })
+    }
+}
+
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid crop value: {}", e))?;
+        let [top, bottom, left, right] = values[..] else {
+            return Err("expected four values: top,bottom,left,right".to_string());
+        };
+
+        if top.saturating_add(bottom) >= SCREEN_HEIGHT as u32
+            || left.saturating_add(right) >= SCREEN_WIDTH as u32
+        {
+            return Err(format!(
+                "cropping {} leaves no picture from the {}x{} screen",
+                s, SCREEN_WIDTH, SCREEN_HEIGHT
+            ));
+        }
+
+        Ok(Crop {
+            top,
+            bottom,
+            left,
+            right,
+        })
+    }
+}
+
+/// Width to height ratio of each emulated pixel when presented.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PixelAspect {
+    #[default]
+    Square,
+    /// The 8:7 pixels of an NTSC television
+    Ntsc,
+}
+
+impl PixelAspect {
+    fn ratio(self) -> f32 {
+        match self {
+            PixelAspect::Square => 1.0,
+            PixelAspect::Ntsc => 8.0 / 7.0,
+        }
     }
 }
 
-fn scale_to_canvas(src_width: u32, src_height: u32, canvas_width: u32, canvas_height: u32) -> Rect {
-    let src_ratio = src_width as f32 / src_height as f32;
+/// The largest rectangle centered in the canvas with the aspect ratio of the source, leaving
+/// letterbox or pillarbox bars.
+fn scale_to_canvas(
+    src_width: u32,
+    src_height: u32,
+    pixel_aspect: PixelAspect,
+    canvas_width: u32,
+    canvas_height: u32,
+) -> Rect {
+    let src_width = src_width as f32 * pixel_aspect.ratio();
+    let src_ratio = src_width / src_height as f32;
     let dst_ratio = canvas_width as f32 / canvas_height as f32;
     if src_ratio <= dst_ratio {
-        let width = (src_width as f32 * canvas_height as f32 / src_height as f32) as u32;
+        let width = (src_width * canvas_height as f32 / src_height as f32) as u32;
         Rect::new((canvas_width - width) as i32 / 2, 0, width, canvas_height)
     } else {
-        let height = (src_height as f32 * canvas_width as f32 / src_width as f32) as u32;
+        let height = (src_height as f32 * canvas_width as f32 / src_width) as u32;
         Rect::new(0, (canvas_height - height) as i32 / 2, canvas_width, height)
     }
 }
 
 pub struct CanvasVideoSink<'a> {
     canvas: &'a mut Canvas<Window>,
+    blender: Option<&'a mut FrameBlender>,
+    quantizer: Option<Quantizer>,
     frame_written: bool,
 }
 
 impl<'a> CanvasVideoSink<'a> {
-    pub fn new(canvas: &'a mut Canvas<Window>) -> Self {
+    pub fn new(
+        canvas: &'a mut Canvas<Window>,
+        blender: Option<&'a mut FrameBlender>,
+        quantizer: Option<Quantizer>,
+    ) -> Self {
         CanvasVideoSink {
             canvas,
+            blender,
+            quantizer,
             frame_written: false,
         }
     }
@@ -575,12 +1463,21 @@ impl<'a> CanvasVideoSink<'a> {
 
 impl<'a> VideoSink for CanvasVideoSink<'a> {
     fn write_frame(&mut self, frame_buffer: &[u8]) {
+        let mut pixels: Vec<u32> = frame_buffer
+            .iter()
+            .map(|palette_index| XRGB8888_PALETTE[*palette_index as usize])
+            .collect();
+        if let Some(ref mut blender) = self.blender {
+            blender.blend(&mut pixels);
+        }
+        if let Some(quantizer) = self.quantizer {
+            quantizer.quantize(&mut pixels, SCREEN_WIDTH);
+        }
+
         let pixel_format = PixelFormatEnum::RGB888.try_into().unwrap();
-        for (i, palette_index) in frame_buffer.iter().enumerate() {
-            self.canvas.set_draw_color(Color::from_u32(
-                &pixel_format,
-                XRGB8888_PALETTE[*palette_index as usize],
-            ));
+        for (i, color) in pixels.iter().enumerate() {
+            self.canvas
+                .set_draw_color(Color::from_u32(&pixel_format, *color));
             self.canvas
                 .draw_point(((i % SCREEN_WIDTH) as i32, (i / SCREEN_WIDTH) as i32))
                 .unwrap();
@@ -628,11 +1525,507 @@ where
     }
 
     fn reset_start_time(&mut self) {
-        self.start_time_ns =
-            self.time_source.time_ns() - (self.emulated_cycles * CPU_CYCLE_TIME_NS);
+        Emulator::reset_start_time(self)
     }
 
     fn step(&mut self, video_frame_sink: &mut V) -> (u32, bool) {
         Emulator::step(self, video_frame_sink)
     }
+
+    fn next_rom(&mut self) {
+        self.switch_rom(true);
+    }
+
+    fn prev_rom(&mut self) {
+        self.switch_rom(false);
+    }
+}
+
+#[test]
+fn test_cycle_time_ns() {
+    let ntsc = Region::Ntsc.cpu_cycle_time_ns();
+    assert_eq!(cycle_time_ns(Region::Ntsc, 100), ntsc);
+    assert_eq!(cycle_time_ns(Region::Ntsc, 50), 2 * ntsc);
+    assert_eq!(cycle_time_ns(Region::Ntsc, 200), ntsc / 2);
+    assert!(cycle_time_ns(Region::Dendy, 100) > ntsc);
+    assert!(cycle_time_ns(Region::Dendy, 100) < cycle_time_ns(Region::Pal, 100));
+}
+
+#[test]
+fn test_cycles_per_second() {
+    assert_eq!(
+        cycles_per_second(1_789_773, Duration::from_secs(1)),
+        1_789_773
+    );
+    assert_eq!(cycles_per_second(1_000, Duration::from_millis(500)), 2_000);
+    assert_eq!(cycles_per_second(1_000, Duration::ZERO), 0);
+}
+
+#[test]
+fn test_crop() {
+    let crop: Crop = "8,8,4,4".parse().unwrap();
+    let src_rect = crop.source_rect();
+    assert_eq!((src_rect.width(), src_rect.height()), (248, 224));
+    assert_eq!((src_rect.x(), src_rect.y()), (4, 8));
+
+    assert_eq!(Crop::default().source_rect(), Rect::new(0, 0, 256, 240));
+    assert!("8,8,4".parse::<Crop>().is_err());
+    assert!("8,8,4,x".parse::<Crop>().is_err());
+    assert!("120,120,0,0".parse::<Crop>().is_err());
+    assert!("0,0,200,56".parse::<Crop>().is_err());
+}
+
+#[test]
+fn test_scale_to_canvas() {
+    // Square pixels fill the height of a wide window
+    assert_eq!(
+        scale_to_canvas(256, 240, PixelAspect::Square, 1280, 720),
+        Rect::new(256, 0, 768, 720)
+    );
+
+    // 8:7 pixels are wider, so the pillarbox bars are narrower
+    assert_eq!(
+        scale_to_canvas(256, 240, PixelAspect::Ntsc, 1280, 720),
+        Rect::new(201, 0, 877, 720)
+    );
+
+    // A tall window gets letterbox bars instead
+    assert_eq!(
+        scale_to_canvas(256, 240, PixelAspect::Ntsc, 800, 800),
+        Rect::new(0, 72, 800, 656)
+    );
+}
+
+#[cfg(test)]
+struct TestTimeSource(std::rc::Rc<std::cell::Cell<u64>>);
+
+#[cfg(test)]
+impl TimeSource for TestTimeSource {
+    fn time_ns(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+#[cfg(test)]
+struct TestAudioSink(std::rc::Rc<std::cell::Cell<usize>>);
+
+#[cfg(test)]
+impl AudioSink for TestAudioSink {
+    fn write_sample(&mut self, _sample: f32) {
+        self.0.set(self.0.get() + 1);
+    }
+
+    fn samples_written(&self) -> usize {
+        self.0.get()
+    }
+}
+
+// NROM-128 that executes NOPs forever, with `tag` at the start of PRG ROM to tell ROMs apart
+#[cfg(test)]
+fn test_cartridge(tag: u8) -> Cartridge {
+    let mut rom = rustednes_core::cartridge::nrom_test_rom(&[tag]);
+    // Start after the tag
+    rom[16 + 0x3FFA..16 + 0x4000].copy_from_slice(&[0x01, 0x80, 0x01, 0x80, 0x01, 0x80]);
+    Cartridge::from_bytes(&rom).unwrap()
+}
+
+// An emulator running `test_cartridge(0)`
+#[cfg(test)]
+fn test_emulator(
+    time: std::rc::Rc<std::cell::Cell<u64>>,
+    samples: std::rc::Rc<std::cell::Cell<usize>>,
+) -> Emulator<TestAudioSink, TestTimeSource> {
+    let mut emulator = Emulator::new(
+        sdl2::init().unwrap(),
+        test_cartridge(0),
+        TestAudioSink(samples),
+        TestTimeSource(time),
+        PathBuf::from("test.nes"),
+    );
+    emulator.reset_start_time();
+    emulator
+}
+
+#[test]
+fn test_run_headless_to_now() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let time = Rc::new(Cell::new(0));
+    let samples = Rc::new(Cell::new(0));
+    let mut emulator = test_emulator(time.clone(), samples.clone());
+
+    time.set(1_000_000_000 / 60);
+    emulator.run_headless_to_now(&mut NullVideoSink::new());
+
+    assert!(emulator.emulated_cycles >= rustednes_core::cpu::CPU_FREQUENCY / 61);
+    assert!(samples.get() > 0);
+    let video_initialized = unsafe { sdl2::sys::SDL_WasInit(sdl2::sys::SDL_INIT_VIDEO) };
+    assert_eq!(video_initialized, 0);
+}
+
+#[test]
+fn test_run_headless_quits() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let mut emulator = test_emulator(Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+    // What SDL pushes on SIGINT
+    let event_subsystem = emulator.sdl_context.event().unwrap();
+    event_subsystem
+        .push_event(Event::Quit { timestamp: 0 })
+        .unwrap();
+
+    emulator.run_headless();
+    assert_eq!(emulator.exit_code, None);
+}
+
+#[test]
+fn test_audio_clock_pacing() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // Stands in for the audio driver's time source, which only advances as samples are played
+    let audio_time = Rc::new(Cell::new(1_000_000));
+    let mut emulator = test_emulator(audio_time.clone(), Rc::new(Cell::new(0)));
+    assert_eq!(emulator.next_target_cycles(), 0);
+
+    // The device stalls, so emulation doesn't run ahead of it
+    assert_eq!(emulator.next_target_cycles(), 0);
+
+    audio_time.set(1_000_000 + 1_000_000_000 / 60);
+    let expected = (1_000_000_000 / 60) / cycle_time_ns(Region::Ntsc, 100);
+    assert_eq!(emulator.next_target_cycles(), expected);
+
+    emulator.set_speed_percent(200);
+    let start_cycles = emulator.start_cycles;
+    audio_time.set(audio_time.get() + 1_000_000_000 / 60);
+    assert_eq!(
+        emulator.next_target_cycles(),
+        start_cycles + (1_000_000_000 / 60) / cycle_time_ns(Region::Ntsc, 200)
+    );
+}
+
+#[test]
+fn test_fixed_step() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    const STEP_CYCLES: u64 = 1000;
+    const ITERATIONS: u64 = 25;
+
+    // Time doesn't advance, so only the fixed step drives emulation
+    let mut emulator = test_emulator(Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+    emulator.set_fixed_step(STEP_CYCLES);
+
+    let mut video_frame_sink = NullVideoSink::new();
+    for _ in 0..ITERATIONS {
+        emulator.run_headless_to_now(&mut video_frame_sink);
+    }
+
+    // NOPs take 2 cycles, so the even step is hit exactly
+    assert_eq!(emulator.emulated_cycles, ITERATIONS * STEP_CYCLES);
+
+    // Steps longer than the instruction budget aren't cut short
+    const LONG_STEP_CYCLES: u64 = 4 * 2 * DEFAULT_INSTRUCTION_BUDGET;
+    let mut emulator = test_emulator(Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+    emulator.set_fixed_step(LONG_STEP_CYCLES);
+    emulator.run_headless_to_now(&mut video_frame_sink);
+    // The program runs off the end of ROM into a BRK, so the step may be overshot by an odd
+    // instruction
+    assert!(emulator.emulated_cycles >= LONG_STEP_CYCLES);
+    assert!(emulator.emulated_cycles < LONG_STEP_CYCLES + 7);
+}
+
+#[test]
+fn test_instruction_budget() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    const BUDGET: u64 = 1000;
+
+    let time = Rc::new(Cell::new(0));
+    let mut emulator = test_emulator(time.clone(), Rc::new(Cell::new(0)));
+    emulator.set_instruction_budget(BUDGET);
+
+    // A ROM that spins in place, with the time source a minute ahead of emulation
+    for (address, value) in [0x4C, 0x00, 0x00].into_iter().enumerate() {
+        rustednes_core::memory::Memory::write_byte(
+            &mut emulator.nes.interconnect,
+            address as u16,
+            value,
+        );
+    }
+    emulator.nes.cpu.set_pc(0x0000);
+    time.set(60 * 1_000_000_000);
+
+    let mut video_frame_sink = NullVideoSink::new();
+    emulator.run_headless_to_now(&mut video_frame_sink);
+    assert_eq!(emulator.emulated_instructions, BUDGET);
+
+    // The backlog was dropped rather than carried into the next iteration
+    emulator.run_headless_to_now(&mut video_frame_sink);
+    assert_eq!(emulator.emulated_instructions, BUDGET);
+}
+
+#[test]
+fn test_switch_rom() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let mut emulator = test_emulator(Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+    let rom_list = RomList::new(vec!["0".into(), "1".into(), "2".into()]);
+    emulator.set_rom_list(
+        rom_list,
+        Box::new(|path| Ok(test_cartridge(path.to_str().unwrap().parse()?))),
+    );
+
+    let tag = |emulator: &mut Emulator<_, _>| emulator.nes.interconnect.read_byte(0x8000);
+    assert_eq!(tag(&mut emulator), 0);
+    emulator.switch_rom(true);
+    assert_eq!(tag(&mut emulator), 1);
+    emulator.switch_rom(false);
+    emulator.switch_rom(false);
+    assert_eq!(tag(&mut emulator), 2);
+    assert_eq!(emulator.rom_list.index(), 2);
+    emulator.switch_rom(true);
+    assert_eq!(tag(&mut emulator), 0);
+}
+
+#[test]
+fn test_switch_rom_failed() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let mut emulator = test_emulator(Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+    let rom_list = RomList::new(vec!["0".into(), "bad".into(), "2".into()]);
+    emulator.set_rom_list(
+        rom_list,
+        Box::new(|path| Ok(test_cartridge(path.to_str().unwrap().parse()?))),
+    );
+
+    let tag = |emulator: &mut Emulator<_, _>| emulator.nes.interconnect.read_byte(0x8000);
+    emulator.switch_rom(true);
+    assert_eq!(tag(&mut emulator), 0);
+    assert_eq!(emulator.rom_list.index(), 0);
+    emulator.switch_rom(false);
+    assert_eq!(tag(&mut emulator), 2);
+    emulator.switch_rom(false);
+    assert_eq!(tag(&mut emulator), 2);
+    assert_eq!(emulator.rom_list.index(), 2);
+}
+
+#[test]
+fn test_exit_on_trap() {
+    use rustednes_core::cpu::Trap;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // Opcode 0x02 jams the CPU
+    let mut emulator = test_emulator(Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+    emulator.nes = Nes::new(test_cartridge(0x02));
+    emulator.nes.cpu.set_pc(0x8000);
+    emulator.set_exit_on_trap(true);
+    emulator.set_fixed_step(1000);
+
+    // Returns instead of running forever
+    emulator.run_headless();
+
+    assert_eq!(emulator.exit_code(), Some(TRAP_EXIT_CODE));
+    assert_eq!(
+        emulator.nes.cpu.trap(),
+        Some(Trap::Jam {
+            pc: 0x8000,
+            opcode: 0x02
+        })
+    );
+}
+
+#[test]
+fn test_watchdog() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // The test ROM never turns rendering on
+    let mut emulator = test_emulator(Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+    emulator.set_exit_on_trap(true);
+    emulator.set_watchdog_frames(2);
+    emulator.set_fixed_step(1000);
+
+    // Returns instead of running forever
+    emulator.run_headless();
+
+    assert_eq!(emulator.exit_code(), Some(TRAP_EXIT_CODE));
+    assert_eq!(emulator.nes.interconnect.ppu.frame(), 2);
+    assert_eq!(emulator.nes.cpu.trap(), None);
+
+    // Rendering restarts the count
+    let mut emulator = test_emulator(Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+    emulator.set_exit_on_trap(true);
+    emulator.set_watchdog_frames(2);
+    emulator.nes.interconnect.ppu.warm_up = false;
+    emulator.nes.interconnect.ppu.write_byte(0x2001, 0x08);
+    let mut video_frame_sink = NullVideoSink::new();
+    for _ in 0..3 {
+        emulator.run_frame(&mut video_frame_sink);
+    }
+    assert_eq!(emulator.exit_code(), None);
+}
+
+#[test]
+fn test_hash_assertion() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    const FRAMES: u64 = 3;
+
+    assert_eq!(
+        "0123abcd@60".parse(),
+        Ok(HashAssertion {
+            hash: 0x0123_ABCD,
+            frame: 60
+        })
+    );
+    assert!("0123abcd".parse::<HashAssertion>().is_err());
+
+    // The test cartridge only runs NOPs, so every run renders the same frames
+    let mut nes = Nes::new(test_cartridge(0));
+    let mut video_sink = NullVideoSink::new();
+    let mut audio_sink = TestAudioSink(Rc::new(Cell::new(0)));
+    while nes.interconnect.ppu.frame() < FRAMES {
+        nes.step(&mut video_sink, &mut audio_sink);
+    }
+    let hash = nes.frame_hash();
+
+    let run = |hash| {
+        let mut emulator = test_emulator(Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        emulator.run_hash_assertion(HashAssertion {
+            hash,
+            frame: FRAMES,
+        });
+        emulator.exit_code()
+    };
+    assert_eq!(run(hash), None);
+    assert_eq!(run(hash ^ 1), Some(HASH_MISMATCH_EXIT_CODE));
+}
+
+#[test]
+fn test_frame_advance_input_log() {
+    use rustednes_core::input_log::InputLogEntry;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let log_path = std::env::temp_dir().join(format!(
+        "rustednes-frame-advance-test-{}.rnil",
+        std::process::id()
+    ));
+    let mut emulator = test_emulator(Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+    emulator.record_input(log_path.clone());
+    let mut video_frame_sink = NullVideoSink::new();
+    emulator.run_frame(&mut video_frame_sink);
+
+    // A key pressed while paused applies to the frame advanced to, and is recorded for it
+    emulator.pending_buttons = Some(0x01);
+    emulator.run_frame(&mut video_frame_sink);
+    assert_eq!(emulator.nes.interconnect.input.game_pad_1.buttons(), 0x01);
+    let (_, log) = emulator.input_recording.take().unwrap();
+    let expected = InputLogEntry {
+        frame: 1,
+        port_0: 0x01,
+        port_1: 0x00,
+    };
+    assert_eq!(log.entries(), &[expected]);
+
+    // Frame advancing through the recording plays it back
+    let mut emulator = test_emulator(Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+    emulator.play_input(log);
+    emulator.run_frame(&mut video_frame_sink);
+    assert_eq!(emulator.nes.interconnect.input.game_pad_1.buttons(), 0x00);
+    emulator.run_frame(&mut video_frame_sink);
+    assert_eq!(emulator.nes.interconnect.input.game_pad_1.buttons(), 0x01);
+    let _ = fs::remove_file(&log_path);
+}
+
+#[test]
+fn test_paused_frame_advance() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let mut emulator = test_emulator(Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+    let mut video_frame_sink = NullVideoSink::new();
+    // Start on a frame boundary
+    emulator.run_frame(&mut video_frame_sink);
+    emulator.mode = EmulationMode::Paused;
+    let frame = emulator.nes.interconnect.ppu.frame();
+    let cycles = emulator.emulated_cycles;
+
+    // A request while running is ignored
+    emulator.frame_advance = true;
+    emulator.mode = EmulationMode::Running;
+    assert!(!emulator.take_frame_advance());
+    emulator.mode = EmulationMode::Paused;
+
+    emulator.frame_advance = true;
+    for _ in 0..3 {
+        if emulator.take_frame_advance() {
+            emulator.run_frame(&mut video_frame_sink);
+        }
+    }
+    assert_eq!(emulator.nes.interconnect.ppu.frame(), frame + 1);
+    assert!(emulator.mode == EmulationMode::Paused);
+    // 341 PPU cycles per scanline, 3 to each CPU cycle
+    let frame_cycles = rustednes_core::ppu::SCANLINES_PER_FRAME as u64 * 341 / 3;
+    assert!((emulator.emulated_cycles - cycles).abs_diff(frame_cycles) <= 7);
+}
+
+#[test]
+fn test_battery_ram_written_on_exit() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // Unique per process, so concurrent test runs don't share the .sav file
+    let rom_path =
+        std::env::temp_dir().join(format!("rustednes-battery-test-{}.nes", std::process::id()));
+    let sav_path = rom_path.with_extension("sav");
+    let _ = fs::remove_file(&sav_path);
+
+    let mut cartridge = test_cartridge(0);
+    cartridge.is_battery_backed = true;
+    let mut emulator = Emulator::new(
+        sdl2::init().unwrap(),
+        cartridge,
+        TestAudioSink(Rc::new(Cell::new(0))),
+        TestTimeSource(Rc::new(Cell::new(0))),
+        rom_path,
+    );
+    emulator.nes.interconnect.write_byte(0x6000, 0x42);
+    emulator.nes.interconnect.write_byte(0x7FFF, 0x24);
+    emulator.exit_code = Some(TRAP_EXIT_CODE);
+    drop(emulator);
+
+    let saved = fs::read(&sav_path).unwrap();
+    let _ = fs::remove_file(&sav_path);
+    assert_eq!(saved.len(), 0x2000);
+    assert_eq!(saved[0], 0x42);
+    assert_eq!(saved[0x1FFF], 0x24);
+}
+
+#[test]
+fn test_stdin_rom_saves() {
+    let mut cartridge = test_cartridge(0);
+    cartridge.is_battery_backed = true;
+    assert_eq!(
+        battery_path(&cartridge, Path::new("roms/game.nes")),
+        Some(PathBuf::from("roms/game.sav"))
+    );
+    assert_eq!(battery_path(&cartridge, Path::new("-")), None);
+
+    // Save states still work within the session
+    let mut nes = Nes::new(test_cartridge(0));
+    let mut state_manager = state_manager(PathBuf::from("-"));
+    nes.interconnect.write_byte(0x0010, 0x11);
+    assert!(state_manager.save_state(&nes, 1));
+    nes.interconnect.write_byte(0x0010, 0x22);
+    assert!(state_manager.load_state(&mut nes, 1));
+    assert_eq!(nes.interconnect.read_byte(0x0010), 0x11);
 }
@@ -2,62 +2,434 @@
 static GLOBAL: System = System;
 
 use crate::emulator::*;
+use crate::rom_list::RomList;
 use crate::sdl_audio_driver::*;
 
 use rustednes_common::logger;
-use rustednes_core::apu::SAMPLE_RATE as NES_SAMPLE_RATE;
+use rustednes_core::apu::{self, CPU_CYCLES_PER_SAMPLE};
 use rustednes_core::cartridge::*;
+use rustednes_core::cpu::{IllegalMode, Region};
+use rustednes_core::input::{Button, TurboMode};
+use rustednes_core::input_log::InputLog;
+use rustednes_core::mapper::MAPPERS;
+use rustednes_core::nes::Accuracy;
+use rustednes_core::ram_system::RamSystem;
+use rustednes_core::sink::ColorDepth;
 
 use rustednes_common::audio::*;
+use rustednes_common::debugger::Debugger;
 use rustednes_common::time::*;
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use clap_verbosity_flag::{InfoLevel, Verbosity};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use std::alloc::System;
 use std::error::Error;
 use std::fs::File;
+use std::io::{self, Read};
+use std::num::NonZeroU64;
 use std::path::{Path, PathBuf};
+use std::process;
 
 mod emulator;
+mod rom_list;
 mod sdl_audio_driver;
 
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 struct Opt {
-    /// The name of the ROM to load
-    #[arg(name = "ROM")]
-    rom_path: PathBuf,
+    /// The names of the ROMs to load, or - to read one from stdin, whose saves aren't written to
+    /// disk. Page Up and Page Down switch between them.
+    #[arg(name = "ROM", required_unless_present_any = ["flat", "list_mappers"])]
+    rom_paths: Vec<PathBuf>,
 
     /// Start in debug mode
     #[arg(short, long)]
     debug: bool,
 
+    /// Run without a window, with audio only
+    #[arg(long)]
+    no_window: bool,
+
     /// Disable audio
     #[arg(long = "noaudio")]
     disable_audio: bool,
 
+    /// Most samples to buffer ahead of the audio device, which bounds audio latency
+    #[arg(long, value_name = "SAMPLES", default_value_t = 32 * 1024)]
+    audio_buffer: usize,
+
+    /// What to do when the audio buffer is full: drop the oldest samples or wait for the device
+    #[arg(long, value_enum, default_value_t = AudioOverflow::DropOldest)]
+    audio_overflow: AudioOverflow,
+
+    /// CPU cycles between the samples the APU generates, before they're resampled to the audio
+    /// device's rate
+    #[arg(
+        long,
+        value_name = "CYCLES",
+        default_value_t = NonZeroU64::new(CPU_CYCLES_PER_SAMPLE).unwrap()
+    )]
+    audio_divider: NonZeroU64,
+
+    /// Emulation speed as a percentage of normal speed
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(10..=1000))]
+    speed: u32,
+
+    /// Console region, which sets the CPU and PPU clocks and the frame length: dendy is the timing
+    /// of Famicom clones with PAL's scanline count
+    #[arg(long, value_enum, default_value_t = ConsoleRegion::Ntsc)]
+    region: ConsoleRegion,
+
+    /// Emulate a fixed number of CPU cycles per loop iteration instead of pacing by real time, for
+    /// profiling
+    #[arg(long, value_name = "CYCLES", value_parser = clap::value_parser!(u64).range(1..))]
+    fixed_step: Option<u64>,
+
+    /// Maximum instructions to emulate between presents, so a runaway ROM can't lock up the
+    /// window
+    #[arg(long, value_name = "INSTRUCTIONS", value_parser = clap::value_parser!(u64).range(1..))]
+    instruction_budget: Option<u64>,
+
+    /// Emulate bus conflicts on discrete-logic mappers (auto follows the NES 2.0 header's
+    /// submapper, then the built-in game list)
+    #[arg(long, value_enum, default_value_t = BusConflicts::Auto)]
+    bus_conflicts: BusConflicts,
+
+    /// Whether the turbo A key fires while held or toggles turbo on and off
+    #[arg(long, value_enum, default_value_t = TurboSetting::Hold)]
+    turbo_a: TurboSetting,
+
+    /// Whether the turbo B key fires while held or toggles turbo on and off
+    #[arg(long, value_enum, default_value_t = TurboSetting::Hold)]
+    turbo_b: TurboSetting,
+
+    /// Pixels to crop from the top, bottom, left and right edges of the picture
+    #[arg(long, value_name = "TOP,BOTTOM,LEFT,RIGHT", default_value = "0,0,0,0")]
+    crop: Crop,
+
+    /// Shape of each pixel, where ntsc is 8:7 as on a television
+    #[arg(long, value_enum, default_value_t = Aspect::Square)]
+    aspect: Aspect,
+
+    /// Average each frame with the previous one to soften sprite flicker
+    #[arg(long)]
+    blend: bool,
+
+    /// Reduce the picture to black and white (1bit), or 4 (2bit) or 16 (gray4) shades of gray
+    #[arg(long, value_enum)]
+    depth: Option<Depth>,
+
+    /// Dither the reduced color depth picture to approximate the shades in between
+    #[arg(long, requires = "depth")]
+    dither: bool,
+
+    /// Record which PRG ROM bytes are executed, and write them to a code/data log file on exit
+    #[arg(long, value_name = "FILE")]
+    cdl: Option<PathBuf>,
+
+    /// Record every APU register write with its CPU cycle, and write them to a file on exit
+    #[arg(long, value_name = "FILE")]
+    apu_log: Option<PathBuf>,
+
+    /// Record controller input from power-on, and write it to an input log file on exit
+    #[arg(long, value_name = "FILE", conflicts_with = "play_input")]
+    record_input: Option<PathBuf>,
+
+    /// Drive the controllers from an input log file instead of the keyboard
+    #[arg(long, value_name = "FILE")]
+    play_input: Option<PathBuf>,
+
+    /// Log writes by code running from RAM or PRG RAM into the bank it's running from
+    #[arg(long)]
+    log_self_modifying: bool,
+
+    /// Warn when ADC or SBC runs with the decimal flag set, which the NES ignores
+    #[arg(long)]
+    warn_decimal: bool,
+
+    /// Trade speed for accuracy: fast skips dummy reads, the PPU warm-up and controller open bus,
+    /// balanced emulates them, and cycle also emulates DMC DMA conflicts with controller reads
+    #[arg(long, value_enum, default_value_t = AccuracyProfile::Balanced)]
+    accuracy: AccuracyProfile,
+
+    /// Whether unofficial opcodes run, or trap like unimplemented ones
+    #[arg(long, value_enum, default_value_t = Illegal::Execute)]
+    illegal: Illegal,
+
+    /// Exit with status 3 when the CPU traps on an illegal opcode or jams, instead of starting the debugger
+    #[arg(long)]
+    exit_on_trap: bool,
+
+    /// With --exit-on-trap, also exit with status 3 when rendering stays off for this many frames,
+    /// as it does in most hung games
+    #[arg(long, value_name = "FRAMES", requires = "exit_on_trap")]
+    watchdog: Option<u64>,
+
+    /// Run without a window to the given frame, and exit with status 4 unless the frame's hash
+    /// matches
+    #[arg(long, value_name = "HASH@FRAME")]
+    assert_hash: Option<HashAssertion>,
+
+    /// Run a raw 6502 binary in 64KB of RAM instead of a cartridge, until the CPU jams, or
+    /// under the debugger with --debug
+    #[arg(long, value_name = "FILE", requires = "start")]
+    flat: Option<PathBuf>,
+
+    /// Address to load the --flat binary at, in hex, defaulting to the start address
+    #[arg(long, value_name = "ADDR", requires = "flat", value_parser = parse_address)]
+    load_address: Option<u16>,
+
+    /// Address to start running the --flat binary at, in hex
+    #[arg(long, alias = "start-address", value_name = "ADDR", requires = "flat", value_parser = parse_address)]
+    start: Option<u16>,
+
+    /// Print the supported mappers and exit
+    #[arg(long)]
+    list_mappers: bool,
+
+    /// Console output level, overriding -v and -q
+    #[arg(long, value_enum)]
+    log_level: Option<LogLevel>,
+
     #[clap(flatten)]
     verbose: Verbosity<InfoLevel>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogLevel {
+    Quiet,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    fn filter(self) -> Option<&'static str> {
+        match self {
+            LogLevel::Quiet => None,
+            LogLevel::Info => Some("info"),
+            LogLevel::Debug => Some("debug"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BusConflicts {
+    Auto,
+    On,
+    Off,
+}
+
+impl BusConflicts {
+    // Overrides what the cartridge detected, unless this is auto
+    fn apply(self, rom: &mut Cartridge) {
+        match self {
+            BusConflicts::Auto => {}
+            BusConflicts::On => rom.bus_conflicts = true,
+            BusConflicts::Off => rom.bus_conflicts = false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ConsoleRegion {
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl ConsoleRegion {
+    fn region(self) -> Region {
+        match self {
+            ConsoleRegion::Ntsc => Region::Ntsc,
+            ConsoleRegion::Pal => Region::Pal,
+            ConsoleRegion::Dendy => Region::Dendy,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum AccuracyProfile {
+    Fast,
+    Balanced,
+    Cycle,
+}
+
+impl AccuracyProfile {
+    fn accuracy(self) -> Accuracy {
+        match self {
+            AccuracyProfile::Fast => Accuracy::Fast,
+            AccuracyProfile::Balanced => Accuracy::Balanced,
+            AccuracyProfile::Cycle => Accuracy::Cycle,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Illegal {
+    Execute,
+    Trap,
+}
+
+impl Illegal {
+    fn mode(self) -> IllegalMode {
+        match self {
+            Illegal::Execute => IllegalMode::Execute,
+            Illegal::Trap => IllegalMode::Trap,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum AudioOverflow {
+    DropOldest,
+    Block,
+}
+
+impl AudioOverflow {
+    fn policy(self) -> OverflowPolicy {
+        match self {
+            AudioOverflow::DropOldest => OverflowPolicy::DropOldest,
+            AudioOverflow::Block => OverflowPolicy::Block,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Aspect {
+    Square,
+    Ntsc,
+}
+
+impl Aspect {
+    fn pixel_aspect(self) -> PixelAspect {
+        match self {
+            Aspect::Square => PixelAspect::Square,
+            Aspect::Ntsc => PixelAspect::Ntsc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Depth {
+    #[value(name = "1bit")]
+    OneBit,
+    #[value(name = "2bit")]
+    TwoBit,
+    Gray4,
+}
+
+impl Depth {
+    fn color_depth(self) -> ColorDepth {
+        match self {
+            Depth::OneBit => ColorDepth::OneBit,
+            Depth::TwoBit => ColorDepth::TwoBit,
+            Depth::Gray4 => ColorDepth::Gray4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum TurboSetting {
+    Hold,
+    Toggle,
+}
+
+impl TurboSetting {
+    fn mode(self) -> TurboMode {
+        match self {
+            TurboSetting::Hold => TurboMode::Hold,
+            TurboSetting::Toggle => TurboMode::Toggle,
+        }
+    }
+}
+
 fn main() {
     let opt: Opt = clap::Parser::parse();
 
-    logger::initialize(&opt.verbose);
+    match opt.log_level {
+        Some(log_level) => logger::initialize_with_filter(log_level.filter()),
+        None => logger::initialize(&opt.verbose),
+    }
 
-    match load_rom(&opt.rom_path) {
-        Ok(rom) => {
-            info!("{:?}", rom);
-            let rom_path = opt.rom_path.to_path_buf();
-            run_rom(rom, opt, rom_path);
+    if opt.list_mappers {
+        for info in MAPPERS {
+            println!("{}", info);
         }
+        return;
+    }
+
+    if let (Some(path), Some(start)) = (&opt.flat, opt.start) {
+        run_flat(path, opt.load_address.unwrap_or(start), start, opt.debug);
+        return;
+    }
+
+    match load_cartridge(&opt.rom_paths[0], opt.bus_conflicts) {
+        Ok(rom) => run_rom(rom, opt),
         Err(e) => error!("Error: {}", e),
     }
 }
 
+// Parses a hex address, with an optional $ or 0x prefix
+fn parse_address(s: &str) -> Result<u16, String> {
+    let digits = s.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|e| format!("invalid address: {}", e))
+}
+
+fn run_flat(path: &Path, load_address: u16, start: u16, debug: bool) {
+    let binary = match std::fs::read(path) {
+        Ok(binary) => binary,
+        Err(e) => {
+            error!("Unable to read {}: {}", path.display(), e);
+            return;
+        }
+    };
+    info!(
+        "Running {} bytes loaded at 0x{:04x} from 0x{:04x}",
+        binary.len(),
+        load_address,
+        start
+    );
+
+    let mut system = RamSystem::from_flat(&binary, load_address, start);
+    if debug {
+        Debugger::new().run_ram_system(&mut system);
+        return;
+    }
+    if let Some(trap) = system.run(u64::MAX) {
+        let regs = system.cpu.regs();
+        info!(
+            "CPU stopped, {}: a=0x{:02x} x=0x{:02x} y=0x{:02x} sp=0x{:02x}",
+            trap, regs.a, regs.x, regs.y, regs.sp
+        );
+    }
+}
+
+fn load_cartridge(
+    filename: &Path,
+    bus_conflicts: BusConflicts,
+) -> Result<Cartridge, Box<dyn Error>> {
+    let mut rom = load_rom(filename)?;
+    bus_conflicts.apply(&mut rom);
+    info!("{:?}", rom);
+    if let Some(expansion_audio) = rom.expansion_audio() {
+        warn!(
+            "This game uses {} expansion audio, which isn't emulated yet",
+            expansion_audio
+        );
+    }
+    Ok(rom)
+}
+
 fn load_rom(filename: &Path) -> Result<Cartridge, Box<dyn Error>> {
+    if filename == Path::new("-") {
+        // Stdin isn't seekable, so buffer the whole ROM first
+        let mut bytes = Vec::new();
+        io::stdin().lock().read_to_end(&mut bytes)?;
+        return Ok(Cartridge::from_bytes(&bytes)?);
+    }
+
     let file = File::open(filename)?;
 
     let cartridge = match filename.extension() {
@@ -76,8 +448,16 @@ fn load_rom(filename: &Path) -> Result<Cartridge, Box<dyn Error>> {
     Ok(cartridge)
 }
 
-fn run_rom(rom: Cartridge, opt: Opt, rom_path: PathBuf) {
+fn run_rom(rom: Cartridge, opt: Opt) {
     let sdl_context = sdl2::init().unwrap();
+    let rom_path = opt.rom_paths[0].clone();
+    let rom_list = || -> (RomList, RomLoader) {
+        let bus_conflicts = opt.bus_conflicts;
+        (
+            RomList::new(opt.rom_paths.clone()),
+            Box::new(move |path| load_cartridge(path, bus_conflicts)),
+        )
+    };
 
     if opt.disable_audio {
         let audio_driver = NullAudioDriver {};
@@ -85,14 +465,176 @@ fn run_rom(rom: Cartridge, opt: Opt, rom_path: PathBuf) {
         info!("Audio disabled");
         let mut emulator =
             Emulator::new(sdl_context, rom, audio_driver.sink(), time_source, rom_path);
-        emulator.run(opt.debug);
+        emulator.set_speed_percent(opt.speed);
+        emulator.set_region(opt.region.region());
+        emulator.set_internal_rate(opt.audio_divider);
+        emulator.set_crop(opt.crop);
+        emulator.set_pixel_aspect(opt.aspect.pixel_aspect());
+        emulator.set_turbo_mode(Button::A, opt.turbo_a.mode());
+        emulator.set_turbo_mode(Button::B, opt.turbo_b.mode());
+        let (roms, rom_loader) = rom_list();
+        emulator.set_rom_list(roms, rom_loader);
+        if let Some(cycles) = opt.fixed_step {
+            emulator.set_fixed_step(cycles);
+        }
+        if let Some(instructions) = opt.instruction_budget {
+            emulator.set_instruction_budget(instructions);
+        }
+        emulator.set_blend(opt.blend);
+        emulator.set_color_depth(opt.depth.map(Depth::color_depth), opt.dither);
+        if let Some(ref path) = opt.cdl {
+            emulator.enable_coverage(path.clone());
+        }
+        if let Some(ref path) = opt.apu_log {
+            emulator.enable_register_log(path.clone());
+        }
+        if let Some(ref path) = opt.record_input {
+            emulator.record_input(path.clone());
+        }
+        if let Some(ref path) = opt.play_input {
+            match File::open(path).and_then(|mut file| InputLog::read(&mut file)) {
+                Ok(log) => emulator.play_input(log),
+                Err(e) => {
+                    error!("Unable to read input log {}: {}", path.display(), e);
+                    return;
+                }
+            }
+        }
+        if opt.log_self_modifying {
+            emulator.enable_self_modification_log();
+        }
+        if opt.warn_decimal {
+            emulator.enable_decimal_warnings();
+        }
+        emulator.set_accuracy(opt.accuracy.accuracy());
+        emulator.set_illegal_mode(opt.illegal.mode());
+        emulator.set_exit_on_trap(opt.exit_on_trap);
+        if let Some(frames) = opt.watchdog {
+            emulator.set_watchdog_frames(frames);
+        }
+        if let Some(assertion) = opt.assert_hash {
+            emulator.run_hash_assertion(assertion);
+        } else if opt.no_window {
+            emulator.run_headless();
+        } else {
+            emulator.run(opt.debug);
+        }
+        // Exiting the process skips destructors, so drop the emulator first to write out saves
+        let exit_code = emulator.exit_code();
+        drop(emulator);
+        if let Some(exit_code) = exit_code {
+            process::exit(exit_code);
+        }
     } else {
-        let audio_driver =
-            Box::new(SdlAudioDriver::new(sdl_context.clone(), NES_SAMPLE_RATE).unwrap());
+        let mut audio_driver = Box::new(
+            SdlAudioDriver::new(
+                sdl_context.clone(),
+                apu::sample_rate(opt.region.region(), opt.audio_divider),
+                opt.audio_buffer,
+                opt.audio_overflow.policy(),
+            )
+            .unwrap(),
+        );
+        // Pace emulation by the samples the device has played rather than the wall clock
         let time_source = audio_driver.time_source();
         info!("Audio sample rate: {}", audio_driver.sample_rate());
+        info!(
+            "Nominal CPU clock: {} cycles per second",
+            opt.region.region().cpu_frequency()
+        );
         let mut emulator =
             Emulator::new(sdl_context, rom, audio_driver.sink(), time_source, rom_path);
-        emulator.run(opt.debug);
+        emulator.set_speed_percent(opt.speed);
+        emulator.set_region(opt.region.region());
+        emulator.set_internal_rate(opt.audio_divider);
+        emulator.set_crop(opt.crop);
+        emulator.set_pixel_aspect(opt.aspect.pixel_aspect());
+        emulator.set_turbo_mode(Button::A, opt.turbo_a.mode());
+        emulator.set_turbo_mode(Button::B, opt.turbo_b.mode());
+        let (roms, rom_loader) = rom_list();
+        emulator.set_rom_list(roms, rom_loader);
+        let mut device_index = None;
+        emulator.set_audio_device_switcher(Box::new(move || {
+            let names = audio_driver.device_names();
+            if names.is_empty() {
+                return Err("no audio output devices".into());
+            }
+            let index = device_index.map_or(0, |index| (index + 1) % names.len());
+            audio_driver.switch_device(Some(&names[index]))?;
+            device_index = Some(index);
+            Ok(names[index].clone())
+        }));
+        if let Some(cycles) = opt.fixed_step {
+            emulator.set_fixed_step(cycles);
+        }
+        if let Some(instructions) = opt.instruction_budget {
+            emulator.set_instruction_budget(instructions);
+        }
+        emulator.set_blend(opt.blend);
+        emulator.set_color_depth(opt.depth.map(Depth::color_depth), opt.dither);
+        if let Some(ref path) = opt.cdl {
+            emulator.enable_coverage(path.clone());
+        }
+        if let Some(ref path) = opt.apu_log {
+            emulator.enable_register_log(path.clone());
+        }
+        if let Some(ref path) = opt.record_input {
+            emulator.record_input(path.clone());
+        }
+        if let Some(ref path) = opt.play_input {
+            match File::open(path).and_then(|mut file| InputLog::read(&mut file)) {
+                Ok(log) => emulator.play_input(log),
+                Err(e) => {
+                    error!("Unable to read input log {}: {}", path.display(), e);
+                    return;
+                }
+            }
+        }
+        if opt.log_self_modifying {
+            emulator.enable_self_modification_log();
+        }
+        if opt.warn_decimal {
+            emulator.enable_decimal_warnings();
+        }
+        emulator.set_accuracy(opt.accuracy.accuracy());
+        emulator.set_illegal_mode(opt.illegal.mode());
+        emulator.set_exit_on_trap(opt.exit_on_trap);
+        if let Some(frames) = opt.watchdog {
+            emulator.set_watchdog_frames(frames);
+        }
+        if let Some(assertion) = opt.assert_hash {
+            emulator.run_hash_assertion(assertion);
+        } else if opt.no_window {
+            emulator.run_headless();
+        } else {
+            emulator.run(opt.debug);
+        }
+        // Exiting the process skips destructors, so drop the emulator first to write out saves
+        let exit_code = emulator.exit_code();
+        drop(emulator);
+        if let Some(exit_code) = exit_code {
+            process::exit(exit_code);
+        }
+    };
+}
+
+#[test]
+fn test_bus_conflicts_flag() {
+    use rustednes_core::cartridge::ines_test_rom;
+
+    // CNROM with an iNES 1.0 header, and as NES 2.0 submapper 2, which has bus conflicts
+    let ines = ines_test_rom(3, 1, 1);
+    let mut nes2 = ines.clone();
+    nes2[7] |= 0x08;
+    nes2[8] = 0x20;
+
+    let bus_conflicts = |rom: &[u8], flag: BusConflicts| {
+        let mut cartridge = Cartridge::from_bytes(rom).unwrap();
+        flag.apply(&mut cartridge);
+        cartridge.bus_conflicts
     };
+    assert!(!bus_conflicts(&ines, BusConflicts::Auto));
+    assert!(bus_conflicts(&nes2, BusConflicts::Auto));
+    assert!(bus_conflicts(&ines, BusConflicts::On));
+    assert!(!bus_conflicts(&nes2, BusConflicts::Off));
 }
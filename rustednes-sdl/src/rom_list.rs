@@ -0,0 +1,58 @@
+use std::path::{Path, PathBuf};
+
+/// The ROMs given on the command line, which can be cycled through while running.
+pub struct RomList {
+    paths: Vec<PathBuf>,
+    index: usize,
+}
+
+impl RomList {
+    pub fn new(paths: Vec<PathBuf>) -> RomList {
+        assert!(!paths.is_empty());
+
+        RomList { paths, index: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn current(&self) -> &Path {
+        &self.paths[self.index]
+    }
+
+    /// Moves to the next ROM, wrapping around to the first, and returns its path.
+    pub fn next(&mut self) -> &Path {
+        self.index = (self.index + 1) % self.paths.len();
+        self.current()
+    }
+
+    /// Moves to the previous ROM, wrapping around to the last, and returns its path.
+    pub fn prev(&mut self) -> &Path {
+        self.index = (self.index + self.paths.len() - 1) % self.paths.len();
+        self.current()
+    }
+}
+
+#[test]
+fn test_rom_list() {
+    let mut rom_list = RomList::new(vec!["a.nes".into(), "b.nes".into(), "c.nes".into()]);
+    assert_eq!(rom_list.len(), 3);
+    assert_eq!(rom_list.current(), Path::new("a.nes"));
+
+    assert_eq!(rom_list.prev(), Path::new("c.nes"));
+    assert_eq!(rom_list.index(), 2);
+    assert_eq!(rom_list.next(), Path::new("a.nes"));
+    assert_eq!(rom_list.next(), Path::new("b.nes"));
+    assert_eq!(rom_list.next(), Path::new("c.nes"));
+    assert_eq!(rom_list.next(), Path::new("a.nes"));
+    assert_eq!(rom_list.index(), 0);
+
+    let mut single = RomList::new(vec!["a.nes".into()]);
+    assert_eq!(single.next(), Path::new("a.nes"));
+    assert_eq!(single.prev(), Path::new("a.nes"));
+}
@@ -1,4 +1,7 @@
-use rustednes_common::audio::{AudioDriver, LinearResampler, SampleBuffer};
+use rustednes_common::audio::{
+    AudioDriver, DeviceOutput, DeviceProvider, LinearResampler, OutputDevice, OverflowPolicy,
+    SampleBuffer,
+};
 use rustednes_common::time::TimeSource;
 
 use rustednes_core::sink::AudioSink;
@@ -6,9 +9,11 @@ use rustednes_core::sink::AudioSink;
 use std::error;
 use std::sync::atomic::{self, AtomicU64};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
-use sdl2::Sdl;
+use sdl2::{AudioSubsystem, Sdl};
 
 pub struct SdlBufferSink {
     sample_buffer: Arc<Mutex<SampleBuffer>>,
@@ -17,6 +22,12 @@ pub struct SdlBufferSink {
 impl AudioSink for SdlBufferSink {
     fn write_sample(&mut self, sample: f32) {
         let mut sample_buffer = self.sample_buffer.lock().unwrap();
+        while sample_buffer.overflow_policy() == OverflowPolicy::Block && sample_buffer.is_full() {
+            // Let the audio callback drain the buffer
+            drop(sample_buffer);
+            thread::sleep(Duration::from_millis(1));
+            sample_buffer = self.sample_buffer.lock().unwrap();
+        }
         sample_buffer.push(sample);
     }
 
@@ -39,49 +50,51 @@ impl TimeSource for SdlTimeSource {
 }
 
 pub struct SdlAudioDriver {
-    device: AudioDevice<SampleCallback>,
-    sample_buffer: Arc<Mutex<SampleBuffer>>,
-    samples_written: Arc<AtomicU64>,
+    device: OutputDevice<SdlDeviceProvider>,
 }
 
 impl SdlAudioDriver {
+    /// Opens the default playback device, buffering at most `max_buffered_samples` samples
+    /// between the emulator and the device
     pub fn new(
         sdl_context: Sdl,
         input_sample_rate: u32,
+        max_buffered_samples: usize,
+        overflow_policy: OverflowPolicy,
     ) -> Result<SdlAudioDriver, Box<dyn error::Error>> {
-        let sample_buffer = Arc::new(Mutex::new(SampleBuffer::with_max_length(32 * 1024)));
-        let samples_written = Arc::new(AtomicU64::new(0));
-
-        let audio_subsystem = sdl_context.audio()?;
-
-        let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
-            channels: Some(1), // mono
-            samples: None,     // default sample size
+        let mut sample_buffer = SampleBuffer::with_max_length(max_buffered_samples);
+        sample_buffer.set_overflow_policy(overflow_policy);
+        let output = DeviceOutput {
+            sample_buffer: Arc::new(Mutex::new(sample_buffer)),
+            samples_written: Arc::new(AtomicU64::new(0)),
         };
 
-        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| SampleCallback {
-            sample_buffer: sample_buffer.clone(),
-            samples_written: samples_written.clone(),
-            resampler: LinearResampler::new(input_sample_rate, spec.freq as u32),
-        })?;
-
-        // Start playback
-        device.resume();
+        let provider = SdlDeviceProvider {
+            audio_subsystem: sdl_context.audio()?,
+            input_sample_rate,
+        };
+        let device = OutputDevice::open(provider, None, output)?;
 
-        Ok(SdlAudioDriver {
-            device,
-            sample_buffer,
-            samples_written,
-        })
+        Ok(SdlAudioDriver { device })
     }
 
     pub fn time_source(&self) -> SdlTimeSource {
         SdlTimeSource {
-            samples_written: self.samples_written.clone(),
-            sample_rate: self.device.spec().freq as u32,
+            samples_written: self.device.output().samples_written.clone(),
+            sample_rate: self.sample_rate(),
         }
     }
+
+    /// Names of the playback devices that can be switched to
+    pub fn device_names(&self) -> Vec<String> {
+        self.device.device_names()
+    }
+
+    /// Moves playback to the named device, or the default one, keeping the buffered samples and
+    /// the time source's count of played samples
+    pub fn switch_device(&mut self, name: Option<&str>) -> Result<(), String> {
+        self.device.switch(name)
+    }
 }
 
 impl AudioDriver for SdlAudioDriver {
@@ -89,12 +102,54 @@ impl AudioDriver for SdlAudioDriver {
 
     fn sink(&self) -> Self::Sink {
         Self::Sink {
-            sample_buffer: self.sample_buffer.clone(),
+            sample_buffer: self.device.output().sample_buffer.clone(),
         }
     }
 
     fn sample_rate(&self) -> u32 {
-        self.device.spec().freq as u32
+        self.device.device().spec().freq as u32
+    }
+}
+
+struct SdlDeviceProvider {
+    audio_subsystem: AudioSubsystem,
+    input_sample_rate: u32,
+}
+
+impl DeviceProvider for SdlDeviceProvider {
+    type Device = AudioDevice<SampleCallback>;
+
+    fn device_names(&self) -> Vec<String> {
+        let count = self
+            .audio_subsystem
+            .num_audio_playback_devices()
+            .unwrap_or(0);
+        (0..count)
+            .filter_map(|index| self.audio_subsystem.audio_playback_device_name(index).ok())
+            .collect()
+    }
+
+    fn open(&self, name: Option<&str>, output: DeviceOutput) -> Result<Self::Device, String> {
+        // SDL converts from the desired format when the device differs, so the sample rate and
+        // with it the time source's clock stay the same across devices
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1), // mono
+            samples: None,     // default sample size
+        };
+
+        let device = self
+            .audio_subsystem
+            .open_playback(name, &desired_spec, |spec| SampleCallback {
+                sample_buffer: output.sample_buffer,
+                samples_written: output.samples_written,
+                resampler: LinearResampler::new(self.input_sample_rate, spec.freq as u32),
+            })?;
+
+        // Start playback
+        device.resume();
+
+        Ok(device)
     }
 }
 